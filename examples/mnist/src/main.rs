@@ -45,47 +45,51 @@ fn main() {
     let mut b =
         mat(DMatrix::<f32>::new_random(10, 1).map(|n| n - 0.5) / (28. * 28. / 2.)).symbol("b");
 
-    for n in 0..ntrain {
-        let x = mat(DMatrix::<f32>::from_iterator(
-            s,
-            1,
-            trn_img[n * s..n * s + s].iter().map(|x| *x as f32 / 255.),
-        ))
-        .symbol("x");
+    // One image per column.
+    let images = mat(DMatrix::<f32>::from_iterator(
+        s,
+        ntrain,
+        trn_img.iter().map(|x| *x as f32 / 255.),
+    ));
+    let tests = mat(DMatrix::<f32>::from_iterator(
+        s,
+        ntest,
+        tst_img.iter().map(|x| *x as f32 / 255.),
+    ));
+
+    for (n, &label) in trn_lbl.iter().enumerate() {
+        let x = Slice(&images, 0..s, n..n + 1);
 
         let y = &w * &x + &b;
         let y = y.exp() / Sum(y.exp());
 
-        let out = y.eval().0.unwrap();
+        let out = y.eval().into_dense().unwrap();
 
         let target = DMatrix::<f32>::from_iterator(
             10,
             1,
-            (0..10).map(|i| if i == trn_lbl[n] as usize { 1. } else { 0. }),
+            (0..10).map(|i| if i == label as usize { 1. } else { 0. }),
         );
 
         // let dy = (y - mat(target)) * 2f32; // Compiletime er over en time, hvis dette også skal være autodiff
 
         let dy = mat(2. * (out.clone() - target));
 
-        let [dw, db] = y.derivative(["w", "b"], dy);
-        let dw = dw.eval().0.unwrap();
-        let db = db.eval().0.unwrap();
+        // Evaluated in a block, so the borrows of `w` and `b` end before they are updated.
+        let (dw, db) = {
+            let [dw, db] = y.derivative(["w", "b"], dy);
+            (dw.eval(), db.eval())
+        };
 
-        *w.0.node.0.as_mut().unwrap() -= dw * 0.001;
-        *b.0.node.0.as_mut().unwrap() -= db * 0.001;
+        w.0.node -= dw * NodeValue(0.001);
+        b.0.node -= db * NodeValue(0.001);
 
         let mut correct = 0;
-        for t in 0..ntest {
-            let x = mat(DMatrix::<f32>::from_iterator(
-                s,
-                1,
-                tst_img[t * s..t * s + s].iter().map(|x| *x as f32 / 255.),
-            ))
-            .symbol("x");
+        for (t, &label) in tst_lbl.iter().enumerate() {
+            let x = Slice(&tests, 0..s, t..t + 1);
             let y = &w * x + &b;
 
-            if tst_lbl[t] as usize == argmax(y.eval().0.unwrap().as_slice()) {
+            if label as usize == argmax(y.eval().into_dense().unwrap().as_slice()) {
                 correct += 1;
             }
         }
@@ -0,0 +1,555 @@
+//! Numeric backends, which store the dense matrices of [`MatrixNode`] and run its kernels.
+//!
+//! Expressions only rely on the operators of their values, so the same expression runs on any
+//! backend by building it from `MatrixNode<T, R, C, B>`s of that backend.
+
+use crate::mat::{pooled, recycle, MatrixNode};
+use crate::value::{Scalar, Zero};
+use nalgebra::allocator::Allocator;
+use nalgebra::constraint::{AreMultipliable, ShapeConstraint};
+use nalgebra::{ClosedAdd, ClosedMul, DefaultAllocator, Dim, OMatrix};
+use std::fmt::Debug;
+use std::ops::Add;
+
+/// Storage of dense matrices, and the kernels of [`MatrixNode`] on it.
+///
+/// Backends without static dimensions ignore `R` and `C` of their storage.
+pub trait Backend<T: nalgebra::Scalar>: 'static {
+    type Storage<R: Dim, C: Dim>: Clone + Debug + 'static
+    where
+        DefaultAllocator: Allocator<T, R, C>;
+
+    fn from_fn<R: Dim, C: Dim>(
+        rows: usize,
+        cols: usize,
+        f: impl FnMut(usize, usize) -> T,
+    ) -> Self::Storage<R, C>
+    where
+        DefaultAllocator: Allocator<T, R, C>;
+
+    fn shape<R: Dim, C: Dim>(m: &Self::Storage<R, C>) -> (usize, usize)
+    where
+        DefaultAllocator: Allocator<T, R, C>;
+
+    fn get<R: Dim, C: Dim>(m: &Self::Storage<R, C>, i: usize, j: usize) -> T
+    where
+        DefaultAllocator: Allocator<T, R, C>;
+
+    /// The elements in the order of the backend, which is the same for matrices of the same
+    /// shape.
+    fn as_slice<R: Dim, C: Dim>(m: &Self::Storage<R, C>) -> &[T]
+    where
+        DefaultAllocator: Allocator<T, R, C>;
+
+    fn as_mut_slice<R: Dim, C: Dim>(m: &mut Self::Storage<R, C>) -> &mut [T]
+    where
+        DefaultAllocator: Allocator<T, R, C>;
+
+    fn matmul<R1: Dim, C1: Dim, R2: Dim, C2: Dim>(
+        a: Self::Storage<R1, C1>,
+        b: Self::Storage<R2, C2>,
+    ) -> Self::Storage<R1, C2>
+    where
+        T: Scalar + num_traits::Zero + num_traits::One + ClosedAdd + ClosedMul,
+        ShapeConstraint: AreMultipliable<R1, C1, R2, C2>,
+        DefaultAllocator: Allocator<T, R1, C1> + Allocator<T, R2, C2> + Allocator<T, R1, C2>;
+
+    fn transpose<R: Dim, C: Dim>(m: Self::Storage<R, C>) -> Self::Storage<C, R>
+    where
+        DefaultAllocator: Allocator<T, R, C> + Allocator<T, C, R>;
+
+    fn map<R: Dim, C: Dim>(mut m: Self::Storage<R, C>, f: impl Fn(T) -> T) -> Self::Storage<R, C>
+    where
+        DefaultAllocator: Allocator<T, R, C>,
+    {
+        Self::as_mut_slice(&mut m)
+            .iter_mut()
+            .for_each(|x| *x = f(x.clone()));
+        m
+    }
+
+    /// `f(x, y)` for the elements `x` of `a` and `y` of `b`, which have the same shape, in the
+    /// storage of `a`. Callers check the shapes first, e.g. with [`crate::shape::values`].
+    fn zip_map<R1: Dim, C1: Dim, R2: Dim, C2: Dim>(
+        mut a: Self::Storage<R1, C1>,
+        b: Self::Storage<R2, C2>,
+        f: impl Fn(T, T) -> T,
+    ) -> Self::Storage<R1, C1>
+    where
+        DefaultAllocator: Allocator<T, R1, C1> + Allocator<T, R2, C2>,
+    {
+        assert_eq!(Self::shape(&a), Self::shape(&b), "{a:?} and {b:?}");
+        Self::as_mut_slice(&mut a)
+            .iter_mut()
+            .zip(Self::as_slice(&b))
+            .for_each(|(x, y)| *x = f(x.clone(), y.clone()));
+        Self::recycle(b);
+        a
+    }
+
+    fn sum<R: Dim, C: Dim>(m: Self::Storage<R, C>) -> T
+    where
+        T: Scalar + Add<Output = T>,
+        DefaultAllocator: Allocator<T, R, C>,
+    {
+        let sum = Self::as_slice(&m)
+            .iter()
+            .fold(T::from(Zero), |acc, x| acc + x.clone());
+        Self::recycle(m);
+        sum
+    }
+
+    fn copy<R: Dim, C: Dim>(m: &Self::Storage<R, C>) -> Self::Storage<R, C>
+    where
+        DefaultAllocator: Allocator<T, R, C>,
+    {
+        m.clone()
+    }
+
+    /// Disposes of a matrix that is no longer needed, e.g. by returning its buffer to a pool.
+    fn recycle<R: Dim, C: Dim>(_m: Self::Storage<R, C>)
+    where
+        DefaultAllocator: Allocator<T, R, C>,
+    {
+    }
+}
+
+/// The default backend, storing an [`OMatrix`]. Dynamically sized matrices take their buffers
+/// from the active [`Workspace`](crate::workspace::Workspace).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Nalgebra;
+
+impl<T: nalgebra::Scalar> Backend<T> for Nalgebra {
+    type Storage<R: Dim, C: Dim> = OMatrix<T, R, C> where DefaultAllocator: Allocator<T, R, C>;
+
+    fn from_fn<R: Dim, C: Dim>(
+        rows: usize,
+        cols: usize,
+        mut f: impl FnMut(usize, usize) -> T,
+    ) -> Self::Storage<R, C>
+    where
+        DefaultAllocator: Allocator<T, R, C>,
+    {
+        pooled(rows, cols, (0..rows * cols).map(|k| f(k % rows, k / rows)))
+    }
+
+    fn shape<R: Dim, C: Dim>(m: &Self::Storage<R, C>) -> (usize, usize)
+    where
+        DefaultAllocator: Allocator<T, R, C>,
+    {
+        m.shape()
+    }
+
+    fn get<R: Dim, C: Dim>(m: &Self::Storage<R, C>, i: usize, j: usize) -> T
+    where
+        DefaultAllocator: Allocator<T, R, C>,
+    {
+        m[(i, j)].clone()
+    }
+
+    fn as_slice<R: Dim, C: Dim>(m: &Self::Storage<R, C>) -> &[T]
+    where
+        DefaultAllocator: Allocator<T, R, C>,
+    {
+        m.as_slice()
+    }
+
+    fn as_mut_slice<R: Dim, C: Dim>(m: &mut Self::Storage<R, C>) -> &mut [T]
+    where
+        DefaultAllocator: Allocator<T, R, C>,
+    {
+        m.as_mut_slice()
+    }
+
+    fn matmul<R1: Dim, C1: Dim, R2: Dim, C2: Dim>(
+        a: Self::Storage<R1, C1>,
+        b: Self::Storage<R2, C2>,
+    ) -> Self::Storage<R1, C2>
+    where
+        T: Scalar + num_traits::Zero + num_traits::One + ClosedAdd + ClosedMul,
+        ShapeConstraint: AreMultipliable<R1, C1, R2, C2>,
+        DefaultAllocator: Allocator<T, R1, C1> + Allocator<T, R2, C2> + Allocator<T, R1, C2>,
+    {
+        let ((m, _k), n) = (a.shape(), b.ncols());
+        let mut product = pooled(m, n, (0..m * n).map(|_| T::zero()));
+        match () {
+            #[cfg(feature = "simd")]
+            () if crate::kernels::gemm_supports::<T>(m, _k, n) => {
+                crate::kernels::matmul(&a, &b, &mut product)
+            }
+            () => a.mul_to(&b, &mut product),
+        }
+        recycle(a);
+        recycle(b);
+        product
+    }
+
+    fn transpose<R: Dim, C: Dim>(m: Self::Storage<R, C>) -> Self::Storage<C, R>
+    where
+        DefaultAllocator: Allocator<T, R, C> + Allocator<T, C, R>,
+    {
+        let (r, c) = m.shape();
+        let t = pooled(c, r, (0..r * c).map(|k| m[(k / c, k % c)].clone()));
+        recycle(m);
+        t
+    }
+
+    fn copy<R: Dim, C: Dim>(m: &Self::Storage<R, C>) -> Self::Storage<R, C>
+    where
+        DefaultAllocator: Allocator<T, R, C>,
+    {
+        let (r, c) = m.shape();
+        pooled(r, c, m.iter().cloned())
+    }
+
+    fn recycle<R: Dim, C: Dim>(m: Self::Storage<R, C>)
+    where
+        DefaultAllocator: Allocator<T, R, C>,
+    {
+        recycle(m)
+    }
+}
+
+/// A pure-Rust backend with straightforward kernels, to check other backends against.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Reference;
+
+/// A row-major matrix.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RowMajor<T> {
+    pub rows: usize,
+    pub cols: usize,
+    pub data: Vec<T>,
+}
+
+impl<T: nalgebra::Scalar> Backend<T> for Reference {
+    type Storage<R: Dim, C: Dim> = RowMajor<T> where DefaultAllocator: Allocator<T, R, C>;
+
+    fn from_fn<R: Dim, C: Dim>(
+        rows: usize,
+        cols: usize,
+        mut f: impl FnMut(usize, usize) -> T,
+    ) -> Self::Storage<R, C>
+    where
+        DefaultAllocator: Allocator<T, R, C>,
+    {
+        let data = (0..rows * cols).map(|n| f(n / cols, n % cols)).collect();
+        RowMajor { rows, cols, data }
+    }
+
+    fn shape<R: Dim, C: Dim>(m: &Self::Storage<R, C>) -> (usize, usize)
+    where
+        DefaultAllocator: Allocator<T, R, C>,
+    {
+        (m.rows, m.cols)
+    }
+
+    fn get<R: Dim, C: Dim>(m: &Self::Storage<R, C>, i: usize, j: usize) -> T
+    where
+        DefaultAllocator: Allocator<T, R, C>,
+    {
+        m.data[i * m.cols + j].clone()
+    }
+
+    fn as_slice<R: Dim, C: Dim>(m: &Self::Storage<R, C>) -> &[T]
+    where
+        DefaultAllocator: Allocator<T, R, C>,
+    {
+        &m.data
+    }
+
+    fn as_mut_slice<R: Dim, C: Dim>(m: &mut Self::Storage<R, C>) -> &mut [T]
+    where
+        DefaultAllocator: Allocator<T, R, C>,
+    {
+        &mut m.data
+    }
+
+    fn matmul<R1: Dim, C1: Dim, R2: Dim, C2: Dim>(
+        a: Self::Storage<R1, C1>,
+        b: Self::Storage<R2, C2>,
+    ) -> Self::Storage<R1, C2>
+    where
+        T: Scalar + num_traits::Zero + num_traits::One + ClosedAdd + ClosedMul,
+        ShapeConstraint: AreMultipliable<R1, C1, R2, C2>,
+        DefaultAllocator: Allocator<T, R1, C1> + Allocator<T, R2, C2> + Allocator<T, R1, C2>,
+    {
+        // The shapes are checked by `MatrixNode::try_mul` and the static dimensions.
+        assert_eq!(a.cols, b.rows);
+        let get = |m: &RowMajor<T>, i: usize, j: usize| m.data[i * m.cols + j].clone();
+        Self::from_fn::<R1, C2>(a.rows, b.cols, |i, j| {
+            (0..a.cols).fold(T::zero(), |acc, k| acc + get(&a, i, k) * get(&b, k, j))
+        })
+    }
+
+    fn transpose<R: Dim, C: Dim>(m: Self::Storage<R, C>) -> Self::Storage<C, R>
+    where
+        DefaultAllocator: Allocator<T, R, C> + Allocator<T, C, R>,
+    {
+        Self::from_fn::<C, R>(m.cols, m.rows, |i, j| m.data[j * m.cols + i].clone())
+    }
+}
+
+/// A backend storing an [`ndarray::Array2`] in standard layout.
+#[cfg(feature = "ndarray")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Ndarray;
+
+#[cfg(feature = "ndarray")]
+impl<T: nalgebra::Scalar + ndarray::LinalgScalar> Backend<T> for Ndarray {
+    type Storage<R: Dim, C: Dim> = ndarray::Array2<T> where DefaultAllocator: Allocator<T, R, C>;
+
+    fn from_fn<R: Dim, C: Dim>(
+        rows: usize,
+        cols: usize,
+        mut f: impl FnMut(usize, usize) -> T,
+    ) -> Self::Storage<R, C>
+    where
+        DefaultAllocator: Allocator<T, R, C>,
+    {
+        ndarray::Array2::from_shape_fn((rows, cols), |(i, j)| f(i, j))
+    }
+
+    fn shape<R: Dim, C: Dim>(m: &Self::Storage<R, C>) -> (usize, usize)
+    where
+        DefaultAllocator: Allocator<T, R, C>,
+    {
+        m.dim()
+    }
+
+    fn get<R: Dim, C: Dim>(m: &Self::Storage<R, C>, i: usize, j: usize) -> T
+    where
+        DefaultAllocator: Allocator<T, R, C>,
+    {
+        m[(i, j)]
+    }
+
+    /// Arrays from [`MatrixNode::Dense`] may have any layout, so this copies those that aren't
+    /// in standard layout first.
+    fn as_mut_slice<R: Dim, C: Dim>(m: &mut Self::Storage<R, C>) -> &mut [T]
+    where
+        DefaultAllocator: Allocator<T, R, C>,
+    {
+        if !m.is_standard_layout() {
+            *m = m.as_standard_layout().into_owned();
+        }
+        m.as_slice_mut().expect("array in standard layout")
+    }
+
+    /// Panics for arrays that aren't in standard layout. The kernels of this backend only take
+    /// slices with [`Backend::as_mut_slice`].
+    fn as_slice<R: Dim, C: Dim>(m: &Self::Storage<R, C>) -> &[T]
+    where
+        DefaultAllocator: Allocator<T, R, C>,
+    {
+        m.as_slice().expect("array in standard layout")
+    }
+
+    fn zip_map<R1: Dim, C1: Dim, R2: Dim, C2: Dim>(
+        mut a: Self::Storage<R1, C1>,
+        b: Self::Storage<R2, C2>,
+        f: impl Fn(T, T) -> T,
+    ) -> Self::Storage<R1, C1>
+    where
+        DefaultAllocator: Allocator<T, R1, C1> + Allocator<T, R2, C2>,
+    {
+        assert_eq!(a.dim(), b.dim(), "{a:?} and {b:?}");
+        a.zip_mut_with(&b, |x, &y| *x = f(*x, y));
+        a
+    }
+
+    fn sum<R: Dim, C: Dim>(m: Self::Storage<R, C>) -> T
+    where
+        T: Scalar + Add<Output = T>,
+        DefaultAllocator: Allocator<T, R, C>,
+    {
+        m.sum()
+    }
+
+    fn copy<R: Dim, C: Dim>(m: &Self::Storage<R, C>) -> Self::Storage<R, C>
+    where
+        DefaultAllocator: Allocator<T, R, C>,
+    {
+        m.as_standard_layout().into_owned()
+    }
+
+    fn matmul<R1: Dim, C1: Dim, R2: Dim, C2: Dim>(
+        a: Self::Storage<R1, C1>,
+        b: Self::Storage<R2, C2>,
+    ) -> Self::Storage<R1, C2>
+    where
+        T: Scalar + num_traits::Zero + num_traits::One + ClosedAdd + ClosedMul,
+        ShapeConstraint: AreMultipliable<R1, C1, R2, C2>,
+        DefaultAllocator: Allocator<T, R1, C1> + Allocator<T, R2, C2> + Allocator<T, R1, C2>,
+    {
+        standard_layout(a.dot(&b))
+    }
+
+    fn transpose<R: Dim, C: Dim>(m: Self::Storage<R, C>) -> Self::Storage<C, R>
+    where
+        DefaultAllocator: Allocator<T, R, C> + Allocator<T, C, R>,
+    {
+        standard_layout(m.reversed_axes())
+    }
+}
+
+#[cfg(feature = "ndarray")]
+fn standard_layout<T: Clone>(a: ndarray::Array2<T>) -> ndarray::Array2<T> {
+    if a.is_standard_layout() {
+        a
+    } else {
+        a.as_standard_layout().into_owned()
+    }
+}
+
+/// The matrix, if the array has two axes.
+#[cfg(feature = "ndarray")]
+impl<T: nalgebra::Scalar + ndarray::LinalgScalar> TryFrom<ndarray::ArrayD<T>>
+    for MatrixNode<T, nalgebra::Dyn, nalgebra::Dyn, Ndarray>
+{
+    type Error = ndarray::ShapeError;
+
+    fn try_from(a: ndarray::ArrayD<T>) -> Result<Self, Self::Error> {
+        a.into_dimensionality()
+            .map(|a| MatrixNode::Dense(standard_layout(a)))
+    }
+}
+
+impl<T: nalgebra::Scalar, R: Dim, C: Dim, B: Backend<T>> MatrixNode<T, R, C, B>
+where
+    DefaultAllocator: Allocator<T, R, C>,
+{
+    /// Panics if `rows` or `cols` differ from a static dimension of the matrix.
+    pub fn from_fn(rows: usize, cols: usize, f: impl FnMut(usize, usize) -> T) -> Self {
+        crate::mat::assert_dims::<R, C>(rows, cols);
+        MatrixNode::Dense(B::from_fn(rows, cols, f))
+    }
+
+    /// The matrix with the elements of `data` in row-major order.
+    ///
+    /// Panics if `data` doesn't have `rows * cols` elements.
+    pub fn from_row_slice(rows: usize, cols: usize, data: &[T]) -> Self {
+        assert_eq!(
+            data.len(),
+            rows * cols,
+            "{rows}x{cols} matrix from {data:?}"
+        );
+        Self::from_fn(rows, cols, |i, j| data[i * cols + j].clone())
+    }
+
+    /// The same matrix, stored by another backend. Lazy matrices stay lazy.
+    pub fn to_backend<B2: Backend<T>>(&self) -> MatrixNode<T, R, C, B2> {
+        match self {
+            MatrixNode::Dense(m) => {
+                let (r, c) = B::shape(m);
+                MatrixNode::Dense(B2::from_fn(r, c, |i, j| B::get(m, i, j)))
+            }
+            MatrixNode::Zero(shape) => MatrixNode::Zero(*shape),
+            MatrixNode::Fill(r, c, v) => MatrixNode::Fill(*r, *c, v.clone()),
+            MatrixNode::Identity(n) => MatrixNode::Identity(*n),
+        }
+    }
+}
+
+#[test]
+fn backends() {
+    use crate::ops::TransposeAble;
+    use crate::prelude::*;
+    use crate::test::{assert_close, fixture};
+    use nalgebra::Dyn;
+
+    // The gradients of a two-layer network, with all matrices stored by the backend `$B`.
+    macro_rules! gradients {
+        ($B:ty) => {{
+            let m = |r, c, s| mat(fixture(r, c, s)).to_backend::<$B>();
+            let x = m(3, 1, 1.).symbol("x");
+            let w1 = m(2, 3, 2.).symbol("w1");
+            let w2 = m(4, 2, 3.).symbol("w2");
+            let h = &w1 * &x;
+            let y = &w2 * h.exp();
+            let f = Sum(&y);
+            let [dw1, dw2] = y.derivative(
+                ["w1", "w2"],
+                MatrixNode::<f64, Dyn, Dyn, $B>::fill(4, 1, 1.),
+            );
+            (
+                f.eval().0,
+                dw1.eval().to_backend::<Reference>(),
+                dw2.eval().to_backend::<Reference>(),
+            )
+        }};
+    }
+
+    let (rf, rdw1, rdw2) = gradients!(Reference);
+    let results = [
+        gradients!(Nalgebra),
+        #[cfg(feature = "ndarray")]
+        gradients!(Ndarray),
+    ];
+    for (f, dw1, dw2) in results {
+        assert!((f - rf).abs() < 1e-12);
+        for (a, b) in [(dw1, rdw1.clone()), (dw2, rdw2.clone())] {
+            assert_eq!(a.shape(), b.shape());
+            assert_close(&a.into_dense().unwrap().data, &b.into_dense().unwrap().data);
+        }
+    }
+
+    let a = MatrixNode::<f64, Dyn, Dyn, Reference>::from_row_slice(2, 3, &[1., 2., 3., 4., 5., 6.]);
+    let n: MatrixNode<f64> = a.to_backend();
+    assert_eq!(
+        n.clone().into_dense().unwrap(),
+        DMatrix::from_row_slice(2, 3, &[1., 2., 3., 4., 5., 6.])
+    );
+    assert_eq!(
+        n.clone().transpose_().to_backend::<Reference>(),
+        a.clone().transpose_()
+    );
+    assert_eq!(n.to_backend::<Reference>(), a);
+
+    // Lazy matrices stay lazy on every backend.
+    let zero = MatrixNode::<f64, Dyn, Dyn, Reference>::zeros(1, 2);
+    assert_eq!((zero.clone() + NodeValue(1.)).get(0, 1), 1.);
+    assert!(zero.to_backend::<Nalgebra>().is_zero());
+    assert_eq!((NodeValue(6.) / a.clone()).get(1, 2), 1.);
+    let i = MatrixNode::<f64, Dyn, Dyn, Reference>::identity(2);
+    assert_eq!(i * a.clone(), a);
+
+    #[cfg(feature = "ndarray")]
+    {
+        use crate::primitive_ops::Exp;
+
+        let d = ndarray::ArrayD::from_shape_vec(vec![2, 3], vec![1., 2., 3., 4., 5., 6.]).unwrap();
+        let t = TensorNode::from(d.clone());
+        assert_eq!(t.get(&[1, 0]), 4.);
+        assert_eq!(ndarray::ArrayD::from(t.permute(&[1, 0])), d.t());
+        let nd = MatrixNode::<f64, Dyn, Dyn, Ndarray>::try_from(d).unwrap();
+        assert_eq!(nd.to_backend::<Reference>(), a);
+        assert_eq!(
+            nd.transpose_().to_backend::<Reference>(),
+            a.clone().transpose_()
+        );
+        assert!(
+            MatrixNode::<f64, Dyn, Dyn, Ndarray>::try_from(ndarray::ArrayD::zeros(vec![2, 2, 2]))
+                .is_err()
+        );
+        // Arrays of any layout, e.g. transposed ones, can be given to `Dense`.
+        let t = ndarray::arr2(&[[1., 4.], [2., 5.], [3., 6.]]).reversed_axes();
+        let t = MatrixNode::<f64, Dyn, Dyn, Ndarray>::Dense(t);
+        let nd =
+            MatrixNode::<f64, Dyn, Dyn, Ndarray>::from_row_slice(2, 3, &[1., 2., 3., 4., 5., 6.]);
+        assert_eq!(t.clone().to_backend::<Reference>(), a);
+        assert_eq!(
+            (t.clone() + nd.clone()).to_backend::<Reference>(),
+            a.clone() + a.clone()
+        );
+        assert_eq!(
+            (nd + t.clone()).to_backend::<Reference>(),
+            a.clone() + a.clone()
+        );
+        assert_eq!(
+            Exp::exp(t.clone()).to_backend::<Reference>(),
+            Exp::exp(a.clone())
+        );
+        assert_eq!(Sum(&t).eval().0, 21.);
+    }
+}
@@ -0,0 +1,651 @@
+//! Source code computing an expression and its gradients, for shipping without this crate.
+//!
+//! [`Compiled::to_rust`] emits a standalone Rust function over scalars and nalgebra `DMatrix`
+//! values. The string can be written to a file from a `build.rs` and included with
+//! `include!`. [`Compiled::to_c`] emits a C99 header and source file over scalars and
+//! fixed-size arrays, which don't allocate.
+
+use crate::compile::{len, Compiled, Instr, Reg};
+use crate::linalg::Real;
+use crate::shape::Shape;
+use std::fmt::Write;
+
+/// Names used by the generated Rust code, besides the temporaries.
+const RUST_RESERVED: &[&str] = &[
+    "as", "else", "e", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod",
+    "move", "mut", "nalgebra", "ref", "return", "self", "struct", "true", "type", "use", "while",
+];
+
+/// Names used by the generated C code, besides the temporaries.
+const C_RESERVED: &[&str] = &[
+    "auto", "break", "case", "char", "const", "continue", "default", "do", "double", "else",
+    "enum", "exp", "expf", "extern", "float", "for", "goto", "if", "inline", "int", "long",
+    "register", "restrict", "return", "short", "signed", "sizeof", "static", "struct", "switch",
+    "typedef", "union", "unsigned", "value", "void", "volatile", "while",
+];
+
+/// `symbol` as an identifier, which doesn't collide with the `reserved` names or the names of
+/// the temporaries, such as `t0` or `t_i`.
+fn ident(symbol: &str, reserved: &[&str]) -> String {
+    let mut ident: String = symbol
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let temporary = ident
+        .strip_prefix('t')
+        .is_some_and(|n| n.starts_with(|c: char| c.is_ascii_digit() || c == '_'));
+    if ident.is_empty() || ident.starts_with(|c: char| c.is_ascii_digit()) || temporary {
+        ident.insert(0, '_');
+    }
+    if reserved.contains(&ident.as_str()) {
+        ident.push('_');
+    }
+    ident
+}
+
+/// `ident` with a numeric suffix if it is already `taken`, e.g. for the symbols `a-b` and `a_b`.
+fn unique(ident: String, taken: &mut Vec<String>) -> String {
+    let ident = match taken.contains(&ident) {
+        false => ident,
+        true => (1..)
+            .map(|i| format!("{ident}_{i}"))
+            .find(|name| !taken.contains(name))
+            .unwrap(),
+    };
+    taken.push(ident.clone());
+    ident
+}
+
+fn is_matrix(shape: Shape) -> bool {
+    matches!(shape, Shape::Matrix(..))
+}
+
+/// The Rust literal of `x`, such as `0.5f64`.
+fn literal<T: Real>(x: T) -> String {
+    let ty = std::any::type_name::<T>();
+    match x.partial_cmp(&T::zero()) {
+        _ if x.is_finite() && x.is_sign_negative() => format!("({x:?}{ty})"),
+        _ if x.is_finite() => format!("{x:?}{ty}"),
+        None => format!("{ty}::NAN"),
+        Some(std::cmp::Ordering::Greater) => format!("{ty}::INFINITY"),
+        Some(_) => format!("{ty}::NEG_INFINITY"),
+    }
+}
+
+/// The names of the registers in the generated code.
+struct Names<'a, T, const LEN: usize> {
+    program: &'a Compiled<T, LEN>,
+    names: Vec<String>,
+    inputs: Vec<bool>,
+}
+
+impl<T: Real, const LEN: usize> Names<'_, T, LEN> {
+    fn shape(&self, r: Reg) -> Shape {
+        self.program.shapes[r.0]
+    }
+
+    /// The register as an operand of a method call, or as a scalar.
+    fn value(&self, r: Reg) -> &str {
+        &self.names[r.0]
+    }
+
+    /// The register as an operand of a matrix op, which takes references.
+    fn reference(&self, r: Reg) -> String {
+        match self.inputs[r.0] || !is_matrix(self.shape(r)) {
+            true => self.names[r.0].clone(),
+            false => format!("&{}", self.names[r.0]),
+        }
+    }
+
+    /// The Rust expression of `instr`.
+    fn rust(&self, instr: Instr) -> String {
+        let (v, rf) = (|r| self.value(r), |r| self.reference(r));
+        let binary =
+            |l: Reg, r: Reg, op: &str| match (is_matrix(self.shape(l)), is_matrix(self.shape(r))) {
+                (false, false) => format!("{} {op} {}", v(l), v(r)),
+                (true, false) => format!("{}.map(|e| e {op} {})", v(l), v(r)),
+                (false, true) => format!("{}.map(|e| {} {op} e)", v(r), v(l)),
+                (true, true) => match op {
+                    "*" => format!("{}.component_mul({})", v(l), rf(r)),
+                    "/" => format!("{}.component_div({})", v(l), rf(r)),
+                    _ => format!("{} {op} {}", rf(l), rf(r)),
+                },
+            };
+        let unary = |x: Reg, scalar: String, matrix: String| match is_matrix(self.shape(x)) {
+            true => matrix,
+            false => scalar,
+        };
+        match instr {
+            Instr::Add(l, r) => binary(l, r, "+"),
+            Instr::Sub(l, r) => binary(l, r, "-"),
+            Instr::Div(l, r) => binary(l, r, "/"),
+            Instr::ElemMul(l, r) => binary(l, r, "*"),
+            Instr::Mul(l, r) if is_matrix(self.shape(l)) && is_matrix(self.shape(r)) => {
+                format!("{} * {}", rf(l), rf(r))
+            }
+            Instr::Mul(l, r) => binary(l, r, "*"),
+            Instr::Neg(x) => unary(x, format!("-{}", v(x)), format!("-{}", rf(x))),
+            Instr::Exp(x) => unary(
+                x,
+                format!("{}.exp()", v(x)),
+                format!("{}.map(|e| e.exp())", v(x)),
+            ),
+            Instr::Transpose(x) => unary(x, v(x).into(), format!("{}.transpose()", v(x))),
+            Instr::Sum(x) => unary(x, v(x).into(), format!("{}.sum()", v(x))),
+        }
+    }
+}
+
+impl<T: Real, const LEN: usize> Compiled<T, LEN> {
+    /// A Rust function `name` computing the value of the expression and its gradients.
+    ///
+    /// The function takes the [`inputs`](Self::inputs) in order, as scalars or `&DMatrix`
+    /// values, named after the symbols with a numeric suffix if names collide, and returns a
+    /// tuple of the value and the gradients. Only the instructions the outputs depend on are
+    /// emitted, and constant matrices are embedded in the code.
+    pub fn to_rust(&self, name: &str) -> String {
+        let ty = std::any::type_name::<T>();
+        let type_of = |s| match is_matrix(s) {
+            true => format!("nalgebra::DMatrix<{ty}>"),
+            false => ty.to_string(),
+        };
+        let live = self.live();
+        let mut names = Names {
+            program: self,
+            names: vec![String::new(); self.registers.len()],
+            inputs: vec![false; self.registers.len()],
+        };
+        let mut computed = vec![false; self.registers.len()];
+        self.code
+            .iter()
+            .for_each(|&(_, dst)| computed[dst.0] = true);
+
+        let mut params = vec![];
+        let mut taken = vec![];
+        for &(symbol, reg) in &self.inputs {
+            names.names[reg.0] = unique(ident(symbol, RUST_RESERVED), &mut taken);
+            names.inputs[reg.0] = true;
+            let ty = match is_matrix(self.shapes[reg.0]) {
+                true => format!("&{}", type_of(self.shapes[reg.0])),
+                false => type_of(self.shapes[reg.0]),
+            };
+            params.push(format!("{}: {ty}", names.names[reg.0]));
+        }
+
+        let mut body = String::new();
+        let mut temporaries = 0;
+        let mut temporary = |body: &mut String, names: &mut Names<T, LEN>, r: Reg, expr| {
+            names.names[r.0] = format!("t{temporaries}");
+            temporaries += 1;
+            writeln!(body, "    let {} = {expr};", names.names[r.0]).unwrap();
+        };
+        for (r, value) in self.registers.iter().enumerate() {
+            if names.inputs[r] || computed[r] {
+                continue;
+            }
+            match self.shapes[r] {
+                Shape::Matrix(rows, cols) if live[r] => {
+                    let expr = match value.iter().all(|&x| x == value[0]) {
+                        true => format!("from_element({rows}, {cols}, {})", literal(value[0])),
+                        false => {
+                            let elements: Vec<_> = value.iter().map(|&x| literal(x)).collect();
+                            format!(
+                                "from_column_slice({rows}, {cols}, &[{}])",
+                                elements.join(", ")
+                            )
+                        }
+                    };
+                    let expr = format!("nalgebra::DMatrix::<{ty}>::{expr}");
+                    temporary(&mut body, &mut names, Reg(r), expr);
+                }
+                _ => names.names[r] = literal(value[0]),
+            }
+        }
+        for &(instr, dst) in &self.code {
+            if live[dst.0] {
+                let expr = names.rust(instr);
+                temporary(&mut body, &mut names, dst, expr);
+            }
+        }
+
+        let outputs: Vec<_> = self.outputs().collect();
+        let results: Vec<_> = (0..outputs.len())
+            .map(|i| {
+                let r = outputs[i];
+                let name = names.value(r);
+                let moved = !names.inputs[r.0] && !outputs[i + 1..].contains(&r);
+                match is_matrix(self.shapes[r.0]) && !moved {
+                    true => format!("{name}.clone()"),
+                    false => name.to_string(),
+                }
+            })
+            .collect();
+        let types: Vec<_> = outputs.iter().map(|r| type_of(self.shapes[r.0])).collect();
+
+        format!(
+            "pub fn {name}({}) -> ({}) {{\n{body}    ({})\n}}\n",
+            params.join(", "),
+            types.join(", "),
+            results.join(", "),
+        )
+    }
+}
+
+/// A C header and source file, from [`Compiled::to_c`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CCode {
+    /// Declares the function.
+    pub header: String,
+    /// Defines the function, and includes the header as `"<name>.h"`.
+    pub source: String,
+}
+
+/// The C literal of `x`, such as `0.5f` for a `float`.
+fn c_literal<T: Real>(x: T, suffix: &str) -> String {
+    match x.partial_cmp(&T::zero()) {
+        _ if x.is_finite() && x.is_sign_negative() => format!("({x:?}{suffix})"),
+        _ if x.is_finite() => format!("{x:?}{suffix}"),
+        None => "NAN".into(),
+        Some(std::cmp::Ordering::Greater) => "INFINITY".into(),
+        Some(_) => "(-INFINITY)".into(),
+    }
+}
+
+impl<T: Real, const LEN: usize> Compiled<T, LEN> {
+    /// A C99 function `name` computing the value of the expression and its gradients.
+    ///
+    /// The function takes the [`inputs`](Self::inputs) in order, as scalars or column-major
+    /// arrays, followed by pointers to the value and to the gradient with respect to each
+    /// symbol `x`, named `d_x`. Names that would collide get a numeric suffix. Temporaries are
+    /// on the stack, so matrices should be small.
+    pub fn to_c(&self, name: &str) -> CCode {
+        let float = std::any::TypeId::of::<T>() == std::any::TypeId::of::<f32>();
+        let (ty, suffix, exp) = match float {
+            true => ("float", "f", "expf"),
+            false => ("double", "", "exp"),
+        };
+        let name = ident(name, C_RESERVED);
+        let live = self.live();
+        let mut names = vec![String::new(); self.registers.len()];
+        let mut computed = vec![false; self.registers.len()];
+        self.code
+            .iter()
+            .for_each(|&(_, dst)| computed[dst.0] = true);
+
+        let mut params = vec![];
+        let mut taken = vec![];
+        for &(symbol, reg) in &self.inputs {
+            names[reg.0] = unique(ident(symbol, C_RESERVED), &mut taken);
+            params.push(match self.shapes[reg.0] {
+                s @ Shape::Matrix(..) => format!("const {ty} {}[{}]", names[reg.0], len(s)),
+                _ => format!("{ty} {}", names[reg.0]),
+            });
+        }
+        let outputs: Vec<_> = self.outputs().collect();
+        let output_names: Vec<_> = std::iter::once("value".to_string())
+            .chain(self.wrt.iter().map(|k| format!("d_{}", ident(k, &[]))))
+            .map(|out| unique(out, &mut taken))
+            .collect();
+        for (&r, out) in outputs.iter().zip(&output_names) {
+            params.push(match self.shapes[r.0] {
+                s @ Shape::Matrix(..) => format!("{ty} {out}[{}]", len(s)),
+                _ => format!("{ty} *{out}"),
+            });
+        }
+        let signature = format!("void {name}({})", params.join(", "));
+
+        let mut body = String::new();
+        let mut temporaries = 0;
+        let mut temporary = |names: &mut Vec<String>, r: Reg| {
+            names[r.0] = format!("t{temporaries}");
+            temporaries += 1;
+            names[r.0].clone()
+        };
+        for (r, value) in self.registers.iter().enumerate() {
+            if !names[r].is_empty() || computed[r] {
+                continue;
+            }
+            match self.shapes[r] {
+                s @ Shape::Matrix(..) if live[r] => {
+                    let t = temporary(&mut names, Reg(r));
+                    let elements: Vec<_> = value.iter().map(|&x| c_literal(x, suffix)).collect();
+                    let elements = elements.join(", ");
+                    writeln!(
+                        body,
+                        "    static const {ty} {t}[{}] = {{{elements}}};",
+                        len(s)
+                    )
+                    .unwrap();
+                }
+                _ => names[r] = c_literal(value[0], suffix),
+            }
+        }
+
+        for &(instr, dst) in &self.code {
+            if !live[dst.0] {
+                continue;
+            }
+            let shape = |r: Reg| self.shapes[r.0];
+            // The element `i` of a register, where scalars are broadcast.
+            let at = |names: &[String], r: Reg, i: &str| match is_matrix(shape(r)) {
+                true => format!("{}[{i}]", names[r.0]),
+                false => names[r.0].clone(),
+            };
+            let elementwise = |names: &[String], i: &str| match instr {
+                Instr::Add(l, r) => format!("{} + {}", at(names, l, i), at(names, r, i)),
+                Instr::Sub(l, r) => format!("{} - {}", at(names, l, i), at(names, r, i)),
+                Instr::Div(l, r) => format!("{} / {}", at(names, l, i), at(names, r, i)),
+                Instr::ElemMul(l, r) | Instr::Mul(l, r) => {
+                    format!("{} * {}", at(names, l, i), at(names, r, i))
+                }
+                Instr::Neg(x) => format!("-{}", at(names, x, i)),
+                Instr::Exp(x) => format!("{exp}({})", at(names, x, i)),
+                Instr::Transpose(x) | Instr::Sum(x) => at(names, x, i),
+            };
+            let out = self.shapes[dst.0];
+            match instr {
+                Instr::Mul(l, r) if is_matrix(shape(l)) && is_matrix(shape(r)) => {
+                    let (Shape::Matrix(m, k), Shape::Matrix(_, n)) = (shape(l), shape(r)) else {
+                        unreachable!()
+                    };
+                    let (a, b) = (names[l.0].clone(), names[r.0].clone());
+                    let t = temporary(&mut names, dst);
+                    writeln!(body, "    {ty} {t}[{}];", m * n).unwrap();
+                    writeln!(body, "    for (int t_j = 0; t_j < {n}; t_j++) {{").unwrap();
+                    writeln!(body, "        for (int t_i = 0; t_i < {m}; t_i++) {{").unwrap();
+                    writeln!(body, "            {ty} t_s = 0;").unwrap();
+                    writeln!(body, "            for (int t_k = 0; t_k < {k}; t_k++) {{").unwrap();
+                    writeln!(
+                        body,
+                        "                t_s += {a}[t_i + t_k * {m}] * {b}[t_k + t_j * {k}];"
+                    )
+                    .unwrap();
+                    writeln!(body, "            }}").unwrap();
+                    writeln!(body, "            {t}[t_i + t_j * {m}] = t_s;").unwrap();
+                    writeln!(body, "        }}").unwrap();
+                    writeln!(body, "    }}").unwrap();
+                }
+                Instr::Transpose(x) if is_matrix(shape(x)) => {
+                    let Shape::Matrix(r, c) = shape(x) else {
+                        unreachable!()
+                    };
+                    let a = names[x.0].clone();
+                    let t = temporary(&mut names, dst);
+                    writeln!(body, "    {ty} {t}[{}];", r * c).unwrap();
+                    writeln!(body, "    for (int t_j = 0; t_j < {c}; t_j++) {{").unwrap();
+                    writeln!(body, "        for (int t_i = 0; t_i < {r}; t_i++) {{").unwrap();
+                    writeln!(
+                        body,
+                        "            {t}[t_j + t_i * {c}] = {a}[t_i + t_j * {r}];"
+                    )
+                    .unwrap();
+                    writeln!(body, "        }}").unwrap();
+                    writeln!(body, "    }}").unwrap();
+                }
+                Instr::Sum(x) if is_matrix(shape(x)) => {
+                    let a = names[x.0].clone();
+                    let t = temporary(&mut names, dst);
+                    writeln!(body, "    {ty} {t} = 0;").unwrap();
+                    writeln!(
+                        body,
+                        "    for (int t_i = 0; t_i < {}; t_i++) {{",
+                        len(shape(x))
+                    )
+                    .unwrap();
+                    writeln!(body, "        {t} += {a}[t_i];").unwrap();
+                    writeln!(body, "    }}").unwrap();
+                }
+                _ if is_matrix(out) => {
+                    let expr = elementwise(&names, "t_i");
+                    let t = temporary(&mut names, dst);
+                    writeln!(body, "    {ty} {t}[{}];", len(out)).unwrap();
+                    writeln!(body, "    for (int t_i = 0; t_i < {}; t_i++) {{", len(out)).unwrap();
+                    writeln!(body, "        {t}[t_i] = {expr};").unwrap();
+                    writeln!(body, "    }}").unwrap();
+                }
+                _ => {
+                    let expr = elementwise(&names, "0");
+                    let t = temporary(&mut names, dst);
+                    writeln!(body, "    {ty} {t} = {expr};").unwrap();
+                }
+            }
+        }
+
+        for (&r, out) in outputs.iter().zip(&output_names) {
+            match self.shapes[r.0] {
+                s @ Shape::Matrix(..) => {
+                    writeln!(body, "    for (int t_i = 0; t_i < {}; t_i++) {{", len(s)).unwrap();
+                    writeln!(body, "        {out}[t_i] = {}[t_i];", names[r.0]).unwrap();
+                    writeln!(body, "    }}").unwrap();
+                }
+                _ => writeln!(body, "    *{out} = {};", names[r.0]).unwrap(),
+            }
+        }
+
+        let guard = format!("{}_H", name.to_uppercase());
+        CCode {
+            header: format!("#ifndef {guard}\n#define {guard}\n\n{signature};\n\n#endif\n"),
+            source: format!(
+                "#include <math.h>\n\n#include \"{name}.h\"\n\n{signature} {{\n{body}}}\n"
+            ),
+        }
+    }
+}
+
+#[test]
+fn rust() {
+    use crate::{prelude::*, Node};
+    use std::process::Command;
+
+    let x = 2f64.symbol("x");
+    let y = 3f64.symbol("y");
+    let f = &x * &y + &x * &x;
+    let program = Compiled::new(&f, ["x", "y"], One).unwrap();
+    let scalar = program.to_rust("f");
+    assert_eq!(
+        scalar,
+        "pub fn f(x: f64, y: f64) -> (f64, f64, f64) {
+    let t0 = x * y;
+    let t1 = x * x;
+    let t2 = t0 + t1;
+    let t3 = x + x;
+    let t4 = y + t3;
+    (t2, t4, x)
+}
+"
+    );
+
+    let x = mat(DMatrix::<f32>::from_element(3, 1, 1.)).symbol("x");
+    let w = mat(DMatrix::<f32>::from_element(2, 3, 1.)).symbol("w");
+    let b = mat(DMatrix::from_column_slice(2, 1, &[0.5f32, -1.])).symbol("b");
+    let y = Node(Exp(&w * &x)) - &b;
+    let program = Compiled::new(&y, ["w"], mat(DMatrix::from_element(2, 1, 1f32))).unwrap();
+    let layer = program.to_rust("layer");
+    assert_eq!(
+        layer,
+        "pub fn layer(w: &nalgebra::DMatrix<f32>, x: &nalgebra::DMatrix<f32>, \
+         b: &nalgebra::DMatrix<f32>) -> (nalgebra::DMatrix<f32>, nalgebra::DMatrix<f32>) {
+    let t0 = w * x;
+    let t1 = t0.map(|e| e.exp());
+    let t2 = &t1 - b;
+    let t3 = x.transpose();
+    let t4 = &t1 * &t3;
+    (t2, t4)
+}
+"
+    );
+
+    // Symbols that map to the same identifier get different parameters.
+    let a = 0.5f64.symbol("a-b");
+    let b = 4f64.symbol("a_b");
+    let g = Node(Exp(&a)) * &b;
+    let program = Compiled::new(&g, ["a-b", "a_b"], One).unwrap();
+    let collide = program.to_rust("g");
+    assert!(collide.starts_with("pub fn g(a_b: f64, a_b_1: f64)"));
+
+    // Builds and runs the generated code against nalgebra, which takes a while, so only if
+    // `AUTODIFF_BUILD_CODEGEN` is set.
+    let (Some(cargo), Some(_)) = (
+        std::env::var_os("CARGO"),
+        std::env::var_os("AUTODIFF_BUILD_CODEGEN"),
+    ) else {
+        return;
+    };
+    let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("target/codegen-rust");
+    std::fs::create_dir_all(dir.join("src")).unwrap();
+    std::fs::write(
+        dir.join("Cargo.toml"),
+        "[package]\nname = \"codegen-rust\"\nversion = \"0.0.0\"\nedition = \"2021\"\n\n\
+         [dependencies]\nnalgebra = \"0.32.2\"\n\n[workspace]\n",
+    )
+    .unwrap();
+    // The versions this crate was built with, which are already downloaded.
+    let _ = std::fs::copy(
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.lock"),
+        dir.join("Cargo.lock"),
+    );
+    std::fs::write(
+        dir.join("src/main.rs"),
+        format!(
+            r#"{scalar}
+{layer}
+{collide}
+fn main() {{
+    let x = nalgebra::DMatrix::from_element(3, 1, 1f32);
+    let w = nalgebra::DMatrix::from_element(2, 3, 1f32);
+    let b = nalgebra::DMatrix::from_column_slice(2, 1, &[0.5f32, -1.]);
+    let (y, dw) = layer(&w, &x, &b);
+    println!("{{:?}}", f(2., 3.));
+    println!("{{:?}} {{:?}}", y.as_slice(), dw.as_slice());
+    println!("{{:?}}", g(0.5, 4.));
+}}
+"#
+        ),
+    )
+    .unwrap();
+    let out = Command::new(cargo)
+        .args(["run", "--quiet", "--offline"])
+        .env_remove("CARGO_TARGET_DIR")
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+
+    let [dx, dy] = f.derivative(["x", "y"], One);
+    let (dx, dy): (NodeValue<f64>, NodeValue<f64>) = (dx.eval(), dy.eval());
+    let [dw] = y.derivative(["w"], mat(DMatrix::from_element(2, 1, 1f32)));
+    let dense = |m: MatrixNode<f32>| m.into_dense().unwrap();
+    let [da, db] = g.derivative(["a-b", "a_b"], One);
+    let expected = format!(
+        "{:?}\n{:?} {:?}\n{:?}\n",
+        (f.eval().0, dx.0, dy.0),
+        dense(y.eval()).as_slice(),
+        dense(dw.eval()).as_slice(),
+        (g.eval().0, da.eval().0, db.eval().0),
+    );
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), expected);
+}
+
+#[test]
+fn c() {
+    use crate::test::assert_close;
+    use crate::{ops, prelude::*, Node};
+    use std::process::Command;
+
+    let x = 2f64.symbol("x");
+    let y = 3f64.symbol("y");
+    let f = &x * &y + &x * &x;
+    let code = Compiled::new(&f, ["x", "y"], One).unwrap().to_c("f");
+    assert_eq!(
+        code.header,
+        "#ifndef F_H\n#define F_H\n\n\
+         void f(double x, double y, double *value, double *d_x, double *d_y);\n\n#endif\n"
+    );
+    assert_eq!(
+        code.source,
+        "#include <math.h>\n\n#include \"f.h\"\n\n\
+         void f(double x, double y, double *value, double *d_x, double *d_y) {
+    double t0 = x * y;
+    double t1 = x * x;
+    double t2 = t0 + t1;
+    double t3 = x + x;
+    double t4 = y + t3;
+    *value = t2;
+    *d_x = t4;
+    *d_y = x;
+}
+"
+    );
+
+    let a = 0.5f64.symbol("a-b");
+    let b = 4f64.symbol("a_b");
+    let code = Compiled::new(&(&a * &b), ["a-b", "a_b"], One)
+        .unwrap()
+        .to_c("g");
+    assert!(code.header.contains(
+        "void g(double a_b, double a_b_1, double *value, double *d_a_b, double *d_a_b_1);"
+    ));
+
+    // Compiles and runs the generated code, if there is a C compiler.
+    let x = mat(DMatrix::from_column_slice(3, 1, &[0.1, -0.2, 0.3])).symbol("x");
+    let w = mat(DMatrix::from_fn(2, 3, |i, j| (i as f64 - j as f64) * 0.3)).symbol("w");
+    let s = 1.5f64.symbol("s");
+    let c = mat(DMatrix::from_column_slice(2, 1, &[2., -0.5]));
+    let h = Node(Exp(&w * &x)).cached();
+    let y = ops::Add(
+        ops::Mul(ops::Div(ops::Sum(ops::ElemMul(&h, &c)), Node(Exp(&s))), &s),
+        ops::Sum(ops::Mul(ops::Transpose(&x), &x)),
+    );
+    let code = Compiled::new(&y, ["x", "w", "s"], One)
+        .unwrap()
+        .to_c("layer");
+    let dir = std::env::temp_dir().join(format!("autodiff-codegen-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("layer.h"), &code.header).unwrap();
+    std::fs::write(dir.join("layer.c"), &code.source).unwrap();
+    std::fs::write(
+        dir.join("main.c"),
+        r#"#include <stdio.h>
+#include "layer.h"
+
+int main(void) {
+    const double w[6] = {0, 0.3, -0.3, 0, -0.6, -0.3}, x[3] = {0.1, -0.2, 0.3};
+    double value, d_x[3], d_w[6], d_s;
+    layer(w, x, 1.5, &value, d_x, d_w, &d_s);
+    printf("%.17g", value);
+    for (int i = 0; i < 3; i++) printf(" %.17g", d_x[i]);
+    for (int i = 0; i < 6; i++) printf(" %.17g", d_w[i]);
+    printf(" %.17g", d_s);
+    return 0;
+}
+"#,
+    )
+    .unwrap();
+    let Ok(cc) = Command::new("cc")
+        .args(["-std=c99", "-Wall", "-Wextra", "-Werror", "-pedantic"])
+        .args(["main.c", "layer.c", "-lm", "-o", "layer"])
+        .current_dir(&dir)
+        .status()
+    else {
+        return std::fs::remove_dir_all(&dir).unwrap();
+    };
+    assert!(cc.success());
+    let out = Command::new(dir.join("layer")).output().unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    let out: Vec<f64> = String::from_utf8(out.stdout)
+        .unwrap()
+        .split(' ')
+        .map(|x| x.parse().unwrap())
+        .collect();
+
+    let [dx, dw, ds] = y.derivative(["x", "w", "s"], One);
+    let mut expected = vec![y.eval().0];
+    for d in [dx.eval(), dw.eval(), ds.eval()] {
+        expected.extend(d.into_dense().unwrap().iter());
+    }
+    assert_close(&out, &expected);
+}
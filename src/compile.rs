@@ -0,0 +1,625 @@
+//! Compilation of an expression and its gradients into a flat sequence of instructions.
+//!
+//! [`Compiled::new`] lowers the nodes once, and [`Compiled::run`] then evaluates the instructions
+//! over registers that are allocated up front, so evaluating the same expression for many inputs
+//! doesn't walk the nodes or allocate. Symbols become inputs, which are rebound with
+//! [`Compiled::bind`] between runs.
+//!
+//! Nodes that are reached more than once, such as the forward values referenced by the
+//! gradients, are computed once. Instructions whose operands are all constants are evaluated
+//! while compiling.
+
+use crate::backend::Backend;
+use crate::error::Result;
+use crate::linalg::Real;
+use crate::mat::MatrixNode;
+use crate::ops::{self, Cached, Checkpoint};
+use crate::shape::{Shape, ShapeError};
+use crate::value::{Atom, NodeValue, Zero};
+use crate::{Differentiable, Eval, Node, Symbol};
+use nalgebra::{allocator::Allocator, DMatrixView, DMatrixViewMut, DefaultAllocator, Dim};
+use std::collections::HashMap;
+
+/// A register of a compiled program, holding a scalar or a column-major matrix.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Reg(pub(crate) usize);
+
+/// An instruction, which writes to a new register. Scalar operands are broadcast.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Instr {
+    Add(Reg, Reg),
+    Sub(Reg, Reg),
+    Div(Reg, Reg),
+    ElemMul(Reg, Reg),
+    /// A matrix product, or a scaling if either operand is a scalar.
+    Mul(Reg, Reg),
+    Neg(Reg),
+    Exp(Reg),
+    Transpose(Reg),
+    Sum(Reg),
+}
+
+impl Instr {
+    fn name(self) -> &'static str {
+        match self {
+            Instr::Add(..) => "Add",
+            Instr::Sub(..) => "Sub",
+            Instr::Div(..) => "Div",
+            Instr::ElemMul(..) => "ElemMul",
+            Instr::Mul(..) => "Mul",
+            Instr::Neg(_) => "Neg",
+            Instr::Exp(_) => "Exp",
+            Instr::Transpose(_) => "Transpose",
+            Instr::Sum(_) => "Sum",
+        }
+    }
+
+    pub(crate) fn operands(self) -> Vec<Reg> {
+        match self {
+            Instr::Add(l, r)
+            | Instr::Sub(l, r)
+            | Instr::Div(l, r)
+            | Instr::ElemMul(l, r)
+            | Instr::Mul(l, r) => vec![l, r],
+            Instr::Neg(x) | Instr::Exp(x) | Instr::Transpose(x) | Instr::Sum(x) => vec![x],
+        }
+    }
+
+    fn shape(self, shapes: &[Shape]) -> Option<Shape> {
+        let s = |r: Reg| shapes[r.0];
+        match self {
+            Instr::Add(l, r) | Instr::Sub(l, r) | Instr::Div(l, r) | Instr::ElemMul(l, r) => {
+                s(l).elementwise(s(r))
+            }
+            Instr::Mul(l, r) => s(l).matmul(s(r)),
+            Instr::Neg(x) | Instr::Exp(x) => Some(s(x)),
+            Instr::Transpose(x) => Some(s(x).transpose()),
+            Instr::Sum(_) => Some(Shape::Scalar),
+        }
+    }
+}
+
+/// Nodes that can be lowered to instructions over elements of type `T`.
+pub trait Compile<T> {
+    /// Emits the instructions computing the node, and returns the register holding its value.
+    /// Operands are lowered with [`Builder::lower`], so that shared nodes are computed once.
+    fn compile<'a>(&'a self, b: &mut Builder<'a, T>) -> Result<Reg>;
+}
+
+/// Collects the registers and instructions of a program while its nodes are lowered.
+///
+/// Nodes are identified by their address, so every node lowered by a builder lives at least as
+/// long as the builder.
+pub struct Builder<'a, T> {
+    registers: Vec<Vec<T>>,
+    shapes: Vec<Shape>,
+    /// Symbols the value of each register depends on, for shape errors.
+    symbols: Vec<Vec<&'static str>>,
+    /// The value of every element of each register that is a constant with equal elements.
+    uniform: Vec<Option<T>>,
+    constant: Vec<bool>,
+    code: Vec<(Instr, Reg)>,
+    /// The register each emitted instruction writes to, so that repeated ones are emitted once.
+    emitted: HashMap<Instr, Reg>,
+    /// The emitted instruction that writes to each register.
+    defs: Vec<Option<Instr>>,
+    inputs: Vec<(&'static str, Reg)>,
+    lowered: HashMap<(usize, &'static str), Reg>,
+    nodes: std::marker::PhantomData<&'a ()>,
+}
+
+impl<'a, T: Real> Builder<'a, T> {
+    fn new() -> Self {
+        Self {
+            registers: vec![],
+            shapes: vec![],
+            symbols: vec![],
+            uniform: vec![],
+            constant: vec![],
+            code: vec![],
+            emitted: HashMap::new(),
+            defs: vec![],
+            inputs: vec![],
+            lowered: HashMap::new(),
+            nodes: std::marker::PhantomData,
+        }
+    }
+
+    /// Lowers `node`, or returns its register if it has already been lowered.
+    pub fn lower<N: Compile<T> + ?Sized>(&mut self, node: &'a N) -> Result<Reg> {
+        let key = (
+            node as *const N as *const () as usize,
+            std::any::type_name::<N>(),
+        );
+        if let Some(&reg) = self.lowered.get(&key) {
+            return Ok(reg);
+        }
+        let reg = node.compile(self)?;
+        self.lowered.insert(key, reg);
+        Ok(reg)
+    }
+
+    fn register(&mut self, shape: Shape, value: Vec<T>, symbols: Vec<&'static str>) -> Reg {
+        self.uniform.push(uniform(&value));
+        self.registers.push(value);
+        self.shapes.push(shape);
+        self.symbols.push(symbols);
+        self.constant.push(true);
+        self.defs.push(None);
+        Reg(self.registers.len() - 1)
+    }
+
+    /// A register holding `value`, a scalar or a column-major matrix.
+    pub fn constant(&mut self, shape: Shape, value: Vec<T>) -> Reg {
+        assert_eq!(
+            len(shape),
+            value.len(),
+            "{shape} constant of the wrong length"
+        );
+        self.register(shape, value, vec![])
+    }
+
+    /// The register of `symbol`, which holds `value` until another value is bound to it.
+    pub fn input(&mut self, symbol: &'static str, shape: Shape, value: Vec<T>) -> Result<Reg> {
+        if let Some(&(_, reg)) = self.inputs.iter().find(|(s, _)| *s == symbol) {
+            if self.shapes[reg.0] != shape {
+                let shapes = vec![self.shapes[reg.0], shape];
+                return Err(ShapeError::new("Symbol", shapes, vec![symbol]).into());
+            }
+            return Ok(reg);
+        }
+        let reg = self.constant(shape, value);
+        self.symbols[reg.0] = vec![symbol];
+        (self.uniform[reg.0], self.constant[reg.0]) = (None, false);
+        self.inputs.push((symbol, reg));
+        Ok(reg)
+    }
+
+    /// Emits `instr`, or folds it if its operands are constants or identities such as zero and
+    /// one.
+    pub fn push(&mut self, instr: Instr) -> Result<Reg> {
+        let operands = instr.operands();
+        let symbols: Vec<_> = operands
+            .iter()
+            .flat_map(|r| self.symbols[r.0].iter().copied())
+            .collect();
+        let Some(shape) = instr.shape(&self.shapes) else {
+            let shapes = operands.iter().map(|r| self.shapes[r.0]).collect();
+            return Err(ShapeError::new(instr.name(), shapes, symbols).into());
+        };
+        if let Some(reg) = self.fold(instr, shape) {
+            return Ok(reg);
+        }
+        if let Some(&reg) = self.emitted.get(&instr) {
+            return Ok(reg);
+        }
+        let constant = operands.iter().all(|r| self.constant[r.0]);
+        let dst = self.register(shape, vec![T::zero(); len(shape)], symbols);
+        if constant {
+            let mut out = std::mem::take(&mut self.registers[dst.0]);
+            exec(instr, &mut out, &self.registers, &self.shapes);
+            self.registers[dst.0] = out;
+            self.uniform[dst.0] = uniform(&self.registers[dst.0]);
+        } else {
+            self.constant[dst.0] = false;
+            self.uniform[dst.0] = None;
+            self.code.push((instr, dst));
+            self.emitted.insert(instr, dst);
+            self.defs[dst.0] = Some(instr);
+        }
+        Ok(dst)
+    }
+
+    /// The result of `instr` as an existing or constant register, if an operand is an identity
+    /// or annihilator of the op, or if `instr` undoes the instruction of its operand.
+    fn fold(&mut self, instr: Instr, shape: Shape) -> Option<Reg> {
+        let (zero, one) = (T::zero(), T::one());
+        let is = |b: &Self, r: Reg, v: T| b.uniform[r.0] == Some(v);
+        // A register that can stand for the result, i.e. that has the same shape.
+        let same = |b: &Self, r: Reg| (b.shapes[r.0] == shape).then_some(r);
+        // Zeros without a shape, like `Zero`, stay without a shape.
+        let zeros = |b: &mut Self, l: Reg, r: Reg| {
+            let unknown = [l, r]
+                .iter()
+                .any(|&x| b.shapes[x.0] == Shape::Unknown && is(b, x, zero));
+            let shape = if unknown { Shape::Unknown } else { shape };
+            b.constant(shape, vec![zero; len(shape)])
+        };
+        match instr {
+            Instr::Add(l, r) if is(self, l, zero) => same(self, r),
+            Instr::Add(l, r) | Instr::Sub(l, r) if is(self, r, zero) => same(self, l),
+            Instr::ElemMul(l, r) | Instr::Mul(l, r) | Instr::Div(l, r)
+                if is(self, l, zero) || is(self, r, zero) && !matches!(instr, Instr::Div(..)) =>
+            {
+                Some(zeros(self, l, r))
+            }
+            Instr::ElemMul(l, r) if is(self, l, one) => same(self, r),
+            Instr::Mul(l, r) if is(self, l, one) && self.shapes[l.0] == Shape::Scalar => {
+                same(self, r)
+            }
+            Instr::ElemMul(l, r) | Instr::Div(l, r) if is(self, r, one) => same(self, l),
+            Instr::Mul(l, r) if is(self, r, one) && self.shapes[r.0] == Shape::Scalar => {
+                same(self, l)
+            }
+            Instr::Sub(l, r) if is(self, l, zero) && self.shapes[r.0] == shape => {
+                self.push(Instr::Neg(r)).ok()
+            }
+            Instr::Transpose(x) if len(self.shapes[x.0]) == 1 => Some(x),
+            Instr::Transpose(x) => match self.defs[x.0] {
+                Some(Instr::Transpose(y)) => Some(y),
+                _ => None,
+            },
+            Instr::Neg(x) => match self.defs[x.0] {
+                Some(Instr::Neg(y)) => Some(y),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// The value of every element, if they are all equal.
+fn uniform<T: Copy + PartialEq>(value: &[T]) -> Option<T> {
+    let first = *value.first()?;
+    value.iter().all(|&x| x == first).then_some(first)
+}
+
+/// The number of elements in a register of `shape`.
+pub(crate) fn len(shape: Shape) -> usize {
+    match shape {
+        Shape::Matrix(r, c) => r * c,
+        Shape::Tensor(dims) => dims.as_slice().iter().product(),
+        Shape::Scalar | Shape::Unknown => 1,
+    }
+}
+
+/// Writes the result of `instr` to `out`, which has the length of the result.
+fn exec<T: Real>(instr: Instr, out: &mut [T], registers: &[Vec<T>], shapes: &[Shape]) {
+    let reg = |r: Reg| registers[r.0].as_slice();
+    let zip = |out: &mut [T], l: Reg, r: Reg, f: fn(T, T) -> T| match (reg(l), reg(r)) {
+        (&[x], ys) if ys.len() != 1 || out.len() == 1 => {
+            out.iter_mut().zip(ys).for_each(|(o, &y)| *o = f(x, y))
+        }
+        (xs, &[y]) => out.iter_mut().zip(xs).for_each(|(o, &x)| *o = f(x, y)),
+        (xs, ys) => out
+            .iter_mut()
+            .zip(xs.iter().zip(ys))
+            .for_each(|(o, (&x, &y))| *o = f(x, y)),
+    };
+    match instr {
+        Instr::Add(l, r) => zip(out, l, r, |x, y| x + y),
+        Instr::Sub(l, r) => zip(out, l, r, |x, y| x - y),
+        Instr::Div(l, r) => zip(out, l, r, |x, y| x / y),
+        Instr::ElemMul(l, r) => zip(out, l, r, |x, y| x * y),
+        Instr::Mul(l, r) => match (shapes[l.0], shapes[r.0]) {
+            (Shape::Matrix(m, k), Shape::Matrix(_, n)) => {
+                let (l, r) = (
+                    DMatrixView::from_slice(reg(l), m, k),
+                    DMatrixView::from_slice(reg(r), k, n),
+                );
+                DMatrixViewMut::from_slice(out, m, n).gemm(T::one(), &l, &r, T::zero());
+            }
+            _ => zip(out, l, r, |x, y| x * y),
+        },
+        Instr::Neg(x) => out.iter_mut().zip(reg(x)).for_each(|(o, &x)| *o = -x),
+        Instr::Exp(x) => out.iter_mut().zip(reg(x)).for_each(|(o, &x)| *o = x.exp()),
+        Instr::Transpose(x) => match shapes[x.0] {
+            Shape::Matrix(r, c) => {
+                for (k, &x) in reg(x).iter().enumerate() {
+                    out[k / r + (k % r) * c] = x;
+                }
+            }
+            _ => out.copy_from_slice(reg(x)),
+        },
+        Instr::Sum(x) => out[0] = reg(x).iter().fold(T::zero(), |s, &x| s + x),
+    }
+}
+
+/// An expression and its gradients, compiled to instructions over preallocated registers.
+#[derive(Clone, Debug)]
+pub struct Compiled<T, const LEN: usize> {
+    pub(crate) registers: Vec<Vec<T>>,
+    pub(crate) shapes: Vec<Shape>,
+    pub(crate) code: Vec<(Instr, Reg)>,
+    pub(crate) inputs: Vec<(&'static str, Reg)>,
+    pub(crate) value: Reg,
+    pub(crate) gradients: [Reg; LEN],
+    /// The symbols of the gradients.
+    pub(crate) wrt: [String; LEN],
+}
+
+impl<T: Real, const LEN: usize> Compiled<T, LEN> {
+    /// Compiles `expr` and its derivatives with respect to `k`, like
+    /// [`derivative`](Differentiable::derivative). The registers start out with the current
+    /// values of the symbols.
+    ///
+    /// Fails with a shape error if a gradient doesn't have the shape of its symbol, e.g. for a
+    /// scalar that is broadcast against a matrix.
+    pub fn new<'a, N, D: Clone>(expr: &'a N, k: [&str; LEN], d: D) -> Result<Self>
+    where
+        N: Differentiable<'a> + Compile<T>,
+        N::Δ<D>: Compile<T>,
+    {
+        let wrt = k.map(str::to_string);
+        let gradients = expr.derivative(k, d);
+        let mut b = Builder::new();
+        let value = b.lower(expr)?;
+        let mut regs = [value; LEN];
+        for ((reg, gradient), symbol) in regs.iter_mut().zip(&gradients).zip(&wrt) {
+            *reg = b.lower(gradient)?;
+            // A scalar broadcast against a matrix gets a gradient of the shape of the matrix,
+            // which isn't summed back to a scalar.
+            let input = b.inputs.iter().find(|(s, _)| s == symbol);
+            if let Some(&(symbol, input)) = input {
+                let shapes = vec![b.shapes[input.0], b.shapes[reg.0]];
+                if shapes[1] != shapes[0] && shapes[1] != Shape::Unknown {
+                    return Err(ShapeError::new("Gradient", shapes, vec![symbol]).into());
+                }
+            }
+        }
+        Ok(Self {
+            registers: b.registers,
+            shapes: b.shapes,
+            code: b.code,
+            inputs: b.inputs,
+            value,
+            gradients: regs,
+            wrt,
+        })
+    }
+
+    /// The registers of the value and the gradients.
+    pub(crate) fn outputs(&self) -> impl Iterator<Item = Reg> {
+        std::iter::once(self.value).chain(self.gradients)
+    }
+
+    /// Whether each register is needed to compute the outputs.
+    pub(crate) fn live(&self) -> Vec<bool> {
+        let mut live = vec![false; self.registers.len()];
+        self.outputs().for_each(|r| live[r.0] = true);
+        for &(instr, dst) in self.code.iter().rev() {
+            if live[dst.0] {
+                instr.operands().into_iter().for_each(|r| live[r.0] = true);
+            }
+        }
+        live
+    }
+
+    /// The symbols that can be bound, with their shapes.
+    pub fn inputs(&self) -> impl Iterator<Item = (&'static str, Shape)> + '_ {
+        self.inputs.iter().map(|&(s, r)| (s, self.shapes[r.0]))
+    }
+
+    /// Binds `value`, a scalar or a column-major matrix, to `symbol` for the following runs.
+    ///
+    /// Panics if the expression doesn't contain `symbol`, or if `value` doesn't have its length.
+    pub fn bind(&mut self, symbol: &str, value: &[T]) {
+        let Some(&(_, reg)) = self.inputs.iter().find(|(s, _)| *s == symbol) else {
+            panic!("{symbol:?} is not a symbol of the compiled expression");
+        };
+        let shape = self.shapes[reg.0];
+        assert_eq!(value.len(), len(shape), "{symbol:?} has shape {shape}");
+        self.registers[reg.0].copy_from_slice(value);
+    }
+
+    /// Evaluates the instructions with the bound values.
+    pub fn run(&mut self) {
+        for &(instr, dst) in &self.code {
+            let mut out = std::mem::take(&mut self.registers[dst.0]);
+            exec(instr, &mut out, &self.registers, &self.shapes);
+            self.registers[dst.0] = out;
+        }
+    }
+
+    /// The value of the expression in the last run.
+    pub fn value(&self) -> &[T] {
+        &self.registers[self.value.0]
+    }
+
+    /// The gradients in the last run, in the order of the symbols they were compiled for.
+    pub fn gradients(&self) -> [&[T]; LEN] {
+        self.gradients.map(|r| self.registers[r.0].as_slice())
+    }
+
+    pub fn shape(&self) -> Shape {
+        self.shapes[self.value.0]
+    }
+
+    pub fn gradient_shapes(&self) -> [Shape; LEN] {
+        self.gradients.map(|r| self.shapes[r.0])
+    }
+}
+
+/// Values that a register can be initialized with.
+pub trait Value<T> {
+    /// The shape and the elements of the value, in column-major order.
+    fn elements(&self) -> (Shape, Vec<T>);
+}
+
+impl<T: Real> Value<T> for Atom {
+    fn elements(&self) -> (Shape, Vec<T>) {
+        // `Zero` multiplies to a zero matrix without a shape.
+        let shape = if *self == Zero {
+            Shape::Unknown
+        } else {
+            Shape::Scalar
+        };
+        (shape, vec![T::from(*self)])
+    }
+}
+
+impl<T: Real> Value<T> for NodeValue<T> {
+    fn elements(&self) -> (Shape, Vec<T>) {
+        (Shape::Scalar, vec![self.0])
+    }
+}
+
+impl<T: Real, R: Dim, C: Dim, B: Backend<T>> Value<T> for MatrixNode<T, R, C, B>
+where
+    DefaultAllocator: Allocator<T, R, C>,
+{
+    fn elements(&self) -> (Shape, Vec<T>) {
+        match self.shape() {
+            Some((r, c)) => (
+                Shape::Matrix(r, c),
+                (0..r * c).map(|k| self.get(k % r, k / r)).collect(),
+            ),
+            None => (Shape::Unknown, vec![T::zero()]),
+        }
+    }
+}
+
+impl<T: Real> Compile<T> for Atom {
+    fn compile<'a>(&'a self, b: &mut Builder<'a, T>) -> Result<Reg> {
+        let (shape, value) = self.elements();
+        Ok(b.constant(shape, value))
+    }
+}
+
+impl<T: Real, R: Dim, C: Dim, B: Backend<T>> Compile<T> for MatrixNode<T, R, C, B>
+where
+    DefaultAllocator: Allocator<T, R, C>,
+{
+    fn compile<'a>(&'a self, b: &mut Builder<'a, T>) -> Result<Reg> {
+        let (shape, value) = self.elements();
+        Ok(b.constant(shape, value))
+    }
+}
+
+impl Compile<f32> for f32 {
+    fn compile<'a>(&'a self, b: &mut Builder<'a, f32>) -> Result<Reg> {
+        Ok(b.constant(Shape::Scalar, vec![*self]))
+    }
+}
+
+impl Compile<f64> for f64 {
+    fn compile<'a>(&'a self, b: &mut Builder<'a, f64>) -> Result<Reg> {
+        Ok(b.constant(Shape::Scalar, vec![*self]))
+    }
+}
+
+impl<T: Real, N: Eval<T: Value<T>>> Compile<T> for Symbol<N> {
+    fn compile<'a>(&'a self, b: &mut Builder<'a, T>) -> Result<Reg> {
+        let (shape, value) = self.node.eval().elements();
+        b.input(self.symbols()[0], shape, value)
+    }
+}
+
+macro_rules! impl_binary {
+    ($($Op:ident)*) => {$(
+        impl<T: Real, L: Compile<T>, R: Compile<T>> Compile<T> for ops::$Op<L, R> {
+            fn compile<'a>(&'a self, b: &mut Builder<'a, T>) -> Result<Reg> {
+                let (l, r) = (b.lower(&self.0)?, b.lower(&self.1)?);
+                b.push(Instr::$Op(l, r))
+            }
+        }
+    )*};
+}
+
+impl_binary!(Add Sub Div ElemMul Mul);
+
+/// Unary nodes, as the instruction applied to the register of their operand. Real elements are
+/// their own conjugates.
+macro_rules! impl_unary {
+    ($($Op:ident => $f:expr;)*) => {$(
+        impl<T: Real, N: Compile<T>> Compile<T> for ops::$Op<N> {
+            fn compile<'a>(&'a self, b: &mut Builder<'a, T>) -> Result<Reg> {
+                let x = b.lower(&self.0)?;
+                $f(b, x)
+            }
+        }
+    )*};
+}
+
+impl_unary!(
+    Neg => |b: &mut Builder<T>, x| b.push(Instr::Neg(x));
+    Exp => |b: &mut Builder<T>, x| b.push(Instr::Exp(x));
+    Transpose => |b: &mut Builder<T>, x| b.push(Instr::Transpose(x));
+    Adjoint => |b: &mut Builder<T>, x| b.push(Instr::Transpose(x));
+    Sum => |b: &mut Builder<T>, x| b.push(Instr::Sum(x));
+    Conj => |_, x| Ok(x);
+    Detach => |_, x| Ok(x);
+);
+
+impl<T: Real, N: Compile<T>> Compile<T> for Node<N> {
+    fn compile<'a>(&'a self, b: &mut Builder<'a, T>) -> Result<Reg> {
+        b.lower(&self.0)
+    }
+}
+
+impl<T: Real, N: Compile<T> + ?Sized> Compile<T> for &N {
+    fn compile<'a>(&'a self, b: &mut Builder<'a, T>) -> Result<Reg> {
+        b.lower(*self)
+    }
+}
+
+impl<T: Real, N: Eval + Compile<T>> Compile<T> for Cached<N> {
+    fn compile<'a>(&'a self, b: &mut Builder<'a, T>) -> Result<Reg> {
+        b.lower(&self.0)
+    }
+}
+
+impl<T: Real, N: Eval + Compile<T>> Compile<T> for Checkpoint<N> {
+    fn compile<'a>(&'a self, b: &mut Builder<'a, T>) -> Result<Reg> {
+        b.lower(&self.0 .0)
+    }
+}
+
+#[test]
+fn matches_eval() {
+    use crate::prelude::*;
+    use crate::test::{assert_close, fixture as m};
+
+    let dy = mat(DMatrix::from_element(2, 1, 1.));
+    let expected = |x: &DMatrix<f64>, w1: &DMatrix<f64>| {
+        let x = mat(x.clone()).symbol("x");
+        let w1 = mat(w1.clone()).symbol("w1");
+        let w2 = mat(m(2, 4, 3.)).symbol("w2");
+        let h = Node(Exp(&w1 * &x)).cached();
+        let y = &w2 * ops::Sub(ops::ElemMul(&h, &h), &h);
+        let [dx, dw1, dw2] = y.derivative(["x", "w1", "w2"], &dy);
+        let dense = |v: MatrixNode<f64>| v.into_dense().unwrap().as_slice().to_vec();
+        (dense(y.eval()), [dx, dw1, dw2].map(|d| dense(d.eval())))
+    };
+
+    let x = mat(m(3, 1, 1.)).symbol("x");
+    let w1 = mat(m(4, 3, 2.)).symbol("w1");
+    let w2 = mat(m(2, 4, 3.)).symbol("w2");
+    let h = Node(Exp(&w1 * &x)).cached();
+    let y = &w2 * ops::Sub(ops::ElemMul(&h, &h), &h);
+    let mut program = Compiled::<f64, 3>::new(&y, ["x", "w1", "w2"], &dy).unwrap();
+
+    for step in 0..3 {
+        let (x, w1) = (m(3, 1, 1. + step as f64), m(4, 3, 2. - step as f64));
+        program.bind("x", x.as_slice());
+        program.bind("w1", w1.as_slice());
+        program.run();
+        let (value, gradients) = expected(&x, &w1);
+        assert_close(program.value(), &value);
+        for (g, e) in program.gradients().into_iter().zip(&gradients) {
+            assert_close(g, e);
+        }
+    }
+    assert_eq!(program.shape(), crate::shape::Shape::Matrix(2, 1));
+    // The gradients reuse the forward values.
+    let exps = program
+        .code
+        .iter()
+        .filter(|(i, _)| matches!(i, Instr::Exp(_)));
+    assert_eq!(exps.count(), 1);
+
+    let bad = &x * &w1;
+    let Err(Error::Shape(e)) = Compiled::<f64, 0>::new(&bad, [], One) else {
+        panic!("expected a shape error");
+    };
+    assert_eq!((e.op, e.symbols), ("Mul", vec!["x", "w1"]));
+
+    // The gradient of a scalar that is broadcast against a matrix isn't summed to a scalar.
+    let s = 2f64.symbol("s");
+    let scaled = Sum(ops::ElemMul(&x, Node(Exp(&s))));
+    let Err(Error::Shape(e)) = Compiled::<f64, 1>::new(&scaled, ["s"], One) else {
+        panic!("expected a shape error");
+    };
+    assert_eq!(e.shapes, [Shape::Scalar, Shape::Matrix(3, 1)]);
+    assert_eq!((e.op, e.symbols), ("Gradient", vec!["s"]));
+}
@@ -0,0 +1,435 @@
+//! User-defined operators with a hand-written backward pass.
+//!
+//! A [`CustomOp`] only works on evaluated [`MatrixNode`]s, so fused kernels or calls into other
+//! libraries can be used in an expression without a type-level derivative.
+
+use crate::error::{self, Error};
+use crate::mat::MatrixNode;
+use crate::ops::{zip_map, Add, Undifferentiable};
+use crate::shape::{self, Shape, ShapeError};
+use crate::{Differentiable, Eval, Node};
+use nalgebra::{allocator::Allocator, DefaultAllocator, Dyn};
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex, PoisonError};
+
+pub trait CustomOp {
+    type T: nalgebra::Scalar;
+
+    fn forward(&self, inputs: &[MatrixNode<Self::T>]) -> MatrixNode<Self::T>;
+
+    /// Returns the gradient for each of `inputs`, given the gradient `grad` of `output`.
+    fn backward(
+        &self,
+        inputs: &[MatrixNode<Self::T>],
+        output: &MatrixNode<Self::T>,
+        grad: MatrixNode<Self::T>,
+    ) -> Vec<MatrixNode<Self::T>>;
+
+    /// Shape of the output, or `None` if `inputs` have incompatible shapes.
+    fn infer_shape(&self, _inputs: &[Shape]) -> Option<Shape> {
+        Some(Shape::Unknown)
+    }
+}
+
+/// Tuples of nodes that can be passed to a [`CustomOp`].
+pub trait Inputs<T: nalgebra::Scalar>
+where
+    DefaultAllocator: Allocator<T, Dyn, Dyn>,
+{
+    fn try_eval(&self) -> error::Result<Vec<MatrixNode<T>>>;
+
+    fn forget(&self);
+
+    fn infer_shapes(&self) -> Result<Vec<Shape>, ShapeError>;
+
+    fn symbols(&self) -> Vec<&'static str>;
+}
+
+pub trait DifferentiableInputs<'a> {
+    type Δ<G>
+    where
+        Self: 'a;
+
+    /// `g(i)` is the gradient flowing into the `i`th input.
+    fn derivative<const LEN: usize, G: Clone>(
+        &'a self,
+        k: [&str; LEN],
+        g: impl Fn(usize) -> G,
+    ) -> [Self::Δ<G>; LEN];
+}
+
+#[derive(Clone, Debug)]
+pub struct Custom<Op, I>(pub Op, pub I);
+
+pub fn custom<Op: CustomOp, I: Inputs<Op::T>>(op: Op, inputs: I) -> Node<Custom<Op, I>> {
+    Node(Custom(op, inputs))
+}
+
+impl<Op: CustomOp, I: Inputs<Op::T>> Eval for Custom<Op, I> {
+    type T = MatrixNode<Op::T>;
+
+    fn try_eval(&self) -> error::Result<Self::T> {
+        let inputs = self.1.try_eval()?;
+        // The inputs are checked again, as their shapes can't always be inferred beforehand.
+        let shapes = inputs
+            .iter()
+            .map(Eval::infer_shape)
+            .collect::<Result<Vec<_>, _>>()?;
+        if self.0.infer_shape(&shapes).is_none() {
+            let op = std::any::type_name::<Op>();
+            return Err(ShapeError::new(op, shapes, self.1.symbols()).into());
+        }
+        Ok(self.0.forward(&inputs))
+    }
+
+    fn forget(&self) {
+        self.1.forget()
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        let shapes = self.1.infer_shapes()?;
+        self.0
+            .infer_shape(&shapes)
+            .ok_or_else(|| ShapeError::new(std::any::type_name::<Op>(), shapes, self.1.symbols()))
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        self.1.symbols()
+    }
+}
+
+impl<'a, Op: CustomOp + 'a, I: DifferentiableInputs<'a> + 'a> Differentiable<'a> for Custom<Op, I>
+where
+    Self: Eval,
+{
+    type Δ<D> = I::Δ<Grad<'a, Op, I, D>> where Self: 'a;
+
+    fn derivative<const LEN: usize, D: Clone>(
+        &'a self,
+        k: [&str; LEN],
+        d: D,
+    ) -> [Self::Δ<D>; LEN] {
+        let backward = Arc::new(Backward {
+            node: self,
+            d,
+            grads: Mutex::new(None),
+        });
+        self.1.derivative(k, |i| Grad {
+            backward: backward.clone(),
+            i,
+        })
+    }
+
+    fn is_zero(&self) -> bool {
+        false
+    }
+}
+
+/// The gradients of every input of a [`Custom`] node for the gradient `d` of its output, which are
+/// shared by the [`Grad`]s of the inputs.
+struct Backward<'a, Op: CustomOp, I, D> {
+    node: &'a Custom<Op, I>,
+    d: D,
+    grads: Mutex<Option<Vec<MatrixNode<Op::T>>>>,
+}
+
+/// The gradient of the `i`th input of a [`Custom`] node, computed by [`CustomOp::backward`].
+///
+/// The forward and backward passes run once for all the inputs, until the gradient is forgotten.
+/// The backward pass is opaque, so the derivative of this node fails to evaluate.
+pub struct Grad<'a, Op: CustomOp, I, D> {
+    backward: Arc<Backward<'a, Op, I, D>>,
+    i: usize,
+}
+
+impl<Op: CustomOp, I, D> Clone for Grad<'_, Op, I, D> {
+    fn clone(&self) -> Self {
+        Self {
+            backward: self.backward.clone(),
+            i: self.i,
+        }
+    }
+}
+
+impl<Op: CustomOp + Debug, I: Debug, D: Debug> Debug for Grad<'_, Op, I, D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Grad")
+            .field("node", &self.backward.node)
+            .field("d", &self.backward.d)
+            .field("i", &self.i)
+            .finish()
+    }
+}
+
+impl<'a, Op: CustomOp, I: Inputs<Op::T>, D: Eval<T = MatrixNode<Op::T>>> Eval
+    for Grad<'a, Op, I, D>
+{
+    type T = MatrixNode<Op::T>;
+
+    fn try_eval(&self) -> error::Result<Self::T> {
+        let Backward { node, d, grads } = &*self.backward;
+        // The lock is held during the backward pass, so that the gradients of the other inputs
+        // wait for it instead of running it again.
+        let mut grads = grads.lock().unwrap_or_else(PoisonError::into_inner);
+        let grads = match &mut *grads {
+            Some(grads) => grads,
+            None => {
+                let Custom(op, inputs) = node;
+                let output = node.try_eval()?;
+                let dv = d.try_eval()?;
+                shape::values("Grad", (*node, &output), (d, &dv), Shape::elementwise)?;
+                let inputs = inputs.try_eval()?;
+                let g = op.backward(&inputs, &output, dv);
+                if g.len() != inputs.len() {
+                    return Err(Error::Value(format!(
+                        "{} returned {} gradients for {} inputs",
+                        std::any::type_name::<Op>(),
+                        g.len(),
+                        inputs.len()
+                    )));
+                }
+                grads.insert(g)
+            }
+        };
+        Ok(grads[self.i].clone())
+    }
+
+    fn forget(&self) {
+        let Backward { node, d, grads } = &*self.backward;
+        grads.lock().unwrap_or_else(PoisonError::into_inner).take();
+        node.forget();
+        d.forget();
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        let Backward { node, d, .. } = &*self.backward;
+        shape::binary("Grad", *node, d, Shape::elementwise)?;
+        Ok(node.1.infer_shapes()?[self.i])
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        let mut symbols = self.backward.node.symbols();
+        symbols.extend(self.backward.d.symbols());
+        symbols
+    }
+}
+
+impl<'a, 'b, Op: CustomOp, I, D> Differentiable<'b> for Grad<'a, Op, I, D>
+where
+    Self: Eval,
+{
+    type Δ<G> = Undifferentiable where Self: 'b;
+
+    fn derivative<const LEN: usize, G>(&'b self, k: [&str; LEN], _: G) -> [Self::Δ<G>; LEN] {
+        Undifferentiable::of(std::any::type_name::<Op>(), self, k)
+    }
+
+    fn is_zero(&self) -> bool {
+        false
+    }
+}
+
+impl<T: nalgebra::Scalar, A: Eval<T = MatrixNode<T>>> Inputs<T> for (A,)
+where
+    DefaultAllocator: Allocator<T, Dyn, Dyn>,
+{
+    fn try_eval(&self) -> error::Result<Vec<MatrixNode<T>>> {
+        Ok(vec![self.0.try_eval()?])
+    }
+
+    fn forget(&self) {
+        self.0.forget()
+    }
+
+    fn infer_shapes(&self) -> Result<Vec<Shape>, ShapeError> {
+        Ok(vec![self.0.infer_shape()?])
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        self.0.symbols()
+    }
+}
+
+impl<T: nalgebra::Scalar, A: Eval<T = MatrixNode<T>>, B: Eval<T = MatrixNode<T>>> Inputs<T>
+    for (A, B)
+where
+    DefaultAllocator: Allocator<T, Dyn, Dyn>,
+{
+    fn try_eval(&self) -> error::Result<Vec<MatrixNode<T>>> {
+        Ok(vec![self.0.try_eval()?, self.1.try_eval()?])
+    }
+
+    fn forget(&self) {
+        self.0.forget();
+        self.1.forget();
+    }
+
+    fn infer_shapes(&self) -> Result<Vec<Shape>, ShapeError> {
+        Ok(vec![self.0.infer_shape()?, self.1.infer_shape()?])
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        [self.0.symbols(), self.1.symbols()].concat()
+    }
+}
+
+impl<
+        T: nalgebra::Scalar,
+        A: Eval<T = MatrixNode<T>>,
+        B: Eval<T = MatrixNode<T>>,
+        C: Eval<T = MatrixNode<T>>,
+    > Inputs<T> for (A, B, C)
+where
+    DefaultAllocator: Allocator<T, Dyn, Dyn>,
+{
+    fn try_eval(&self) -> error::Result<Vec<MatrixNode<T>>> {
+        Ok(vec![
+            self.0.try_eval()?,
+            self.1.try_eval()?,
+            self.2.try_eval()?,
+        ])
+    }
+
+    fn forget(&self) {
+        self.0.forget();
+        self.1.forget();
+        self.2.forget();
+    }
+
+    fn infer_shapes(&self) -> Result<Vec<Shape>, ShapeError> {
+        Ok(vec![
+            self.0.infer_shape()?,
+            self.1.infer_shape()?,
+            self.2.infer_shape()?,
+        ])
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        [self.0.symbols(), self.1.symbols(), self.2.symbols()].concat()
+    }
+}
+
+impl<'a, A: Differentiable<'a>> DifferentiableInputs<'a> for (A,) {
+    type Δ<G> = A::Δ<G> where Self: 'a;
+
+    fn derivative<const LEN: usize, G: Clone>(
+        &'a self,
+        k: [&str; LEN],
+        g: impl Fn(usize) -> G,
+    ) -> [Self::Δ<G>; LEN] {
+        self.0.derivative(k, g(0))
+    }
+}
+
+impl<'a, A: Differentiable<'a>, B: Differentiable<'a>> DifferentiableInputs<'a> for (A, B) {
+    type Δ<G> = Add<A::Δ<G>, B::Δ<G>> where Self: 'a;
+
+    fn derivative<const LEN: usize, G: Clone>(
+        &'a self,
+        k: [&str; LEN],
+        g: impl Fn(usize) -> G,
+    ) -> [Self::Δ<G>; LEN] {
+        zip_map(self.0.derivative(k, g(0)), self.1.derivative(k, g(1)), Add)
+    }
+}
+
+impl<'a, A: Differentiable<'a>, B: Differentiable<'a>, C: Differentiable<'a>>
+    DifferentiableInputs<'a> for (A, B, C)
+{
+    type Δ<G> = Add<Add<A::Δ<G>, B::Δ<G>>, C::Δ<G>> where Self: 'a;
+
+    fn derivative<const LEN: usize, G: Clone>(
+        &'a self,
+        k: [&str; LEN],
+        g: impl Fn(usize) -> G,
+    ) -> [Self::Δ<G>; LEN] {
+        zip_map(
+            zip_map(self.0.derivative(k, g(0)), self.1.derivative(k, g(1)), Add),
+            self.2.derivative(k, g(2)),
+            Add,
+        )
+    }
+}
+
+#[test]
+fn affine() {
+    use crate::prelude::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// `w * x + b` as a single op, which counts its backward passes.
+    struct Affine(AtomicUsize);
+
+    impl CustomOp for Affine {
+        type T = f32;
+
+        fn forward(&self, inputs: &[MatrixNode<f32>]) -> MatrixNode<f32> {
+            let [w, x, b] = inputs else { unreachable!() };
+            w.clone() * x.clone() + b.clone()
+        }
+
+        fn backward(
+            &self,
+            inputs: &[MatrixNode<f32>],
+            _: &MatrixNode<f32>,
+            grad: MatrixNode<f32>,
+        ) -> Vec<MatrixNode<f32>> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            let [w, x, _] = inputs else { unreachable!() };
+            let dense = |m: &MatrixNode<f32>| m.clone().into_dense().unwrap();
+            let (w, x, g) = (dense(w), dense(x), dense(&grad));
+            vec![mat(&g * x.transpose()), mat(w.transpose() * &g), mat(g)]
+        }
+    }
+
+    let x = mat(DMatrix::<f32>::new_random(3, 1)).symbol("x");
+    let w = mat(DMatrix::<f32>::new_random(2, 3)).symbol("w");
+    let b = mat(DMatrix::<f32>::new_random(2, 1)).symbol("b");
+    let dl = mat(DMatrix::<f32>::new_random(2, 1));
+
+    let fused = custom(Affine(AtomicUsize::new(0)), (&w, &x, &b));
+    let reference = &w * &x + &b;
+
+    assert_eq!(fused.eval(), reference.eval());
+
+    let [dw, dx, db] = fused.derivative(["w", "x", "b"], &dl);
+    let [dw_ref, dx_ref, db_ref] = reference.derivative(["w", "x", "b"], &dl);
+
+    assert_eq!(dw.eval(), dw_ref.eval());
+    assert_eq!(dx.eval(), dx_ref.eval());
+    assert_eq!(db.eval(), db_ref.eval());
+    assert_eq!(fused.0 .0 .0.load(Ordering::Relaxed), 1);
+
+    dw.forget();
+    dw.eval();
+    assert_eq!(fused.0 .0 .0.load(Ordering::Relaxed), 2);
+
+    // The backward pass can't be differentiated again.
+    let [ddw, ddy] = Sum(&dx).derivative(["w", "y"], One);
+    assert!(matches!(ddw.try_eval(), Err(Error::Value(_))));
+    assert_eq!(ddy.try_eval(), Ok(Zero));
+
+    /// An op whose backward pass forgets the gradient of its input.
+    struct Forgetful;
+
+    impl CustomOp for Forgetful {
+        type T = f32;
+
+        fn forward(&self, inputs: &[MatrixNode<f32>]) -> MatrixNode<f32> {
+            inputs[0].clone()
+        }
+
+        fn backward(
+            &self,
+            _: &[MatrixNode<f32>],
+            _: &MatrixNode<f32>,
+            _: MatrixNode<f32>,
+        ) -> Vec<MatrixNode<f32>> {
+            vec![]
+        }
+    }
+
+    let y = custom(Forgetful, (&x,));
+    let [dx] = y.derivative(["x"], &x);
+    assert!(matches!(dx.try_eval(), Err(Error::Value(e)) if e.contains("Forgetful")));
+}
@@ -0,0 +1,456 @@
+//! Matrix decompositions, whose factors are separate nodes.
+//!
+//! Each factor evaluates the decomposition of its input, so an input that is expensive to
+//! evaluate should be [`cached`](crate::Differentiable::cached). The gradients of the factors
+//! are computed independently and added up, which the adjoint formulas allow since they are
+//! linear in the gradients of the factors.
+
+use crate::error;
+use crate::indexing::{check_grad, IntoMatrix};
+use crate::linalg::{dense_input, inverse, Real};
+use crate::mat::{mat, MatrixNode};
+use crate::ops::Undifferentiable;
+use crate::shape::{Shape, ShapeError};
+use crate::value::Atom;
+use crate::{Differentiable, Eval};
+use nalgebra::{DMatrix, DVector, Dyn};
+
+/// A factor of a decomposition of a `m x n` matrix `A`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Factor {
+    /// The `m x k` left singular vectors of the thin SVD, where `k = min(m, n)`.
+    U,
+    /// The `k x 1` singular values, in descending order.
+    SingularValues,
+    /// The `n x k` right singular vectors, with `A = U diag(S) V^T`.
+    V,
+    /// The `n x 1` eigenvalues of a symmetric `A`, in no particular order.
+    EigenValues,
+    /// The `n x n` eigenvectors, as columns.
+    EigenVectors,
+    /// The `m x n` orthonormal columns of the thin QR decomposition, for `m >= n`.
+    Q,
+    /// The `n x n` upper triangular factor, with `A = Q R`.
+    R,
+}
+
+#[derive(Clone, Copy)]
+enum Decomposition {
+    Svd,
+    SymmetricEigen,
+    Qr,
+}
+
+impl Factor {
+    /// The decomposition the factor is part of, and its position among the factors.
+    fn position(self) -> (Decomposition, usize) {
+        match self {
+            Factor::U => (Decomposition::Svd, 0),
+            Factor::SingularValues => (Decomposition::Svd, 1),
+            Factor::V => (Decomposition::Svd, 2),
+            Factor::EigenValues => (Decomposition::SymmetricEigen, 0),
+            Factor::EigenVectors => (Decomposition::SymmetricEigen, 1),
+            Factor::Q => (Decomposition::Qr, 0),
+            Factor::R => (Decomposition::Qr, 1),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self.position().0 {
+            Decomposition::Svd => "Svd",
+            Decomposition::SymmetricEigen => "SymmetricEigen",
+            Decomposition::Qr => "Qr",
+        }
+    }
+
+    /// The shape of the factor of a `m x n` matrix, or `None` if it can't be decomposed.
+    fn output_shape(self, (m, n): (usize, usize)) -> Option<(usize, usize)> {
+        let k = m.min(n);
+        match self {
+            Factor::U => Some((m, k)),
+            Factor::SingularValues => Some((k, 1)),
+            Factor::V => Some((n, k)),
+            Factor::EigenValues => (m == n).then_some((n, 1)),
+            Factor::EigenVectors => (m == n).then_some((n, n)),
+            Factor::Q => (m >= n).then_some((m, n)),
+            Factor::R => (m >= n).then_some((n, n)),
+        }
+    }
+
+    fn infer_shape(self, n: &impl Eval) -> Result<Shape, ShapeError> {
+        match n.infer_shape()? {
+            Shape::Unknown => Ok(Shape::Unknown),
+            Shape::Matrix(r, c) => {
+                if let Some((r, c)) = self.output_shape((r, c)) {
+                    return Ok(Shape::Matrix(r, c));
+                }
+                Err(ShapeError::new(
+                    self.name(),
+                    vec![Shape::Matrix(r, c)],
+                    n.symbols(),
+                ))
+            }
+            s => Err(ShapeError::new(self.name(), vec![s], n.symbols())),
+        }
+    }
+
+    /// All factors of the decomposition the factor is part of, for the value `a` of `n`.
+    fn factors<T: Real>(self, n: &impl Eval, a: DMatrix<T>) -> error::Result<Vec<DMatrix<T>>> {
+        if self.output_shape(a.shape()).is_none() {
+            let s = Shape::Matrix(a.nrows(), a.ncols());
+            return Err(ShapeError::new(self.name(), vec![s], n.symbols()).into());
+        }
+        Ok(match self.position().0 {
+            Decomposition::Svd => {
+                let svd = a.svd(true, true);
+                let v = svd.v_t.unwrap().transpose();
+                vec![svd.u.unwrap(), column(svd.singular_values), v]
+            }
+            Decomposition::SymmetricEigen => {
+                let eigen = a.symmetric_eigen();
+                vec![column(eigen.eigenvalues), eigen.eigenvectors]
+            }
+            Decomposition::Qr => {
+                let qr = a.qr();
+                vec![qr.q(), qr.r()]
+            }
+        })
+    }
+
+    /// The gradient of the input `a`, the value of `n`, given the gradient `d` of the factor.
+    fn backward<T: Real>(
+        self,
+        n: &impl Eval,
+        a: MatrixNode<T>,
+        d: impl IntoMatrix<T>,
+        symbols: impl FnOnce() -> Vec<&'static str>,
+    ) -> error::Result<MatrixNode<T>> {
+        let a = dense_input(self.name(), a)?;
+        let (rows, cols) = a.shape();
+        let f = self.factors(n, a)?;
+        let (decomposition, i) = self.position();
+        let d = d.into_matrix(f[i].nrows(), f[i].ncols());
+        check_grad("FactorGrad", &d, f[i].shape(), symbols)?;
+        let Some(d) = d.into_dense() else {
+            return Ok(MatrixNode::zeros(rows, cols));
+        };
+        let mut g: Vec<_> = f
+            .iter()
+            .map(|f| DMatrix::zeros(f.nrows(), f.ncols()))
+            .collect();
+        g[i] = d;
+        Ok(mat(match decomposition {
+            Decomposition::Svd => svd_backward(&f[0], &f[1].column(0).into(), &f[2], &g),
+            Decomposition::SymmetricEigen => eigen_backward(&f[0].column(0).into(), &f[1], &g),
+            Decomposition::Qr => qr_backward(&f[0], &f[1], &g)?,
+        }))
+    }
+}
+
+fn column<T: Real>(v: DVector<T>) -> DMatrix<T> {
+    let n = v.len();
+    v.reshape_generic(Dyn(n), Dyn(1))
+}
+
+fn half<T: Real>() -> T {
+    T::from(Atom::ratio(1, 2).unwrap())
+}
+
+/// `F_ij = 1 / (x_j - x_i)`, or zero where the values are degenerate. This drops the terms
+/// that rotate within a degenerate subspace, which is exact for losses that don't depend on the
+/// choice of basis of that subspace.
+fn inverse_gaps<T: Real>(x: &DVector<T>) -> DMatrix<T> {
+    let tol = T::from(Atom::ratio(1, 1 << 20).unwrap());
+    DMatrix::from_fn(x.len(), x.len(), |i, j| {
+        let gap = x[j] - x[i];
+        if gap.abs() > tol * (x[i].abs() + x[j].abs()) {
+            T::one() / gap
+        } else {
+            T::zero()
+        }
+    })
+}
+
+/// `Ā = U (F ∘ skew(U^T Ū) S + diag(S̄) + S F ∘ skew(V^T V̄)) V^T
+///    + (I - U U^T) Ū S^-1 V^T + U S^-1 V̄^T (I - V V^T)`,
+/// where `F_ij = 1 / (s_j^2 - s_i^2)` and `skew(X) = X - X^T`.
+fn svd_backward<T: Real>(
+    u: &DMatrix<T>,
+    s: &DVector<T>,
+    v: &DMatrix<T>,
+    g: &[DMatrix<T>],
+) -> DMatrix<T> {
+    let (gu, gs, gv) = (&g[0], &g[1], &g[2]);
+    let f = inverse_gaps(&s.component_mul(s));
+    let j = f.component_mul(&(u.transpose() * gu - gu.transpose() * u));
+    let k = f.component_mul(&(v.transpose() * gv - gv.transpose() * v));
+    let s_mat = DMatrix::from_diagonal(s);
+    let inner = j * &s_mat + DMatrix::from_diagonal(&gs.column(0)) + &s_mat * k;
+    let mut grad = u * inner * v.transpose();
+
+    // Singular vectors of zero singular values are arbitrary, and contribute nothing.
+    let tol = T::from(Atom::ratio(1, 1 << 20).unwrap()) * s.max();
+    let s_inv = DMatrix::from_diagonal(&s.map(|s| if s > tol { T::one() / s } else { T::zero() }));
+    let (m, n) = (u.nrows(), v.nrows());
+    if m > s.len() {
+        grad += (DMatrix::identity(m, m) - u * u.transpose()) * gu * &s_inv * v.transpose();
+    }
+    if n > s.len() {
+        grad += u * s_inv * gv.transpose() * (DMatrix::identity(n, n) - v * v.transpose());
+    }
+    grad
+}
+
+/// `Ā = V (diag(λ̄) + F ∘ V^T V̄) V^T`, where `F_ij = 1 / (λ_j - λ_i)`, made symmetric since
+/// only the lower triangle is read.
+fn eigen_backward<T: Real>(
+    values: &DVector<T>,
+    vectors: &DMatrix<T>,
+    g: &[DMatrix<T>],
+) -> DMatrix<T> {
+    let (gl, gv) = (&g[0], &g[1]);
+    let inner = DMatrix::from_diagonal(&gl.column(0))
+        + inverse_gaps(values).component_mul(&(vectors.transpose() * gv));
+    let grad = vectors * inner * vectors.transpose();
+    (&grad + grad.transpose()) * half::<T>()
+}
+
+/// `Ā = (Q̄ + Q copyltu(M)) R^-T`, where `M = R R̄^T - Q̄^T Q` and `copyltu` mirrors the lower
+/// triangle into the upper one.
+fn qr_backward<T: Real>(
+    q: &DMatrix<T>,
+    r: &DMatrix<T>,
+    g: &[DMatrix<T>],
+) -> error::Result<DMatrix<T>> {
+    let (gq, gr) = (&g[0], &g[1]);
+    let m = r * gr.transpose() - gq.transpose() * q;
+    let lower = m.lower_triangle();
+    let copyltu = &lower + lower.transpose() - DMatrix::from_diagonal(&m.diagonal());
+    Ok((gq + q * copyltu) * inverse(r.clone())?.transpose())
+}
+
+/// A factor of a decomposition of a matrix.
+#[derive(Clone, Debug)]
+pub struct Decomposed<N>(pub N, pub Factor);
+
+/// The thin singular value decomposition `A = U diag(S) V^T`, as the nodes `(U, S, V)`.
+pub fn svd<N: Clone>(n: N) -> (Decomposed<N>, Decomposed<N>, Decomposed<N>) {
+    (
+        Decomposed(n.clone(), Factor::U),
+        Decomposed(n.clone(), Factor::SingularValues),
+        Decomposed(n, Factor::V),
+    )
+}
+
+/// The eigendecomposition of a symmetric matrix, as the nodes `(eigenvalues, eigenvectors)`.
+/// Only the lower triangle of the matrix is read, and its gradient is symmetric.
+pub fn symmetric_eigen<N: Clone>(n: N) -> (Decomposed<N>, Decomposed<N>) {
+    (
+        Decomposed(n.clone(), Factor::EigenValues),
+        Decomposed(n, Factor::EigenVectors),
+    )
+}
+
+/// The thin QR decomposition `A = Q R` of a matrix with at least as many rows as columns, as
+/// the nodes `(Q, R)`.
+pub fn qr<N: Clone>(n: N) -> (Decomposed<N>, Decomposed<N>) {
+    (Decomposed(n.clone(), Factor::Q), Decomposed(n, Factor::R))
+}
+
+impl<T: Real, N: Eval<T = MatrixNode<T>>> Eval for Decomposed<N> {
+    type T = MatrixNode<T>;
+
+    fn try_eval(&self) -> error::Result<Self::T> {
+        let a = dense_input(self.1.name(), self.0.try_eval()?)?;
+        let mut factors = self.1.factors(&self.0, a)?;
+        Ok(mat(factors.swap_remove(self.1.position().1)))
+    }
+
+    fn forget(&self) {
+        self.0.forget()
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        self.1.infer_shape(&self.0)
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        self.0.symbols()
+    }
+}
+
+impl<'a, N: Differentiable<'a>> Differentiable<'a> for Decomposed<N>
+where
+    Self: Eval,
+{
+    type Δ<D> = N::Δ<FactorGrad<'a, N, D>> where Self: 'a;
+
+    fn derivative<const LEN: usize, D: Clone>(
+        &'a self,
+        k: [&str; LEN],
+        d: D,
+    ) -> [Self::Δ<D>; LEN] {
+        self.0.derivative(k, FactorGrad(d, &self.0, self.1))
+    }
+
+    fn is_zero(&self) -> bool {
+        false
+    }
+}
+
+/// The gradient of the input of a [`Decomposed`] factor. It can't be differentiated again.
+#[derive(Debug)]
+pub struct FactorGrad<'a, N, D>(pub D, pub &'a N, pub Factor);
+
+impl<N, D: Clone> Clone for FactorGrad<'_, N, D> {
+    fn clone(&self) -> Self {
+        FactorGrad(self.0.clone(), self.1, self.2)
+    }
+}
+
+impl<T: Real, N: Eval<T = MatrixNode<T>>, D: Eval> Eval for FactorGrad<'_, N, D>
+where
+    D::T: IntoMatrix<T>,
+{
+    type T = MatrixNode<T>;
+
+    fn try_eval(&self) -> error::Result<Self::T> {
+        let (a, d) = (self.1.try_eval()?, self.0.try_eval()?);
+        self.2.backward(self.1, a, d, || self.symbols())
+    }
+
+    fn forget(&self) {
+        self.0.forget();
+        self.1.forget();
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        let (d, out) = (self.0.infer_shape()?, self.2.infer_shape(self.1)?);
+        match d.elementwise(out) {
+            Some(_) => self.1.infer_shape(),
+            None => Err(ShapeError::new("FactorGrad", vec![d, out], self.symbols())),
+        }
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        [self.0.symbols(), self.1.symbols()].concat()
+    }
+}
+
+impl<'b, N, D> Differentiable<'b> for FactorGrad<'_, N, D>
+where
+    Self: Eval,
+{
+    type Δ<G> = Undifferentiable where Self: 'b;
+
+    fn derivative<const LEN: usize, G>(&'b self, k: [&str; LEN], _: G) -> [Self::Δ<G>; LEN] {
+        Undifferentiable::of(self.2.name(), self, k)
+    }
+
+    fn is_zero(&self) -> bool {
+        false
+    }
+}
+
+#[test]
+fn decompositions() {
+    use crate::linalg::check_gradient;
+    use crate::{ops, prelude::*};
+
+    let a = DMatrix::<f64>::from_row_slice(
+        4,
+        3,
+        &[4., 1., 0.5, 1., 3., 0.2, 0.5, 0.2, 2., -1., 0.7, 1.5],
+    );
+    let sym = DMatrix::<f64>::from_row_slice(3, 3, &[4., 1., 0.5, 1., 3., 0.2, 0.5, 0.2, 2.]);
+
+    let (u, s, v) = svd(mat(a.clone()));
+    let (u, s, v) = [u, s, v].map(|f| f.eval().into_dense().unwrap()).into();
+    assert!(
+        (u * DMatrix::from_diagonal(&s.column(0)) * v.transpose() - &a)
+            .abs()
+            .max()
+            < 1e-12
+    );
+    let (q, r) = qr(mat(a.clone()));
+    let (q, r) = (
+        q.eval().into_dense().unwrap(),
+        r.eval().into_dense().unwrap(),
+    );
+    assert!((q * r - &a).abs().max() < 1e-12);
+
+    // Losses of squared singular vectors, which don't depend on their signs.
+    let w = |r, c| mat(DMatrix::from_fn(r, c, |i, j| (i + 2 * j) as f64 / 3. - 1.));
+    macro_rules! grad {
+        ($a:ident => $y:expr) => {
+            |m: DMatrix<f64>| {
+                let $a = mat(m).symbol("a");
+                let y = $y;
+                let [da] = y.derivative(["a"], One);
+                (y.eval().0, da.eval().into_dense().unwrap())
+            }
+        };
+    }
+
+    for m in [a.clone(), a.transpose()] {
+        let (wu, ws, wv) = (w(m.nrows(), 3), w(3, 1), w(m.ncols(), 3));
+        check_gradient(
+            &m,
+            false,
+            grad!(a => {
+                let (u, s, v) = svd(&a);
+                ops::Add(
+                    Sum(ops::ElemMul(ops::ElemMul(u.clone(), u), &wu)),
+                    ops::Add(Sum(ops::ElemMul(ops::ElemMul(v.clone(), v), &wv)), Sum(ops::ElemMul(s, &ws))),
+                )
+            }),
+        );
+    }
+    let (w31, w33, w43) = (w(3, 1), w(3, 3), w(4, 3));
+    check_gradient(
+        &sym,
+        true,
+        grad!(a => {
+            let (values, vectors) = symmetric_eigen(&a);
+            ops::Add(
+                Sum(ops::ElemMul(values, &w31)),
+                Sum(ops::ElemMul(ops::ElemMul(vectors.clone(), vectors), &w33)),
+            )
+        }),
+    );
+    check_gradient(
+        &a,
+        false,
+        grad!(a => {
+            let (q, r) = qr(&a);
+            ops::Add(Sum(ops::ElemMul(q, &w43)), Sum(ops::ElemMul(r, &w33)))
+        }),
+    );
+
+    // With a repeated eigenvalue, the gradient of a loss that doesn't depend on the basis of
+    // its eigenspace is still finite.
+    let degenerate = mat(DMatrix::<f64>::from_diagonal(&nalgebra::dvector![
+        2., 2., 3.
+    ]))
+    .symbol("a");
+    let y = Sum(symmetric_eigen(&degenerate).0);
+    let [da] = y.derivative(["a"], One);
+    assert!(
+        (da.eval().into_dense().unwrap() - DMatrix::identity(3, 3))
+            .abs()
+            .max()
+            < 1e-12
+    );
+
+    // The gradients of the factors can't be differentiated again.
+    let [dda, ddb] = Sum(&da).derivative(["a", "b"], One);
+    assert!(matches!(dda.try_eval(), Err(crate::error::Error::Value(_))));
+    assert_eq!(ddb.try_eval(), Ok(Zero));
+
+    let err = qr(mat(a.transpose()).symbol("b"))
+        .0
+        .infer_shape()
+        .unwrap_err();
+    assert_eq!((err.op, err.symbols), ("Qr", vec!["b"]));
+    assert_eq!(svd(mat(a.clone())).2.infer_shape(), Ok(Shape::Matrix(3, 3)));
+}
@@ -0,0 +1,317 @@
+//! Dual numbers for forward-mode differentiation of plain scalar functions.
+//!
+//! Evaluating a function with `Dual::variable(x)` as input yields `f(x)` in `re` and `f'(x)` in
+//! `eps`. `HyperDual` carries two infinitesimal parts, which gives the exact second derivative in
+//! `e12`.
+
+use crate::primitive_ops::{Conj, ElemMul, Exp};
+use crate::shape::{Shape, ShapeError};
+use crate::value::{Atom, NodeValue, One, Scalar, Zero};
+use crate::{error, Differentiable, Eval};
+use std::ops::*;
+
+/// `re + eps * ε`, where `ε² = 0`.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct Dual<T> {
+    pub re: T,
+    pub eps: T,
+}
+
+impl<T> Dual<T> {
+    pub fn new(re: T, eps: T) -> Self {
+        Self { re, eps }
+    }
+}
+
+impl<T: From<Atom>> Dual<T> {
+    /// A value that is constant with respect to the differentiation variable.
+    pub fn constant(re: T) -> Self {
+        Self::new(re, T::from(Zero))
+    }
+
+    /// The differentiation variable itself.
+    pub fn variable(re: T) -> Self {
+        Self::new(re, T::from(One))
+    }
+}
+
+impl<T: Add<Output = T>> Add for Dual<T> {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self::new(self.re + rhs.re, self.eps + rhs.eps)
+    }
+}
+
+impl<T: Sub<Output = T>> Sub for Dual<T> {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Self::new(self.re - rhs.re, self.eps - rhs.eps)
+    }
+}
+
+impl<T: Copy + Add<Output = T> + Mul<Output = T>> Mul for Dual<T> {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        Self::new(self.re * rhs.re, self.re * rhs.eps + self.eps * rhs.re)
+    }
+}
+
+impl<T: Copy + Sub<Output = T> + Mul<Output = T> + Div<Output = T>> Div for Dual<T> {
+    type Output = Self;
+
+    // (a + bε) / (c + dε) = a/c + (bc - ad)/c² ε
+    fn div(self, rhs: Self) -> Self::Output {
+        Self::new(
+            self.re / rhs.re,
+            (self.eps * rhs.re - self.re * rhs.eps) / (rhs.re * rhs.re),
+        )
+    }
+}
+
+impl<T: Neg<Output = T>> Neg for Dual<T> {
+    type Output = Self;
+
+    fn neg(self) -> Self::Output {
+        Self::new(-self.re, -self.eps)
+    }
+}
+
+impl<T: Conj<Output = T>> Conj for Dual<T> {
+    type Output = Self;
+
+    fn conj(self) -> Self::Output {
+        Self::new(self.re.conj(), self.eps.conj())
+    }
+}
+
+impl<T: Copy + Exp<Output = T> + Mul<Output = T>> Exp for Dual<T> {
+    type Output = Self;
+
+    fn exp(self) -> Self::Output {
+        let re = self.re.exp();
+        Self::new(re, self.eps * re)
+    }
+}
+
+/// `re + e1 * ε₁ + e2 * ε₂ + e12 * ε₁ε₂`, where `ε₁² = ε₂² = 0`.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct HyperDual<T> {
+    pub re: T,
+    pub e1: T,
+    pub e2: T,
+    pub e12: T,
+}
+
+impl<T> HyperDual<T> {
+    pub fn new(re: T, e1: T, e2: T, e12: T) -> Self {
+        Self { re, e1, e2, e12 }
+    }
+}
+
+impl<T: From<Atom>> HyperDual<T> {
+    pub fn constant(re: T) -> Self {
+        Self::new(re, T::from(Zero), T::from(Zero), T::from(Zero))
+    }
+
+    pub fn variable(re: T) -> Self {
+        Self::new(re, T::from(One), T::from(One), T::from(Zero))
+    }
+}
+
+impl<T: Add<Output = T>> Add for HyperDual<T> {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self::new(
+            self.re + rhs.re,
+            self.e1 + rhs.e1,
+            self.e2 + rhs.e2,
+            self.e12 + rhs.e12,
+        )
+    }
+}
+
+impl<T: Sub<Output = T>> Sub for HyperDual<T> {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Self::new(
+            self.re - rhs.re,
+            self.e1 - rhs.e1,
+            self.e2 - rhs.e2,
+            self.e12 - rhs.e12,
+        )
+    }
+}
+
+impl<T: Copy + Add<Output = T> + Mul<Output = T>> Mul for HyperDual<T> {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        Self::new(
+            self.re * rhs.re,
+            self.re * rhs.e1 + self.e1 * rhs.re,
+            self.re * rhs.e2 + self.e2 * rhs.re,
+            self.re * rhs.e12 + self.e1 * rhs.e2 + self.e2 * rhs.e1 + self.e12 * rhs.re,
+        )
+    }
+}
+
+impl<T> Div for HyperDual<T>
+where
+    T: Copy
+        + From<Atom>
+        + Add<Output = T>
+        + Sub<Output = T>
+        + Mul<Output = T>
+        + Div<Output = T>
+        + Neg<Output = T>,
+{
+    type Output = Self;
+
+    // x / y = x * (1/y), with 1/y expanded as f(y) for f(u) = 1/u.
+    fn div(self, rhs: Self) -> Self::Output {
+        let inv = T::from(One) / rhs.re;
+        let d1 = -(inv * inv);
+        let d2 = (T::from(One) + T::from(One)) * inv * inv * inv;
+        self * Self::new(
+            inv,
+            d1 * rhs.e1,
+            d1 * rhs.e2,
+            d1 * rhs.e12 + d2 * rhs.e1 * rhs.e2,
+        )
+    }
+}
+
+impl<T: Neg<Output = T>> Neg for HyperDual<T> {
+    type Output = Self;
+
+    fn neg(self) -> Self::Output {
+        Self::new(-self.re, -self.e1, -self.e2, -self.e12)
+    }
+}
+
+impl<T: Conj<Output = T>> Conj for HyperDual<T> {
+    type Output = Self;
+
+    fn conj(self) -> Self::Output {
+        Self::new(
+            self.re.conj(),
+            self.e1.conj(),
+            self.e2.conj(),
+            self.e12.conj(),
+        )
+    }
+}
+
+impl<T: Copy + Exp<Output = T> + Add<Output = T> + Mul<Output = T>> Exp for HyperDual<T> {
+    type Output = Self;
+
+    fn exp(self) -> Self::Output {
+        let re = self.re.exp();
+        Self::new(
+            re,
+            self.e1 * re,
+            self.e2 * re,
+            (self.e12 + self.e1 * self.e2) * re,
+        )
+    }
+}
+
+/// Derivative of `f` at `x`, computed by evaluating `f` once with a dual input.
+pub fn derivative<T: From<Atom>>(f: impl Fn(Dual<T>) -> Dual<T>, x: T) -> T {
+    f(Dual::variable(x)).eps
+}
+
+/// Second derivative of `f` at `x`, computed by evaluating `f` once with a hyper-dual input.
+pub fn second_derivative<T: From<Atom>>(f: impl Fn(HyperDual<T>) -> HyperDual<T>, x: T) -> T {
+    f(HyperDual::variable(x)).e12
+}
+
+macro_rules! impl_dual_scalar {
+    ($($d: ident)*) => {$(
+        impl<T: From<Atom>> From<Atom> for $d<T> {
+            fn from(n: Atom) -> Self {
+                Self::constant(T::from(n))
+            }
+        }
+
+        impl<T: From<Atom>> Scalar for $d<T> {}
+
+        impl<T> ElemMul for $d<T>
+        where
+            Self: Mul<Output = Self>,
+        {
+            type Output = Self;
+            fn elem_mul(self, rhs: Self) -> Self::Output {
+                self * rhs
+            }
+        }
+
+        impl<T: Copy> Eval for $d<T> {
+            type T = NodeValue<$d<T>>;
+
+            fn try_eval(&self) -> error::Result<Self::T> {
+                Ok(NodeValue(*self))
+            }
+
+            fn infer_shape(&self) -> Result<Shape, ShapeError> {
+                Ok(Shape::Scalar)
+            }
+        }
+
+        impl<'a, T: Copy + 'a> Differentiable<'a> for $d<T> {
+            type Δ<D> = Atom;
+
+            fn derivative<const LEN: usize, D>(&'a self, _: [&str; LEN], _: D) -> [Self::Δ<D>; LEN] {
+                [Zero; LEN]
+            }
+
+            fn is_zero(&self) -> bool {
+                false
+            }
+        }
+    )*};
+}
+
+impl_dual_scalar!(Dual HyperDual);
+
+#[test]
+fn dual() {
+    fn f<T: Copy + Add<Output = T> + Mul<Output = T> + Div<Output = T> + Exp<Output = T>>(
+        x: T,
+    ) -> T {
+        x * x * x + x.exp() / x
+    }
+
+    let x = 1.5f64;
+    let df = 3. * x * x + x.exp() / x - x.exp() / (x * x);
+
+    assert!((derivative(f, x) - df).abs() < 1e-12);
+    assert_eq!(f(Dual::variable(x)).re, f(x));
+}
+
+#[test]
+fn hyper_dual() {
+    let f = |x: HyperDual<f64>| x * x * x + x.exp() / x;
+
+    let x = 1.5f64;
+    let e = x.exp();
+    let ddf = 6. * x + e / x - 2. * e / (x * x) + 2. * e / (x * x * x);
+
+    assert!((second_derivative(f, x) - ddf).abs() < 1e-12);
+}
+
+#[test]
+fn dual_node() {
+    use crate::prelude::*;
+
+    let x = Dual::variable(2f32).symbol("x");
+    let y = Dual::constant(3f32).symbol("y");
+    let f = &x * &y + &x * &x;
+
+    assert_eq!(f.eval().0, Dual::new(10., 7.));
+}
@@ -0,0 +1,103 @@
+use crate::shape::ShapeError;
+use std::fmt::Display;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    Shape(ShapeError),
+    /// An op is undefined for the value of its operand, e.g. the inverse of a singular matrix.
+    Value(String),
+    /// Native code couldn't be generated, e.g. for an unsupported target.
+    #[cfg(feature = "cranelift")]
+    Jit(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl From<ShapeError> for Error {
+    fn from(e: ShapeError) -> Self {
+        Error::Shape(e)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Shape(e) => write!(f, "{e}"),
+            Error::Value(msg) => write!(f, "{msg}"),
+            #[cfg(feature = "cranelift")]
+            Error::Jit(msg) => write!(f, "JIT compilation failed: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[test]
+fn try_eval() {
+    use crate::prelude::*;
+
+    let x = mat(DMatrix::<f32>::new_random(3, 1)).symbol("x");
+    let w = mat(DMatrix::<f32>::new_random(2, 3)).symbol("w");
+    let dl = mat(DMatrix::<f32>::repeat(2, 1, 1.));
+
+    let y = &w * &x;
+    assert_eq!(y.try_eval(), Ok(y.eval()));
+
+    let [dw, dx] = y.try_derivative(["w", "x"], &dl).unwrap();
+    let [dw_ref, dx_ref] = y.derivative(["w", "x"], &dl);
+    assert_eq!(dw, dw_ref.eval());
+    assert_eq!(dx, dx_ref.eval());
+
+    let bad = &x * &w;
+    match bad.try_eval() {
+        Err(Error::Shape(e)) => assert_eq!(e.symbols, ["x", "w"]),
+        r => panic!("expected a shape error, got {r:?}"),
+    }
+
+    let wrong_seed = mat(DMatrix::<f32>::repeat(3, 1, 1.));
+    assert!(matches!(
+        y.try_derivative(["w"], &wrong_seed),
+        Err(Error::Shape(_))
+    ));
+
+    let wrong_seed = mat(DMatrix::<f32>::repeat(2, 2, 1.));
+    assert!(matches!(
+        Slice(&w, 0..1, 0..3).try_derivative(["w"], &wrong_seed),
+        Err(Error::Shape(e)) if e.op == "Scatter"
+    ));
+
+    // The shape of the output of a custom op is only known once it has been evaluated.
+    struct Id;
+    impl CustomOp for Id {
+        type T = f32;
+
+        fn forward(&self, inputs: &[MatrixNode<f32>]) -> MatrixNode<f32> {
+            inputs[0].clone()
+        }
+
+        fn backward(
+            &self,
+            _: &[MatrixNode<f32>],
+            _: &MatrixNode<f32>,
+            grad: MatrixNode<f32>,
+        ) -> Vec<MatrixNode<f32>> {
+            vec![grad]
+        }
+    }
+    let unknown = custom(Id, (&x,));
+    assert_eq!(unknown.infer_shape(), Ok(crate::shape::Shape::Unknown));
+    match (&unknown * &w).try_eval() {
+        Err(Error::Shape(e)) => assert_eq!((e.op, e.symbols), ("Mul", vec!["x", "w"])),
+        r => panic!("expected a shape error, got {r:?}"),
+    }
+
+    let singular = mat(DMatrix::<f32>::from_element(2, 2, 1.)).symbol("a");
+    assert!(matches!(
+        Inverse(&singular).try_eval(),
+        Err(Error::Value(_))
+    ));
+    let [da] = Det(&singular).try_derivative(["a"], One).unwrap();
+    let da = da.try_eval().unwrap().into_dense().unwrap();
+    let expected = DMatrix::from_row_slice(2, 2, &[1., -1., -1., 1.]);
+    assert!((da - expected).abs().max() < 1e-6);
+}
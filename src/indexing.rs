@@ -0,0 +1,837 @@
+//! Ops that select or combine parts of matrices.
+//!
+//! They read the parts they select from a borrowed input, see [`Eval::with_value`]. Their
+//! derivatives scatter the gradient of the output into a zero matrix of the shape of the input,
+//! which is inferred without evaluating the input where possible. Scattering is linear, so these
+//! gradients are differentiated again by selecting from the gradient of the scattered matrix.
+
+use crate::mat::{mat, MatrixNode};
+use crate::ops::{zip_map, Add};
+use crate::shape::{Shape, ShapeError};
+use crate::value::{Atom, NodeValue, Scalar, Zero};
+use crate::{error, Differentiable, Eval};
+use nalgebra::{DMatrix, Dyn};
+use std::ops::Range;
+
+/// Where the output of a unary indexing op comes from in its input.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Selection {
+    Block(Range<usize>, Range<usize>),
+    Element(usize, usize),
+    Rows(Vec<usize>),
+    /// The elements in column-major order, with a new shape.
+    Reshape(usize, usize),
+}
+
+impl Selection {
+    fn fits(&self, (r, c): (usize, usize)) -> bool {
+        match self {
+            Selection::Block(rows, cols) => {
+                rows.start <= rows.end && rows.end <= r && cols.start <= cols.end && cols.end <= c
+            }
+            Selection::Element(i, j) => *i < r && *j < c,
+            Selection::Rows(rows) => rows.iter().all(|&i| i < r),
+            Selection::Reshape(nr, nc) => nr * nc == r * c,
+        }
+    }
+
+    fn output_shape(&self, (_, c): (usize, usize)) -> (usize, usize) {
+        match self {
+            Selection::Block(rows, cols) => (rows.len(), cols.len()),
+            Selection::Element(..) => (1, 1),
+            Selection::Rows(rows) => (rows.len(), c),
+            Selection::Reshape(r, c) => (*r, *c),
+        }
+    }
+
+    /// The shape of the selection from an input of shape `s`, or `None` if it doesn't fit.
+    fn output(&self, s: Shape) -> Option<Shape> {
+        match s {
+            Shape::Matrix(r, c) if self.fits((r, c)) => {
+                let (r, c) = self.output_shape((r, c));
+                Some(Shape::Matrix(r, c))
+            }
+            Shape::Unknown => Some(match self {
+                Selection::Rows(_) => Shape::Unknown,
+                s => {
+                    let (r, c) = s.output_shape((0, 0));
+                    Shape::Matrix(r, c)
+                }
+            }),
+            _ => None,
+        }
+    }
+
+    fn infer_shape(&self, op: &'static str, n: &impl Eval) -> Result<Shape, ShapeError> {
+        let s = n.infer_shape()?;
+        self.output(s)
+            .ok_or_else(|| ShapeError::new(op, vec![s], n.symbols()))
+    }
+
+    /// A copy of the selected part of `m`, the value of `n`.
+    fn apply<T: Element>(
+        &self,
+        op: &'static str,
+        n: &impl Eval,
+        m: &MatrixNode<T>,
+    ) -> Result<MatrixNode<T>, ShapeError> {
+        let Some(shape) = m.shape() else {
+            return Ok(MatrixNode::Zero(None));
+        };
+        if !self.fits(shape) {
+            return Err(ShapeError::new(op, vec![m.infer_shape()?], n.symbols()));
+        }
+        let (r, c) = self.output_shape(shape);
+        let select = |m: &DMatrix<T>| match self {
+            Selection::Block(rows, cols) => m.view((rows.start, cols.start), (r, c)).into_owned(),
+            Selection::Element(i, j) => DMatrix::from_element(1, 1, m[(*i, *j)]),
+            Selection::Rows(rows) => m.select_rows(rows),
+            Selection::Reshape(r, c) => DMatrix::from_column_slice(*r, *c, m.as_slice()),
+        };
+        Ok(match m {
+            MatrixNode::Zero(_) => MatrixNode::zeros(r, c),
+            MatrixNode::Fill(_, _, v) => MatrixNode::Fill(r, c, *v),
+            MatrixNode::Dense(m) => mat(select(m)),
+            m => mat(select(&m.clone().into_dense().unwrap())),
+        })
+    }
+
+    /// The gradient of the input of shape `(r, c)`, given the gradient `d` of the selection.
+    fn scatter<T: Element>(&self, d: MatrixNode<T>, (r, c): (usize, usize)) -> MatrixNode<T> {
+        match (self, d) {
+            (_, MatrixNode::Zero(_)) => MatrixNode::zeros(r, c),
+            (Selection::Reshape(..), MatrixNode::Fill(_, _, v)) => MatrixNode::Fill(r, c, v),
+            (Selection::Reshape(..), d) => {
+                mat(d.into_dense().unwrap().reshape_generic(Dyn(r), Dyn(c)))
+            }
+            (s, d) => {
+                let d = d.into_dense().unwrap();
+                let mut g = DMatrix::from_element(r, c, T::from(Zero));
+                match s {
+                    Selection::Block(rows, cols) => g
+                        .view_mut((rows.start, cols.start), (rows.len(), cols.len()))
+                        .copy_from(&d),
+                    Selection::Element(i, j) => g[(*i, *j)] = d[(0, 0)],
+                    // Rows can be gathered more than once, so their gradients add up.
+                    Selection::Rows(rows) => {
+                        for (k, &i) in rows.iter().enumerate() {
+                            for j in 0..c {
+                                g[(i, j)] = g[(i, j)] + d[(k, j)];
+                            }
+                        }
+                    }
+                    Selection::Reshape(..) => unreachable!(),
+                }
+                mat(g)
+            }
+        }
+    }
+}
+
+/// The shape of the value of `n`, which is only evaluated if its shape can't be inferred.
+pub(crate) fn shape_of<T: Element>(
+    n: &impl Eval<T = MatrixNode<T>>,
+) -> error::Result<Option<(usize, usize)>> {
+    match n.infer_shape()? {
+        Shape::Matrix(r, c) => Ok(Some((r, c))),
+        _ => n.with_value(MatrixNode::shape),
+    }
+}
+
+/// Checks that the gradient `d` of an indexing op has the shape `s` of its output.
+pub(crate) fn check_grad<T: Element>(
+    op: &'static str,
+    d: &MatrixNode<T>,
+    s: (usize, usize),
+    symbols: impl FnOnce() -> Vec<&'static str>,
+) -> Result<(), ShapeError> {
+    let (ds, s) = (d.infer_shape()?, Shape::Matrix(s.0, s.1));
+    match ds.elementwise(s) {
+        Some(_) => Ok(()),
+        None => Err(ShapeError::new(op, vec![ds, s], symbols())),
+    }
+}
+
+/// Elements of matrices that can be indexed.
+pub trait Element: nalgebra::Scalar + Scalar + Copy + std::ops::Add<Output = Self> {}
+
+impl<T: nalgebra::Scalar + Scalar + Copy + std::ops::Add<Output = Self>> Element for T {}
+
+/// Gradients flowing into an indexing op: matrices, or scalars that are broadcast to the shape
+/// of its output.
+pub trait IntoMatrix<T: Element> {
+    fn into_matrix(self, rows: usize, cols: usize) -> MatrixNode<T>;
+}
+
+/// A 1x1 matrix is the gradient of a scalar, so it is broadcast like one.
+impl<T: Element> IntoMatrix<T> for MatrixNode<T> {
+    fn into_matrix(self, rows: usize, cols: usize) -> MatrixNode<T> {
+        match self.shape() {
+            Some((1, 1)) if (rows, cols) != (1, 1) => MatrixNode::fill(rows, cols, self.get(0, 0)),
+            _ => self,
+        }
+    }
+}
+
+impl<T: Element> IntoMatrix<T> for NodeValue<T> {
+    fn into_matrix(self, rows: usize, cols: usize) -> MatrixNode<T> {
+        MatrixNode::fill(rows, cols, self.0)
+    }
+}
+
+impl<T: Element> IntoMatrix<T> for Atom {
+    fn into_matrix(self, rows: usize, cols: usize) -> MatrixNode<T> {
+        MatrixNode::fill(rows, cols, T::from(self))
+    }
+}
+
+macro_rules! impl_selection {
+    ($($Op:ident($($arg:ident),*) => $selection:expr;)*) => {$(
+        impl<T: Element, N: Eval<T = MatrixNode<T>>> Eval for $Op<N> {
+            type T = MatrixNode<T>;
+
+            fn try_eval(&self) -> error::Result<Self::T> {
+                let $Op(n, $($arg),*) = self;
+                Ok(n.with_value(|m| $selection.apply(stringify!($Op), n, m))??)
+            }
+
+            fn forget(&self) {
+                self.0.forget()
+            }
+
+            fn infer_shape(&self) -> Result<Shape, ShapeError> {
+                let $Op(n, $($arg),*) = self;
+                $selection.infer_shape(stringify!($Op), n)
+            }
+
+            fn symbols(&self) -> Vec<&'static str> {
+                self.0.symbols()
+            }
+        }
+
+        impl<'a, N: Differentiable<'a>> Differentiable<'a> for $Op<N>
+        where
+            Self: Eval,
+        {
+            type Δ<D> = N::Δ<Scatter<'a, N, D>> where Self: 'a;
+
+            fn derivative<const LEN: usize, D: Clone>(
+                &'a self,
+                k: [&str; LEN],
+                d: D,
+            ) -> [Self::Δ<D>; LEN] {
+                let $Op(n, $($arg),*) = self;
+                n.derivative(k, Scatter(d, n, $selection))
+            }
+
+            fn is_zero(&self) -> bool {
+                self.0.is_zero()
+            }
+        }
+    )*};
+}
+
+/// The block of `N` in the given rows and columns.
+#[derive(Clone, Debug)]
+pub struct Slice<N>(pub N, pub Range<usize>, pub Range<usize>);
+
+/// The given rows of `N`, which may repeat.
+#[derive(Clone, Debug)]
+pub struct Gather<N>(pub N, pub Vec<usize>);
+
+/// `N` with a new shape, keeping the elements in column-major order.
+#[derive(Clone, Debug)]
+pub struct Reshape<N>(pub N, pub usize, pub usize);
+
+impl_selection!(
+    Slice(rows, cols) => Selection::Block(rows.clone(), cols.clone());
+    Gather(rows) => Selection::Rows(rows.clone());
+    Reshape(r, c) => Selection::Reshape(*r, *c);
+);
+
+/// The element of `N` at the given row and column.
+#[derive(Clone, Debug)]
+pub struct Index<N>(pub N, pub usize, pub usize);
+
+impl<T: Element, N: Eval<T = MatrixNode<T>>> Eval for Index<N> {
+    type T = NodeValue<T>;
+
+    fn try_eval(&self) -> error::Result<Self::T> {
+        let element = Selection::Element(self.1, self.2);
+        let m = self
+            .0
+            .with_value(|m| element.apply("Index", &self.0, m))??;
+        Ok(NodeValue(m.get(0, 0)))
+    }
+
+    fn forget(&self) {
+        self.0.forget()
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        Selection::Element(self.1, self.2)
+            .infer_shape("Index", &self.0)
+            .map(|_| Shape::Scalar)
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        self.0.symbols()
+    }
+}
+
+impl<'a, N: Differentiable<'a>> Differentiable<'a> for Index<N>
+where
+    Self: Eval,
+{
+    type Δ<D> = N::Δ<Scatter<'a, N, D>> where Self: 'a;
+
+    fn derivative<const LEN: usize, D: Clone>(
+        &'a self,
+        k: [&str; LEN],
+        d: D,
+    ) -> [Self::Δ<D>; LEN] {
+        self.0
+            .derivative(k, Scatter(d, &self.0, Selection::Element(self.1, self.2)))
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+}
+
+/// The gradient of a selection from `N`, scattered into a zero matrix of the shape of `N`.
+#[derive(Debug)]
+pub struct Scatter<'a, N, D>(pub D, pub &'a N, pub Selection);
+
+impl<N, D: Clone> Clone for Scatter<'_, N, D> {
+    fn clone(&self) -> Self {
+        Scatter(self.0.clone(), self.1, self.2.clone())
+    }
+}
+
+impl<T: Element, N: Eval<T = MatrixNode<T>>, D: Eval> Eval for Scatter<'_, N, D>
+where
+    D::T: IntoMatrix<T>,
+{
+    type T = MatrixNode<T>;
+
+    fn try_eval(&self) -> error::Result<Self::T> {
+        let Some(shape) = shape_of(self.1)? else {
+            return Ok(MatrixNode::Zero(None));
+        };
+        if !self.2.fits(shape) {
+            let s = Shape::Matrix(shape.0, shape.1);
+            return Err(ShapeError::new("Scatter", vec![s], self.1.symbols()).into());
+        }
+        let (r, c) = self.2.output_shape(shape);
+        let d = self.0.try_eval()?.into_matrix(r, c);
+        check_grad("Scatter", &d, (r, c), || self.symbols())?;
+        Ok(self.2.scatter(d, shape))
+    }
+
+    fn forget(&self) {
+        self.0.forget();
+        self.1.forget();
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        let (d, s) = (self.0.infer_shape()?, self.1.infer_shape()?);
+        match self.2.output(s) {
+            Some(out) if d.elementwise(out).is_some() => Ok(s),
+            _ => Err(ShapeError::new("Scatter", vec![d, s], self.symbols())),
+        }
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        [self.0.symbols(), self.1.symbols()].concat()
+    }
+}
+
+impl<'a, 'b, N, D: Differentiable<'b>> Differentiable<'b> for Scatter<'a, N, D>
+where
+    Self: Eval,
+{
+    type Δ<G> = D::Δ<Select<'a, N, G>> where Self: 'b;
+
+    fn derivative<const LEN: usize, G: Clone>(
+        &'b self,
+        k: [&str; LEN],
+        g: G,
+    ) -> [Self::Δ<G>; LEN] {
+        self.0.derivative(k, Select(g, self.1, self.2.clone()))
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+}
+
+/// The selection from the gradient `D` of the input `N` of a [`Scatter`], which is its adjoint.
+#[derive(Debug)]
+pub struct Select<'a, N, D>(pub D, pub &'a N, pub Selection);
+
+impl<N, D: Clone> Clone for Select<'_, N, D> {
+    fn clone(&self) -> Self {
+        Select(self.0.clone(), self.1, self.2.clone())
+    }
+}
+
+impl<T: Element, N: Eval<T = MatrixNode<T>>, D: Eval> Eval for Select<'_, N, D>
+where
+    D::T: IntoMatrix<T>,
+{
+    type T = MatrixNode<T>;
+
+    fn try_eval(&self) -> error::Result<Self::T> {
+        let Some(shape) = shape_of(self.1)? else {
+            return Ok(MatrixNode::Zero(None));
+        };
+        let d = self.0.try_eval()?.into_matrix(shape.0, shape.1);
+        check_grad("Select", &d, shape, || self.symbols())?;
+        Ok(self.2.apply("Select", self.1, &d)?)
+    }
+
+    fn forget(&self) {
+        self.0.forget();
+        self.1.forget();
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        let (d, s) = (self.0.infer_shape()?, self.1.infer_shape()?);
+        match (d.elementwise(s), self.2.output(s)) {
+            (Some(_), Some(out)) => Ok(out),
+            _ => Err(ShapeError::new("Select", vec![d, s], self.symbols())),
+        }
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        [self.0.symbols(), self.1.symbols()].concat()
+    }
+}
+
+impl<'a, 'b, N, D: Differentiable<'b>> Differentiable<'b> for Select<'a, N, D>
+where
+    Self: Eval,
+{
+    type Δ<G> = D::Δ<Scatter<'a, N, G>> where Self: 'b;
+
+    fn derivative<const LEN: usize, G: Clone>(
+        &'b self,
+        k: [&str; LEN],
+        g: G,
+    ) -> [Self::Δ<G>; LEN] {
+        self.0.derivative(k, Scatter(g, self.1, self.2.clone()))
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+}
+
+/// Which operand of a [`Concat`] or [`Stack`] a gradient flows into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+impl Side {
+    fn horizontal(self) -> bool {
+        matches!(self, Side::Left | Side::Right)
+    }
+
+    /// The block of this side in `l` joined with `r`, and the shape of the joined matrix, or
+    /// `None` if either is a zero matrix without a shape.
+    fn block<T: Element>(
+        self,
+        op: &'static str,
+        l: &impl Eval<T = MatrixNode<T>>,
+        r: &impl Eval<T = MatrixNode<T>>,
+    ) -> error::Result<Option<(Selection, (usize, usize))>> {
+        let (Some((lr, lc)), Some((rr, rc))) = (shape_of(l)?, shape_of(r)?) else {
+            return Ok(None);
+        };
+        let (ls, rs) = (Shape::Matrix(lr, lc), Shape::Matrix(rr, rc));
+        let Some(Shape::Matrix(rows, cols)) = joined(ls, rs, self.horizontal()) else {
+            let symbols = [l.symbols(), r.symbols()].concat();
+            return Err(ShapeError::new(op, vec![ls, rs], symbols).into());
+        };
+        let block = match self {
+            Side::Left | Side::Top => Selection::Block(0..lr, 0..lc),
+            Side::Right => Selection::Block(0..rr, lc..cols),
+            Side::Bottom => Selection::Block(lr..rows, 0..rc),
+        };
+        Ok(Some((block, (rows, cols))))
+    }
+
+    /// The shape of this side, given the shapes of `l` and `r`.
+    fn of(self, l: Shape, r: Shape) -> Shape {
+        match self {
+            Side::Left | Side::Top => l,
+            Side::Right | Side::Bottom => r,
+        }
+    }
+}
+
+/// The shape of `l` joined with `r`, or `None` if they don't fit together.
+fn joined(l: Shape, r: Shape, horizontal: bool) -> Option<Shape> {
+    match (l, r) {
+        (Shape::Matrix(lr, lc), Shape::Matrix(rr, rc)) if horizontal && lr == rr => {
+            Some(Shape::Matrix(lr, lc + rc))
+        }
+        (Shape::Matrix(lr, lc), Shape::Matrix(rr, rc)) if !horizontal && lc == rc => {
+            Some(Shape::Matrix(lr + rr, lc))
+        }
+        (Shape::Unknown, Shape::Matrix(..) | Shape::Unknown)
+        | (Shape::Matrix(..), Shape::Unknown) => Some(Shape::Unknown),
+        _ => None,
+    }
+}
+
+/// `l` joined with `r`. Zero matrices without a shape can't be joined, as the shape of the
+/// output depends on them.
+fn join<T: Element>(
+    l: MatrixNode<T>,
+    r: MatrixNode<T>,
+    horizontal: bool,
+    symbols: impl FnOnce() -> Vec<&'static str>,
+) -> Result<MatrixNode<T>, ShapeError> {
+    let op = if horizontal { "Concat" } else { "Stack" };
+    let (ls, rs) = (l.infer_shape()?, r.infer_shape()?);
+    let (Shape::Matrix(lr, lc), Some(Shape::Matrix(rows, cols))) = (ls, joined(ls, rs, horizontal))
+    else {
+        return Err(ShapeError::new(op, vec![ls, rs], symbols()));
+    };
+    Ok(match (l, r) {
+        (MatrixNode::Zero(_), MatrixNode::Zero(_)) => MatrixNode::zeros(rows, cols),
+        (MatrixNode::Fill(_, _, a), MatrixNode::Fill(_, _, b)) if a == b => {
+            MatrixNode::Fill(rows, cols, a)
+        }
+        (l, r) => {
+            let (l, r) = (l.into_dense().unwrap(), r.into_dense().unwrap());
+            mat(DMatrix::from_fn(rows, cols, |i, j| {
+                match (i < lr, j < lc) {
+                    (true, true) => l[(i, j)],
+                    _ if horizontal => r[(i, j - lc)],
+                    _ => r[(i - lr, j)],
+                }
+            }))
+        }
+    })
+}
+
+fn join_shape<L: Eval, R: Eval>(l: &L, r: &R, horizontal: bool) -> Result<Shape, ShapeError> {
+    let op = if horizontal { "Concat" } else { "Stack" };
+    crate::shape::binary(op, l, r, |l, r| joined(l, r, horizontal))
+}
+
+macro_rules! impl_join {
+    ($($Op:ident: $first:ident, $second:ident, $horizontal:expr;)*) => {$(
+        impl<T: Element, LNode: Eval<T = MatrixNode<T>>, RNode: Eval<T = MatrixNode<T>>> Eval
+            for $Op<LNode, RNode>
+        {
+            type T = MatrixNode<T>;
+
+            fn try_eval(&self) -> error::Result<Self::T> {
+                let (l, r) = (self.0.try_eval()?, self.1.try_eval()?);
+                Ok(join(l, r, $horizontal, || self.symbols())?)
+            }
+
+            fn forget(&self) {
+                self.0.forget();
+                self.1.forget();
+            }
+
+            fn infer_shape(&self) -> Result<Shape, ShapeError> {
+                join_shape(&self.0, &self.1, $horizontal)
+            }
+
+            fn symbols(&self) -> Vec<&'static str> {
+                [self.0.symbols(), self.1.symbols()].concat()
+            }
+        }
+
+        impl<'a, LNode: Differentiable<'a>, RNode: Differentiable<'a>> Differentiable<'a>
+            for $Op<LNode, RNode>
+        where
+            Self: Eval,
+        {
+            type Δ<D> = Add<
+                LNode::Δ<Part<'a, LNode, RNode, D>>,
+                RNode::Δ<Part<'a, LNode, RNode, D>>,
+            > where Self: 'a;
+
+            fn derivative<const LEN: usize, D: Clone>(
+                &'a self,
+                k: [&str; LEN],
+                d: D,
+            ) -> [Self::Δ<D>; LEN] {
+                let part = |side| Part(d.clone(), &self.0, &self.1, side);
+                zip_map(
+                    self.0.derivative(k, part(Side::$first)),
+                    self.1.derivative(k, part(Side::$second)),
+                    Add,
+                )
+            }
+
+            fn is_zero(&self) -> bool {
+                self.0.is_zero() && self.1.is_zero()
+            }
+        }
+    )*};
+}
+
+/// The columns of `L` followed by the columns of `R`.
+#[derive(Clone, Debug)]
+pub struct Concat<L, R>(pub L, pub R);
+
+/// The rows of `L` followed by the rows of `R`.
+#[derive(Clone, Debug)]
+pub struct Stack<L, R>(pub L, pub R);
+
+impl_join!(
+    Concat: Left, Right, true;
+    Stack: Top, Bottom, false;
+);
+
+/// The part of the gradient of a [`Concat`] or [`Stack`] that flows into one operand.
+#[derive(Debug)]
+pub struct Part<'a, L, R, D>(pub D, pub &'a L, pub &'a R, pub Side);
+
+impl<L, R, D: Clone> Clone for Part<'_, L, R, D> {
+    fn clone(&self) -> Self {
+        Part(self.0.clone(), self.1, self.2, self.3)
+    }
+}
+
+impl<T: Element, L: Eval<T = MatrixNode<T>>, R: Eval<T = MatrixNode<T>>, D: Eval> Eval
+    for Part<'_, L, R, D>
+where
+    D::T: IntoMatrix<T>,
+{
+    type T = MatrixNode<T>;
+
+    fn try_eval(&self) -> error::Result<Self::T> {
+        let Some((block, (rows, cols))) = self.3.block("Part", self.1, self.2)? else {
+            return Ok(MatrixNode::Zero(None));
+        };
+        let d = self.0.try_eval()?.into_matrix(rows, cols);
+        check_grad("Part", &d, (rows, cols), || self.symbols())?;
+        Ok(block.apply("Part", &self.0, &d)?)
+    }
+
+    fn forget(&self) {
+        self.0.forget();
+        self.1.forget();
+        self.2.forget();
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        let (d, l, r) = (
+            self.0.infer_shape()?,
+            self.1.infer_shape()?,
+            self.2.infer_shape()?,
+        );
+        match joined(l, r, self.3.horizontal()) {
+            Some(s) if d.elementwise(s).is_some() => Ok(self.3.of(l, r)),
+            _ => Err(ShapeError::new("Part", vec![d, l, r], self.symbols())),
+        }
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        [self.0.symbols(), self.1.symbols(), self.2.symbols()].concat()
+    }
+}
+
+impl<'a, 'b, L, R, D: Differentiable<'b>> Differentiable<'b> for Part<'a, L, R, D>
+where
+    Self: Eval,
+{
+    type Δ<G> = D::Δ<Pad<'a, L, R, G>> where Self: 'b;
+
+    fn derivative<const LEN: usize, G: Clone>(
+        &'b self,
+        k: [&str; LEN],
+        g: G,
+    ) -> [Self::Δ<G>; LEN] {
+        self.0.derivative(k, Pad(g, self.1, self.2, self.3))
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+}
+
+/// The gradient `D` of one operand of a [`Concat`] or [`Stack`], padded with zeros to the shape
+/// of their output. It is the adjoint of [`Part`].
+#[derive(Debug)]
+pub struct Pad<'a, L, R, D>(pub D, pub &'a L, pub &'a R, pub Side);
+
+impl<L, R, D: Clone> Clone for Pad<'_, L, R, D> {
+    fn clone(&self) -> Self {
+        Pad(self.0.clone(), self.1, self.2, self.3)
+    }
+}
+
+impl<T: Element, L: Eval<T = MatrixNode<T>>, R: Eval<T = MatrixNode<T>>, D: Eval> Eval
+    for Pad<'_, L, R, D>
+where
+    D::T: IntoMatrix<T>,
+{
+    type T = MatrixNode<T>;
+
+    fn try_eval(&self) -> error::Result<Self::T> {
+        let Some((block, shape)) = self.3.block("Pad", self.1, self.2)? else {
+            return Ok(MatrixNode::Zero(None));
+        };
+        let (r, c) = block.output_shape(shape);
+        let d = self.0.try_eval()?.into_matrix(r, c);
+        check_grad("Pad", &d, (r, c), || self.symbols())?;
+        Ok(block.scatter(d, shape))
+    }
+
+    fn forget(&self) {
+        self.0.forget();
+        self.1.forget();
+        self.2.forget();
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        let (d, l, r) = (
+            self.0.infer_shape()?,
+            self.1.infer_shape()?,
+            self.2.infer_shape()?,
+        );
+        match joined(l, r, self.3.horizontal()) {
+            Some(s) if d.elementwise(self.3.of(l, r)).is_some() => Ok(s),
+            _ => Err(ShapeError::new("Pad", vec![d, l, r], self.symbols())),
+        }
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        [self.0.symbols(), self.1.symbols(), self.2.symbols()].concat()
+    }
+}
+
+impl<'a, 'b, L, R, D: Differentiable<'b>> Differentiable<'b> for Pad<'a, L, R, D>
+where
+    Self: Eval,
+{
+    type Δ<G> = D::Δ<Part<'a, L, R, G>> where Self: 'b;
+
+    fn derivative<const LEN: usize, G: Clone>(
+        &'b self,
+        k: [&str; LEN],
+        g: G,
+    ) -> [Self::Δ<G>; LEN] {
+        self.0.derivative(k, Part(g, self.1, self.2, self.3))
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+}
+
+#[test]
+fn selections() {
+    use crate::{prelude::*, Node};
+
+    let x = mat(DMatrix::from_fn(3, 4, |i, j| (i * 4 + j) as f32)).symbol("x");
+    let y = mat(DMatrix::from_fn(3, 2, |i, j| (i + j) as f32)).symbol("y");
+
+    let s = Slice(&x, 1..3, 2..4);
+    assert_eq!(
+        s.eval(),
+        mat(DMatrix::from_row_slice(2, 2, &[6., 7., 10., 11.]))
+    );
+    assert_eq!(s.infer_shape(), Ok(Shape::Matrix(2, 2)));
+
+    // Only the sliced block receives a gradient.
+    let f = Sum(&s);
+    let [dx] = f.derivative(["x"], One);
+    let dx = dx.eval();
+    assert_eq!(dx.shape(), Some((3, 4)));
+    assert_eq!(dx.get(2, 3), 1.);
+    assert_eq!(dx.get(0, 3), 0.);
+    assert_eq!(dx.get(2, 1), 0.);
+
+    // The input of a slice is only evaluated for its shape if it can't be inferred.
+    let h = Node(Exp(&x)).cached();
+    let f = Sum(Slice(&h, 0..2, 1..2));
+    let [dx] = f.derivative(["x"], One);
+    h.forget();
+    assert_eq!(dx.eval().get(1, 1), 5f32.exp());
+    assert!(!h.0.is_cached());
+
+    let i = Index(&x, 2, 1);
+    let [dx, dy] = i.derivative(["x", "y"], One);
+    assert_eq!(i.eval().0, 9.);
+    assert_eq!(dx.eval().get(2, 1), 1.);
+    assert_eq!(dx.eval().into_dense().unwrap().sum(), 1.);
+    assert_eq!(dy.eval().shape(), None);
+
+    // Gradients of repeated rows add up.
+    let g = Gather(&x, vec![2, 0, 2]);
+    assert_eq!(g.eval().get(1, 3), 3.);
+    let f = Sum(&g);
+    let [dx] = f.derivative(["x"], One);
+    let dx = dx.eval();
+    assert_eq!((dx.get(0, 0), dx.get(1, 0), dx.get(2, 0)), (1., 0., 2.));
+
+    // Columns are gathered through transposes.
+    let cols = Gather((&x).transpose(), vec![3]).transpose();
+    let col = Slice(&x, 0..3, 3..4);
+    assert_eq!(cols.eval(), col.eval());
+    let dcol = mat(DMatrix::from_column_slice(3, 1, &[1., 2., 3.]));
+    let [dx] = cols.derivative(["x"], &dcol);
+    let [dx_ref] = col.derivative(["x"], &dcol);
+    assert_eq!(dx.eval(), dx_ref.eval());
+    assert_eq!(dx.eval().get(2, 3), 3.);
+
+    // The gradients of selections are differentiated again through the same selection.
+    let f = crate::ops::Mul(Index(&x, 1, 1), Index(&x, 1, 1));
+    let [dx] = f.derivative(["x"], One);
+    let [ddx] = dx.derivative(["x"], One);
+    assert_eq!(dx.eval().get(1, 1), 10.);
+    let ddx = ddx.eval();
+    assert_eq!((ddx.get(1, 1), ddx.get(0, 1)), (2., 0.));
+
+    let r = Reshape(&x, 6, 2);
+    assert_eq!(r.eval().get(4, 1), x.eval().get(1, 3));
+    let f = Sum(Exp(&r));
+    let [dx] = f.derivative(["x"], One);
+    assert_eq!(dx.eval().get(1, 3), 7f32.exp());
+
+    let c = Concat(&x, &y);
+    assert_eq!(c.eval().shape(), Some((3, 6)));
+    assert_eq!(c.eval().get(1, 5), 2.);
+    let [dx, dy] = c.derivative(
+        ["x", "y"],
+        mat(DMatrix::from_fn(3, 6, |i, j| (i * 6 + j) as f32)),
+    );
+    assert_eq!(dx.eval().shape(), Some((3, 4)));
+    assert_eq!(
+        dy.eval(),
+        mat(DMatrix::from_row_slice(3, 2, &[4., 5., 10., 11., 16., 17.]))
+    );
+
+    let st = Stack(&x, (&y).transpose());
+    assert_eq!(st.infer_shape().unwrap_err().op, "Stack");
+    let st = Stack(&x, Reshape(&y, 2, 3)).transpose();
+    assert_eq!(st.infer_shape().unwrap_err().op, "Stack");
+    let st = Stack(&x, Slice(&x, 0..1, 0..4));
+    assert_eq!(st.infer_shape(), Ok(Shape::Matrix(4, 4)));
+    let f = Sum(&st);
+    let [dx] = f.derivative(["x"], One);
+    assert_eq!(dx.eval().get(0, 0), 2.);
+    assert_eq!(dx.eval().get(1, 0), 1.);
+
+    let c = Node(Concat(&y, &x));
+    let f = Sum(crate::ops::ElemMul(&c, &c));
+    let [dx] = f.derivative(["x"], One);
+    let [ddx] = dx.derivative(["x"], One);
+    assert_eq!(dx.eval(), x.eval().map(|v| v * 2.));
+    assert_eq!(ddx.eval(), MatrixNode::fill(3, 4, 2.));
+}
@@ -0,0 +1,244 @@
+//! Native code for scalar expressions and their gradients, generated with Cranelift. Enabled by
+//! the `cranelift` feature.
+//!
+//! [`Jit::new`] lowers the expression like [`Compiled::new`], and translates its instructions to
+//! a function that reads the values of the symbols and writes the value of the expression,
+//! followed by the gradients.
+
+use crate::compile::{len, Compile, Compiled, Instr};
+use crate::error::{Error, Result};
+use crate::linalg::Real;
+use crate::shape::ShapeError;
+use crate::Differentiable;
+use cranelift_codegen::ir::{types, AbiParam, InstBuilder, MemFlags, Type, Value};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Linkage, Module};
+use std::mem::ManuallyDrop;
+
+/// The element types that native code can be generated for, i.e. `f32` and `f64`.
+pub trait Float: Real {
+    #[doc(hidden)]
+    const TYPE: Type;
+
+    #[doc(hidden)]
+    fn constant(b: &mut FunctionBuilder, x: Self) -> Value;
+
+    /// `exp`, which is called by the generated code.
+    #[doc(hidden)]
+    extern "C" fn exp(x: Self) -> Self;
+}
+
+impl Float for f32 {
+    const TYPE: Type = types::F32;
+
+    fn constant(b: &mut FunctionBuilder, x: Self) -> Value {
+        b.ins().f32const(x)
+    }
+
+    extern "C" fn exp(x: Self) -> Self {
+        x.exp()
+    }
+}
+
+impl Float for f64 {
+    const TYPE: Type = types::F64;
+
+    fn constant(b: &mut FunctionBuilder, x: Self) -> Value {
+        b.ins().f64const(x)
+    }
+
+    extern "C" fn exp(x: Self) -> Self {
+        x.exp()
+    }
+}
+
+/// The signature of the generated functions. The first pointer is to the values of the symbols,
+/// in the order of [`Jit::symbols`], and the second to `1 + LEN` elements for the value and the
+/// gradients.
+pub type Function<T> = unsafe extern "C" fn(*const T, *mut T);
+
+/// A scalar expression and its gradients, compiled to native code.
+pub struct Jit<T, const LEN: usize> {
+    /// Owns the memory of the code, which is freed when the `Jit` is dropped.
+    module: ManuallyDrop<JITModule>,
+    function: Function<T>,
+    symbols: Vec<&'static str>,
+}
+
+fn jit_error(e: impl std::fmt::Display) -> Error {
+    Error::Jit(e.to_string())
+}
+
+impl<T: Float, const LEN: usize> Jit<T, LEN> {
+    /// Compiles `expr` and its derivatives with respect to `k`, like
+    /// [`derivative`](Differentiable::derivative). Every value in the expression must be a
+    /// scalar.
+    pub fn new<'a, N, D: Clone>(expr: &'a N, k: [&str; LEN], d: D) -> Result<Self>
+    where
+        N: Differentiable<'a> + Compile<T>,
+        N::Δ<D>: Compile<T>,
+    {
+        let program = Compiled::new(expr, k, d)?;
+        if let Some(&shape) = program.shapes.iter().find(|&&s| len(s) != 1) {
+            let symbols = program.inputs.iter().map(|&(s, _)| s).collect();
+            return Err(ShapeError::new("Jit", vec![shape], symbols).into());
+        }
+
+        let mut flags = settings::builder();
+        flags.set("opt_level", "speed").map_err(jit_error)?;
+        let isa = cranelift_native::builder()
+            .map_err(jit_error)?
+            .finish(settings::Flags::new(flags))
+            .map_err(jit_error)?;
+        let mut jit = JITBuilder::with_isa(isa, default_libcall_names());
+        jit.symbol("autodiff_exp", <T as Float>::exp as *const u8);
+        let mut module = JITModule::new(jit);
+
+        let ptr = module.target_config().pointer_type();
+        let mut exp = module.make_signature();
+        exp.params.push(AbiParam::new(T::TYPE));
+        exp.returns.push(AbiParam::new(T::TYPE));
+        let exp = module
+            .declare_function("autodiff_exp", Linkage::Import, &exp)
+            .map_err(jit_error)?;
+
+        let mut ctx = module.make_context();
+        ctx.func.signature.params = vec![AbiParam::new(ptr); 2];
+        let id = module
+            .declare_anonymous_function(&ctx.func.signature)
+            .map_err(jit_error)?;
+        let mut fctx = FunctionBuilderContext::new();
+        let mut b = FunctionBuilder::new(&mut ctx.func, &mut fctx);
+        let exp = module.declare_func_in_func(exp, b.func);
+        let block = b.create_block();
+        b.append_block_params_for_function_params(block);
+        b.switch_to_block(block);
+        b.seal_block(block);
+        let (inputs, outputs) = (b.block_params(block)[0], b.block_params(block)[1]);
+        let size = T::TYPE.bytes() as i32;
+
+        // Constants, then the symbols, then the results of the instructions.
+        let mut values: Vec<_> = program
+            .registers
+            .iter()
+            .map(|r| T::constant(&mut b, r[0]))
+            .collect();
+        for (i, &(_, reg)) in program.inputs.iter().enumerate() {
+            values[reg.0] = b
+                .ins()
+                .load(T::TYPE, MemFlags::trusted(), inputs, i as i32 * size);
+        }
+        for &(instr, dst) in &program.code {
+            let v = |r: crate::compile::Reg| values[r.0];
+            values[dst.0] = match instr {
+                Instr::Add(l, r) => b.ins().fadd(v(l), v(r)),
+                Instr::Sub(l, r) => b.ins().fsub(v(l), v(r)),
+                Instr::Div(l, r) => b.ins().fdiv(v(l), v(r)),
+                Instr::ElemMul(l, r) | Instr::Mul(l, r) => b.ins().fmul(v(l), v(r)),
+                Instr::Neg(x) => b.ins().fneg(v(x)),
+                Instr::Exp(x) => {
+                    let call = b.ins().call(exp, &[v(x)]);
+                    b.inst_results(call)[0]
+                }
+                Instr::Transpose(x) | Instr::Sum(x) => v(x),
+            };
+        }
+        let results = program.outputs();
+        for (i, reg) in results.enumerate() {
+            b.ins()
+                .store(MemFlags::trusted(), values[reg.0], outputs, i as i32 * size);
+        }
+        b.ins().return_(&[]);
+        b.finalize();
+
+        module.define_function(id, &mut ctx).map_err(jit_error)?;
+        module.clear_context(&mut ctx);
+        module.finalize_definitions().map_err(jit_error)?;
+        let code = module.get_finalized_function(id);
+        Ok(Self {
+            module: ManuallyDrop::new(module),
+            // SAFETY: the function was defined with this signature.
+            function: unsafe { std::mem::transmute::<*const u8, Function<T>>(code) },
+            symbols: program.inputs.iter().map(|&(s, _)| s).collect(),
+        })
+    }
+
+    /// The symbols whose values the function takes, in order.
+    pub fn symbols(&self) -> &[&'static str] {
+        &self.symbols
+    }
+
+    /// The generated function, which is valid as long as `self` isn't dropped.
+    pub fn function(&self) -> Function<T> {
+        self.function
+    }
+
+    /// The value and the gradients for `values` of the [`symbols`](Self::symbols).
+    ///
+    /// Panics if there isn't a value for every symbol.
+    pub fn call(&self, values: &[T]) -> (T, [T; LEN]) {
+        /// The layout of the outputs of the generated function.
+        #[repr(C)]
+        struct Outputs<T, const LEN: usize>(T, [T; LEN]);
+
+        assert_eq!(
+            values.len(),
+            self.symbols.len(),
+            "expected the values of {:?}",
+            self.symbols
+        );
+        let mut out = Outputs(T::zero(), [T::zero(); LEN]);
+        // SAFETY: the function reads one value per symbol and writes `1 + LEN` values.
+        unsafe { (self.function)(values.as_ptr(), &mut out as *mut _ as *mut T) };
+        (out.0, out.1)
+    }
+}
+
+impl<T, const LEN: usize> Drop for Jit<T, LEN> {
+    fn drop(&mut self) {
+        // SAFETY: the module isn't used again, and the function pointers handed out by
+        // `function` aren't valid after the `Jit` is dropped.
+        unsafe { ManuallyDrop::take(&mut self.module).free_memory() }
+    }
+}
+
+impl<T, const LEN: usize> std::fmt::Debug for Jit<T, LEN> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Jit")
+            .field("symbols", &self.symbols)
+            .finish_non_exhaustive()
+    }
+}
+
+#[test]
+fn matches_derivative() {
+    use crate::test::assert_close;
+    use crate::{prelude::*, Node};
+
+    let x = 2f64.symbol("x");
+    let y = 3f64.symbol("y");
+    let f = &x * &y + &x * Node(Exp(&x / &y)) - 0.5;
+    let jit = Jit::new(&f, ["x", "y"], One).unwrap();
+    assert_eq!(jit.symbols(), ["x", "y"]);
+
+    let close = |a: f64, b: f64| assert_close(&[a], &[b]);
+    for (xv, yv) in [(2., 3.), (-1.5, 0.25), (0., 7.)] {
+        let (x, y) = (xv.symbol("x"), yv.symbol("y"));
+        let f = &x * &y + &x * Node(Exp(&x / &y)) - 0.5;
+        let (value, [dx, dy]) = jit.call(&[xv, yv]);
+        let [dx_ref, dy_ref] = f.derivative(["x", "y"], One);
+        let [dx_ref, dy_ref]: [NodeValue<f64>; 2] = [dx_ref.eval(), dy_ref.eval()];
+        let f: NodeValue<f64> = f.eval();
+        close(value, *f);
+        close(dx, *dx_ref);
+        close(dy, *dy_ref);
+    }
+
+    let m = mat(DMatrix::<f64>::repeat(2, 1, 1.)).symbol("m");
+    let Err(Error::Shape(e)) = Jit::<f64, 0>::new(&m, [], One) else {
+        panic!("expected a shape error");
+    };
+    assert_eq!(e.op, "Jit");
+}
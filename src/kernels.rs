@@ -0,0 +1,205 @@
+//! Vectorized kernels for dense `f32` and `f64` matrices, enabled by the `simd` feature.
+//!
+//! Elementwise ops run on the SIMD types of [`wide`], and large matrix products on [`gemm`]. The
+//! callers check [`supports`] or [`gemm_supports`] and fall back to their scalar loops otherwise.
+
+use crate::mat::BinOp;
+use crate::value::{One, Scalar, Zero};
+use nalgebra::{allocator::Allocator, Complex, DefaultAllocator, Dim, OMatrix};
+use std::any::TypeId;
+use wide::{f32x8, f64x4};
+
+/// Products with fewer multiply-adds than this are left to nalgebra, which has less overhead for
+/// small matrices.
+const GEMM_THRESHOLD: usize = 32 * 32 * 32;
+
+trait Simd: Copy + Default + 'static {
+    fn exp(xs: &mut [Self]);
+
+    fn zip(xs: &mut [Self], ys: &[Self], op: BinOp);
+
+    /// `x op y` for every `x` in `xs`, or `y op x` if `swap`.
+    fn broadcast(xs: &mut [Self], op: BinOp, y: Self, swap: bool);
+}
+
+/// Applies `f` to the lanes of `xs`, padding the last chunk so every element goes through `f`.
+fn lanes<T: Copy + Default, const N: usize>(xs: &mut [T], f: impl Fn([T; N]) -> [T; N]) {
+    let mut chunks = xs.chunks_exact_mut(N);
+    for chunk in &mut chunks {
+        chunk.copy_from_slice(&f((&*chunk).try_into().unwrap()));
+    }
+    let tail = chunks.into_remainder();
+    if !tail.is_empty() {
+        let mut x = [T::default(); N];
+        x[..tail.len()].copy_from_slice(tail);
+        tail.copy_from_slice(&f(x)[..tail.len()]);
+    }
+}
+
+/// Like [`lanes`], with the matching lanes of `ys`.
+fn zip_lanes<T: Copy + Default, const N: usize>(
+    xs: &mut [T],
+    ys: &[T],
+    f: impl Fn([T; N], [T; N]) -> [T; N],
+) {
+    assert_eq!(xs.len(), ys.len());
+    let (mut chunks, mut ys) = (xs.chunks_exact_mut(N), ys.chunks_exact(N));
+    for (chunk, y) in (&mut chunks).zip(&mut ys) {
+        chunk.copy_from_slice(&f((&*chunk).try_into().unwrap(), y.try_into().unwrap()));
+    }
+    let (tail, ys) = (chunks.into_remainder(), ys.remainder());
+    if !tail.is_empty() {
+        let (mut x, mut y) = ([T::default(); N], [T::default(); N]);
+        x[..tail.len()].copy_from_slice(tail);
+        y[..tail.len()].copy_from_slice(ys);
+        tail.copy_from_slice(&f(x, y)[..tail.len()]);
+    }
+}
+
+macro_rules! impl_simd {
+    ($($t:ty: $v:ident),*) => {$(
+        impl Simd for $t {
+            fn exp(xs: &mut [Self]) {
+                lanes(xs, |x| $v::from(x).exp().to_array())
+            }
+
+            fn zip(xs: &mut [Self], ys: &[Self], op: BinOp) {
+                zip_lanes(xs, ys, |x, y| op.apply($v::from(x), $v::from(y)).to_array())
+            }
+
+            fn broadcast(xs: &mut [Self], op: BinOp, y: Self, swap: bool) {
+                let y = $v::splat(y);
+                lanes(xs, |x| {
+                    let x = $v::from(x);
+                    if swap { op.apply(y, x) } else { op.apply(x, y) }.to_array()
+                })
+            }
+        }
+    )*};
+}
+
+impl_simd!(f32: f32x8, f64: f64x4);
+
+/// `xs` as a slice of `S`. Panics if `T` isn't `S`.
+fn cast_mut<T: 'static, S: 'static>(xs: &mut [T]) -> &mut [S] {
+    assert_eq!(TypeId::of::<T>(), TypeId::of::<S>());
+    // SAFETY: `T` and `S` are the same type.
+    unsafe { &mut *(xs as *mut [T] as *mut [S]) }
+}
+
+/// `xs` as a slice of `S`. Panics if `T` isn't `S`.
+fn cast_ref<T: 'static, S: 'static>(xs: &[T]) -> &[S] {
+    assert_eq!(TypeId::of::<T>(), TypeId::of::<S>());
+    // SAFETY: `T` and `S` are the same type.
+    unsafe { &*(xs as *const [T] as *const [S]) }
+}
+
+/// Runs `$body` with `$S` as an alias of `$T`, which must be `f32` or `f64`, as checked by
+/// [`supports`].
+macro_rules! dispatch {
+    ($T:ty, $S:ident => $body:expr) => {
+        if TypeId::of::<$T>() == TypeId::of::<f32>() {
+            type $S = f32;
+            $body
+        } else if TypeId::of::<$T>() == TypeId::of::<f64>() {
+            type $S = f64;
+            $body
+        } else {
+            unreachable!("no kernels for {}", std::any::type_name::<$T>())
+        }
+    };
+}
+
+/// Whether the elementwise kernels support `T`.
+pub(crate) fn supports<T: 'static>() -> bool {
+    [TypeId::of::<f32>(), TypeId::of::<f64>()].contains(&TypeId::of::<T>())
+}
+
+pub(crate) fn exp<T: 'static>(xs: &mut [T]) {
+    dispatch!(T, S => <S as Simd>::exp(cast_mut::<T, S>(xs)))
+}
+
+pub(crate) fn elem_mul<L: 'static, R: 'static>(xs: &mut [L], ys: &[R]) {
+    dispatch!(L, S => <S as Simd>::zip(cast_mut::<L, S>(xs), cast_ref::<R, S>(ys), BinOp::Mul))
+}
+
+pub(crate) fn broadcast<T: 'static>(xs: &mut [T], op: BinOp, y: T, swap: bool) {
+    dispatch!(T, S => <S as Simd>::broadcast(cast_mut::<T, S>(xs), op, cast_ref(&[y])[0], swap))
+}
+
+/// Whether an `m x k` by `k x n` product of `T` is large enough to be computed by gemm.
+pub(crate) fn gemm_supports<T: 'static>(m: usize, k: usize, n: usize) -> bool {
+    let types = [
+        TypeId::of::<f32>(),
+        TypeId::of::<f64>(),
+        TypeId::of::<Complex<f32>>(),
+        TypeId::of::<Complex<f64>>(),
+    ];
+    m * k * n >= GEMM_THRESHOLD && types.contains(&TypeId::of::<T>())
+}
+
+/// Writes the product `l * r` to `dst`, for a `T` that [`gemm_supports`].
+pub(crate) fn matmul<T, R1: Dim, C1: Dim, R2: Dim, C2: Dim>(
+    l: &OMatrix<T, R1, C1>,
+    r: &OMatrix<T, R2, C2>,
+    dst: &mut OMatrix<T, R1, C2>,
+) where
+    T: nalgebra::Scalar + Scalar,
+    DefaultAllocator: Allocator<T, R1, C1> + Allocator<T, R2, C2> + Allocator<T, R1, C2>,
+{
+    let ((m, k), n) = (l.shape(), r.ncols());
+    assert_eq!((k, dst.shape()), (r.nrows(), (m, n)));
+    // SAFETY: the matrices are contiguous and column-major with the given shapes, and gemm
+    // supports `T`.
+    unsafe {
+        gemm::gemm(
+            m,
+            n,
+            k,
+            dst.as_mut_ptr(),
+            m as isize,
+            1,
+            false,
+            l.as_ptr(),
+            m as isize,
+            1,
+            r.as_ptr(),
+            k as isize,
+            1,
+            T::from(Zero),
+            T::from(One),
+            false,
+            false,
+            false,
+            gemm::Parallelism::None,
+        )
+    };
+}
+
+#[test]
+fn kernels() {
+    use crate::prelude::*;
+    use crate::primitive_ops::{ElemMul, Exp};
+    use crate::test::{assert_close, fixture};
+
+    // Not a multiple of the lanes, and large enough for gemm.
+    let a = fixture(67, 45, 1.);
+    let b = DMatrix::<f64>::from_fn(67, 45, |i, j| ((i * 45 + j) as f64).cos());
+    let c = fixture(45, 33, 0.5);
+    let close = |x: MatrixNode<f64>, y: DMatrix<f64>| {
+        let x = x.into_dense().unwrap();
+        assert_eq!(x.shape(), y.shape());
+        assert_close(x.as_slice(), y.as_slice());
+    };
+
+    close(mat(a.clone()).exp(), a.map(f64::exp));
+    close(mat(a.clone()).elem_mul(mat(b.clone())), a.component_mul(&b));
+    close(mat(a.clone()) - NodeValue(3.), a.map(|x| x - 3.));
+    close(NodeValue(3.) / mat(b.clone()), b.map(|x| 3. / x));
+    close(mat(a.clone()) * mat(c.clone()), &a * &c);
+    assert!(!supports::<i32>() && !supports::<Complex<f64>>());
+
+    let z = DMatrix::from_fn(40, 40, |i, j| Complex::new(i as f32, j as f32 - 20.) / 40.);
+    let p = (mat(z.clone()) * mat(z.clone())).into_dense().unwrap();
+    assert!((p - &z * &z).iter().all(|e| e.norm_sqr() < 1e-8));
+}
@@ -1,4 +1,4 @@
-#![feature(test)]
+#![cfg_attr(test, feature(test))]
 
 //! A library for automatic differentiation. [![Rust](https://github.com/unic0rn9k/autodiff/actions/workflows/rust.yml/badge.svg)](https://github.com/unic0rn9k/autodiff/actions/workflows/rust.yml)
 //!
@@ -21,6 +21,26 @@
 use std::fmt::Debug;
 mod symbol;
 
+pub mod backend;
+pub mod codegen;
+pub mod compile;
+pub mod custom;
+pub mod decompose;
+pub mod dual;
+pub mod error;
+pub mod indexing;
+#[cfg(feature = "cranelift")]
+pub mod jit;
+pub mod linalg;
+#[cfg(feature = "rayon")]
+pub mod par;
+pub mod print;
+pub mod shape;
+pub mod tensor;
+pub mod workspace;
+
+#[cfg(feature = "simd")]
+mod kernels;
 mod mat;
 pub mod prelude;
 pub mod primitive_ops;
@@ -28,6 +48,7 @@ pub mod primitive_ops;
 mod test;
 mod value;
 use ops::Transpose;
+use shape::{Shape, ShapeError};
 pub use symbol::{symbol, Symbol};
 pub mod ops;
 
@@ -40,14 +61,33 @@ impl<N: Debug> Debug for Node<N> {
     }
 }
 
-impl<'a, N: Differentiable<'a>> Differentiable<'a> for Node<N> {
-    type Δ<D> = N::Δ<D> where Self: 'a;
+impl<N: Eval> Eval for Node<N> {
     type T = N::T;
 
-    fn eval(&self) -> Self::T {
-        self.0.eval()
+    fn try_eval(&self) -> error::Result<Self::T> {
+        self.0.try_eval()
+    }
+
+    fn with_value<R>(&self, f: impl FnOnce(&Self::T) -> R) -> error::Result<R> {
+        self.0.with_value(f)
+    }
+
+    fn forget(&self) {
+        self.0.forget()
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        self.0.infer_shape()
     }
 
+    fn symbols(&self) -> Vec<&'static str> {
+        self.0.symbols()
+    }
+}
+
+impl<'a, N: Differentiable<'a>> Differentiable<'a> for Node<N> {
+    type Δ<D> = N::Δ<D> where Self: 'a;
+
     fn derivative<'d, const LEN: usize, D: Clone>(
         &'a self,
         k: [&str; LEN],
@@ -67,17 +107,70 @@ impl<'a, N: Differentiable<'a>> Node<N> {
     }
 }
 
-pub trait Differentiable<'a> {
+/// Evaluation of a node, kept apart from [`Differentiable`] so that the output type of an
+/// expression does not depend on the lifetime of its derivative.
+pub trait Eval {
+    type T;
+
+    /// Evaluates the node, returning an error instead of panicking if the shapes of its operands
+    /// don't match, or if an op is undefined for their values.
+    fn try_eval(&self) -> error::Result<Self::T>;
+
+    /// Evaluates the node. Panics where [`Eval::try_eval`] returns an error.
+    fn eval(&self) -> Self::T {
+        self.try_eval().unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Calls `f` with the value of this node. Nodes that hold their value, such as matrices,
+    /// symbols and cached nodes, lend it instead of copying it.
+    fn with_value<R>(&self, f: impl FnOnce(&Self::T) -> R) -> error::Result<R> {
+        Ok(f(&self.try_eval()?))
+    }
+
+    /// Drops the values cached by [`Cached`](crate::ops::Cached) and
+    /// [`Checkpoint`](crate::ops::Checkpoint) nodes in this expression, e.g. after a symbol has
+    /// been updated.
+    fn forget(&self) {}
+
+    /// Infers the shape of the value of this node without evaluating it.
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        Ok(Shape::Unknown)
+    }
+
+    /// Names of the symbols in this expression.
+    fn symbols(&self) -> Vec<&'static str> {
+        vec![]
+    }
+}
+
+pub trait Differentiable<'a>: Eval {
     type Δ<D>
     where
         Self: 'a;
-    type T;
-
-    fn eval(&self) -> Self::T;
 
     fn derivative<const LEN: usize, D: Clone>(&'a self, k: [&str; LEN], d: D)
         -> [Self::Δ<D>; LEN];
 
+    /// Evaluates the derivatives with respect to `k`, returning an error instead of panicking.
+    fn try_derivative<const LEN: usize, D: Clone>(
+        &'a self,
+        k: [&str; LEN],
+        d: D,
+    ) -> error::Result<[<Self::Δ<D> as Eval>::T; LEN]>
+    where
+        Self::Δ<D>: Eval,
+    {
+        self.infer_shape()?;
+        let mut values = Vec::with_capacity(LEN);
+        for delta in self.derivative(k, d) {
+            values.push(delta.try_eval()?);
+        }
+        match values.try_into() {
+            Ok(values) => Ok(values),
+            Err(_) => unreachable!(),
+        }
+    }
+
     fn symbol(self, symbol: &'static str) -> Node<Symbol<Self>>
     where
         Self: Sized + 'a,
@@ -97,15 +190,52 @@ pub trait Differentiable<'a> {
     fn exp(&self) -> Node<crate::ops::Exp<&Self>> {
         Node(crate::ops::Exp(self))
     }
+
+    fn detach(&self) -> Node<crate::ops::Detach<&Self>> {
+        Node(crate::ops::Detach(self))
+    }
+
+    fn cached(self) -> Node<crate::ops::Cached<Self>>
+    where
+        Self: Sized,
+    {
+        Node(crate::ops::Cached::new(self))
+    }
+
+    fn checkpoint(self) -> Node<crate::ops::Checkpoint<Self>>
+    where
+        Self: Sized,
+    {
+        Node(crate::ops::Checkpoint::new(self))
+    }
 }
 
-impl<'a, N: Differentiable<'a>> Differentiable<'a> for &N {
-    type Δ<D> = N::Δ<D> where Self: 'a;
+impl<N: Eval> Eval for &N {
     type T = N::T;
 
-    fn eval(&self) -> Self::T {
-        (*self).eval()
+    fn try_eval(&self) -> error::Result<Self::T> {
+        (*self).try_eval()
+    }
+
+    fn with_value<R>(&self, f: impl FnOnce(&Self::T) -> R) -> error::Result<R> {
+        (*self).with_value(f)
+    }
+
+    fn forget(&self) {
+        (*self).forget()
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        (*self).infer_shape()
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        (*self).symbols()
     }
+}
+
+impl<'a, N: Differentiable<'a>> Differentiable<'a> for &N {
+    type Δ<D> = N::Δ<D> where Self: 'a;
 
     fn derivative<'d, const LEN: usize, D: Clone>(
         &'a self,
@@ -161,7 +291,8 @@ fn softmax() {
     delta_softmax(&mut x_src);
     let dsoftmax_true = nalgebra::DMatrix::from_column_slice(x_src.len(), 1, &x_src);
 
-    assert!((dsoftmax.eval().0.unwrap() - dsoftmax_true).abs().sum() < 1e-6);
+    let dsoftmax = dsoftmax.eval().into_dense().unwrap();
+    assert!((dsoftmax - dsoftmax_true).abs().sum() < 1e-6);
 }
 
 #[test]
@@ -180,8 +311,8 @@ fn exp() {
 
     let [dexp2] = exp.derivative(["x"], 1f32);
     assert_eq!(
-        dexp.eval().0.unwrap().as_slice(),
-        dexp2.eval().0.unwrap().as_slice()
+        dexp.eval().into_dense().unwrap().as_slice(),
+        dexp2.eval().into_dense().unwrap().as_slice()
     )
 }
 
@@ -206,3 +337,100 @@ fn div() {
     assert!(dxv - 0.2222222 < 1e-6);
     assert!(dxy - 0.1111111 < 1e-6);
 }
+
+#[test]
+fn detach() {
+    use crate::prelude::*;
+
+    let x = 3f32.symbol("x");
+    let y = 2f32.symbol("y");
+
+    let f = &x * x.detach() + y.detach();
+    let [dx, dy] = f.derivative(["x", "y"], 1f32);
+
+    assert_eq!(f.eval().0, 11.);
+    assert_eq!(dx.eval().0, 3.);
+    assert!(dy.is_zero());
+}
+
+#[test]
+fn second_order() {
+    use crate::prelude::*;
+
+    let x = 3f32.symbol("x");
+
+    let cube = &x * &x * &x;
+    let [dcube] = cube.derivative(["x"], 1f32);
+    let [ddcube] = dcube.derivative(["x"], 1f32);
+    assert_eq!(dcube.eval().0, 27.);
+    assert_eq!(ddcube.eval().0, 18.);
+
+    let exp = x.exp();
+    let [dexp] = exp.derivative(["x"], 1f32);
+    let [ddexp] = dexp.derivative(["x"], 1f32);
+    assert_eq!(ddexp.eval().0, 3f32.exp());
+}
+
+#[test]
+fn second_order_div() {
+    use crate::prelude::*;
+
+    let x = 1f32.symbol("x");
+    let y = 2f32.symbol("y");
+
+    let f1 = &x / (&y + &x);
+    let f2 = &y / (&y + &x);
+    let [dx] = f1.derivative(["x"], 1f32);
+    let [dy] = f2.derivative(["y"], 1f32);
+    let [ddx, dxdy] = dx.derivative(["x", "y"], 1f32);
+    let [ddy] = dy.derivative(["y"], 1f32);
+
+    // -2y/(x+y)^3, (x-y)/(x+y)^3 and -2x/(x+y)^3
+    assert!((ddx.eval().0 + 4. / 27.).abs() < 1e-6);
+    assert!((dxdy.eval().0 + 1. / 27.).abs() < 1e-6);
+    assert!((ddy.eval().0 + 2. / 27.).abs() < 1e-6);
+}
+
+#[test]
+fn complex() {
+    use crate::{ops, prelude::*};
+
+    let z = Complex::new(1f64, 2.).symbol("z");
+    let w = Complex::new(3f64, -1.);
+    let close = |a: Complex<f64>, b: Complex<f64>| (a - b).norm_sqr() < 1e-24;
+
+    // Holomorphic functions get the conjugate of their derivative.
+    let f = &z * w;
+    let [dz] = f.derivative(["z"], One);
+    assert!(close(dz.eval().0, w.conj()));
+    let f = &z / w;
+    let [dz] = f.derivative(["z"], One);
+    assert!(close(dz.eval().0, (Complex::new(1., 0.) / w).conj()));
+    let f = z.exp();
+    let [dz] = f.derivative(["z"], One);
+    assert!(close(
+        dz.eval().0,
+        nalgebra::ComplexField::exp(z.eval().0).conj()
+    ));
+
+    // |z|^2 is real, with gradient 2z.
+    let f = ops::ElemMul(&z, Conj(&z));
+    let [dz] = f.derivative(["z"], One);
+    assert!(close(dz.eval().0, z.eval().0 * 2.));
+
+    // |Wx|^2, with gradient 2 W x x^H.
+    let c = |re: f64, im: f64| Complex::new(re, im);
+    let wm = DMatrix::from_row_slice(2, 2, &[c(1., 1.), c(0., -2.), c(0.5, 0.), c(-1., 3.)]);
+    let xm = DMatrix::from_row_slice(2, 1, &[c(2., -1.), c(0., 1.)]);
+    let wn = mat(wm.clone()).symbol("w");
+    let x = mat(xm.clone());
+    let y = &wn * &x;
+    let f = Sum(ops::ElemMul(&y, Conj(&y)));
+    let [dw] = f.derivative(["w"], One);
+    let expected = &wm * &xm * xm.adjoint() * c(2., 0.);
+    assert!((dw.eval().into_dense().unwrap() - expected).norm() < 1e-12);
+    assert!(close(f.eval().0, c((&wm * &xm).norm_squared(), 0.)));
+
+    assert_eq!(Adjoint(&wn).eval().into_dense().unwrap(), wm.adjoint());
+    assert_eq!(Adjoint(&wn).infer_shape(), Ok(Shape::Matrix(2, 2)));
+}
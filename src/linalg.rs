@@ -0,0 +1,679 @@
+//! Linear-algebra ops on square matrices, built on nalgebra's decompositions.
+//!
+//! Their gradients are expressions of the same ops, e.g. the gradient of [`Inverse`] multiplies
+//! the gradient of its output by the inverse again, so they can be differentiated again.
+
+use crate::error::{self, Error};
+use crate::indexing::{check_grad, shape_of, Element, IntoMatrix};
+use crate::mat::{mat, MatrixNode};
+use crate::ops::{zip_map, Add, ElemMul, Mul, Neg, Sub, Summable, Transpose};
+use crate::shape::{self, Shape, ShapeError};
+use crate::value::{Atom, NodeValue, One, Zero};
+use crate::{Differentiable, Eval};
+use nalgebra::DMatrix;
+use std::cmp::Ordering;
+
+/// Elements of matrices that linear-algebra ops can be applied to, i.e. `f32` and `f64`.
+pub trait Real: Element + nalgebra::RealField {}
+
+impl<T: Element + nalgebra::RealField> Real for T {}
+
+/// A unary linear-algebra op, for computing its value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rule {
+    Inverse,
+    Det,
+    LogDet,
+    Trace,
+    Cholesky,
+    Cofactor,
+}
+
+impl Rule {
+    fn name(self) -> &'static str {
+        match self {
+            Rule::Inverse => "Inverse",
+            Rule::Det => "Det",
+            Rule::LogDet => "LogDet",
+            Rule::Trace => "Trace",
+            Rule::Cholesky => "Cholesky",
+            Rule::Cofactor => "Cofactor",
+        }
+    }
+
+    fn infer_shape(self, n: &impl Eval) -> Result<Shape, ShapeError> {
+        match (self, n.infer_shape()?) {
+            (
+                Rule::Inverse | Rule::Cholesky | Rule::Cofactor,
+                s @ (Shape::Matrix(..) | Shape::Unknown),
+            ) => square(self.name(), n, s),
+            (_, s @ (Shape::Matrix(..) | Shape::Unknown)) => {
+                square(self.name(), n, s).map(|_| Shape::Scalar)
+            }
+            (_, s) => Err(ShapeError::new(self.name(), vec![s], n.symbols())),
+        }
+    }
+
+    fn matrix<T: Real>(self, n: &impl Eval, a: MatrixNode<T>) -> error::Result<MatrixNode<T>> {
+        if let (Rule::Inverse | Rule::Cholesky | Rule::Cofactor, MatrixNode::Identity(n)) =
+            (self, &a)
+        {
+            return Ok(MatrixNode::Identity(*n));
+        }
+        let a = square_input(self.name(), n, a)?;
+        Ok(mat(match self {
+            Rule::Inverse => inverse(a)?,
+            Rule::Cholesky => cholesky(a)?,
+            Rule::Cofactor => cofactor(a),
+            _ => unreachable!(),
+        }))
+    }
+
+    fn scalar<T: Real>(self, n: &impl Eval, a: MatrixNode<T>) -> error::Result<NodeValue<T>> {
+        let a = square_input(self.name(), n, a)?;
+        Ok(NodeValue(match self {
+            Rule::Det => a.determinant(),
+            // The sum of the logs of the pivots of the LU decomposition.
+            Rule::LogDet => a.lu().u().diagonal().map(|u| u.abs().ln()).sum(),
+            Rule::Trace => a.trace(),
+            _ => unreachable!(),
+        }))
+    }
+}
+
+/// The input `a` of `op` as a dense matrix.
+pub(crate) fn dense_input<T: Real>(
+    op: &'static str,
+    a: MatrixNode<T>,
+) -> error::Result<DMatrix<T>> {
+    a.into_dense().ok_or_else(|| {
+        Error::Value(format!(
+            "{op} cannot be applied to a zero matrix without a shape"
+        ))
+    })
+}
+
+/// The value `a` of `n` as a dense square matrix.
+fn square_input<T: Real>(
+    op: &'static str,
+    n: &impl Eval,
+    a: MatrixNode<T>,
+) -> error::Result<DMatrix<T>> {
+    let a = dense_input(op, a)?;
+    if !a.is_square() {
+        let s = Shape::Matrix(a.nrows(), a.ncols());
+        return Err(ShapeError::new(op, vec![s], n.symbols()).into());
+    }
+    Ok(a)
+}
+
+fn square(op: &'static str, n: &impl Eval, s: Shape) -> Result<Shape, ShapeError> {
+    match s {
+        Shape::Matrix(r, c) if r != c => Err(ShapeError::new(op, vec![s], n.symbols())),
+        s => Ok(s),
+    }
+}
+
+pub(crate) fn inverse<T: Real>(a: DMatrix<T>) -> error::Result<DMatrix<T>> {
+    a.try_inverse()
+        .ok_or_else(|| Error::Value("Inverse cannot be applied to a singular matrix".into()))
+}
+
+fn cholesky<T: Real>(a: DMatrix<T>) -> error::Result<DMatrix<T>> {
+    a.cholesky().map(|c| c.l()).ok_or_else(|| {
+        Error::Value("Cholesky can only be applied to a positive definite matrix".into())
+    })
+}
+
+/// `det(A) A^-T` from the SVD `A = U S V^T`, as `det(U) det(V) U adj(S) V^T`, where the diagonal
+/// `adj(S)` holds the products of all singular values but one.
+fn cofactor<T: Real>(a: DMatrix<T>) -> DMatrix<T> {
+    let n = a.nrows();
+    let svd = a.svd(true, true);
+    let (Some(u), Some(v_t)) = (svd.u, svd.v_t) else {
+        unreachable!()
+    };
+    let s = svd.singular_values;
+    let adj = DMatrix::from_fn(n, n, |i, j| match i == j {
+        true => (0..n)
+            .filter(|&k| k != i)
+            .fold(T::from(One), |p, k| p * s[k]),
+        false => T::from(Zero),
+    });
+    let sign = u.determinant() * v_t.determinant();
+    u * adj * v_t * sign
+}
+
+macro_rules! impl_rule {
+    ($($Op:ident: $output:ident => $f:ident;)*) => {$(
+        impl<T: Real, N: Eval<T = MatrixNode<T>>> Eval for $Op<N> {
+            type T = $output<T>;
+
+            fn try_eval(&self) -> error::Result<Self::T> {
+                Rule::$Op.$f(&self.0, self.0.try_eval()?)
+            }
+
+            fn forget(&self) {
+                self.0.forget()
+            }
+
+            fn infer_shape(&self) -> Result<Shape, ShapeError> {
+                Rule::$Op.infer_shape(&self.0)
+            }
+
+            fn symbols(&self) -> Vec<&'static str> {
+                self.0.symbols()
+            }
+        }
+    )*};
+}
+
+/// The inverse of a square matrix. Fails to evaluate if it is singular.
+#[derive(Clone, Debug)]
+pub struct Inverse<N>(pub N);
+
+/// The determinant of a square matrix.
+#[derive(Clone, Debug)]
+pub struct Det<N>(pub N);
+
+/// The log of the absolute value of the determinant of a square matrix, which doesn't overflow
+/// for large matrices like the determinant does.
+#[derive(Clone, Debug)]
+pub struct LogDet<N>(pub N);
+
+/// The sum of the diagonal of a square matrix.
+#[derive(Clone, Debug)]
+pub struct Trace<N>(pub N);
+
+/// The lower triangular `L` with `L L^T = A`, for a symmetric positive definite `A`. Only the
+/// lower triangle of `A` is read, and the gradient is symmetric.
+#[derive(Clone, Debug)]
+pub struct Cholesky<N>(pub N);
+
+/// The cofactor matrix `det(A) A^-T` of a square matrix, i.e. the gradient of its determinant,
+/// which is also defined if it is singular. Its own gradient fails to evaluate if it is.
+#[derive(Clone, Debug)]
+pub struct Cofactor<N>(pub N);
+
+impl_rule!(
+    Inverse: MatrixNode => matrix;
+    Det: NodeValue => scalar;
+    LogDet: NodeValue => scalar;
+    Trace: NodeValue => scalar;
+    Cholesky: MatrixNode => matrix;
+    Cofactor: MatrixNode => matrix;
+);
+
+macro_rules! impl_grad {
+    ($($Op:ident => |$n:ident, $d:ident| $grad:expr, $Δ:ty;)*) => {$(
+        impl<'a, N: Differentiable<'a> + 'a> Differentiable<'a> for $Op<N>
+        where
+            Self: Eval,
+        {
+            type Δ<D> = N::Δ<$Δ> where Self: 'a;
+
+            fn derivative<const LEN: usize, D: Clone>(
+                &'a self,
+                k: [&str; LEN],
+                d: D,
+            ) -> [Self::Δ<D>; LEN] {
+                let ($n, $d) = (self, d);
+                self.0.derivative(k, $grad)
+            }
+
+            fn is_zero(&self) -> bool {
+                false
+            }
+        }
+    )*};
+}
+
+/// `L^-T Φ(L^T D) L^-1` for the factor `L` of a [`Cholesky`] decomposition, where `Φ` takes the
+/// lower triangle and halves the diagonal. The gradient of `A` is its symmetric part.
+type CholeskyGrad<'a, N, D> = Mul<
+    Mul<
+        Transpose<Inverse<&'a Cholesky<N>>>,
+        ElemMul<Constant<&'a N>, Mul<Transpose<&'a Cholesky<N>>, Broadcast<'a, Cholesky<N>, D>>>,
+    >,
+    Inverse<&'a Cholesky<N>>,
+>;
+
+/// `A^-1 D` for the gradient `D` of a [`Cofactor`] of `A`.
+type CofactorGrad<'a, N, D> = Mul<Inverse<&'a N>, Broadcast<'a, Cofactor<N>, D>>;
+
+impl_grad!(
+    // Ā = -Y^T D Y^T
+    Inverse => |y, d| Neg(Mul(Mul(Transpose(y), Broadcast(d, y)), Transpose(y))),
+        Neg<Mul<Mul<Transpose<&'a Self>, Broadcast<'a, Self, D>>, Transpose<&'a Self>>>;
+    // Ā = d cof(A)
+    Det => |n, d| ElemMul(Broadcast(d, &n.0), Cofactor(&n.0)),
+        ElemMul<Broadcast<'a, N, D>, Cofactor<&'a N>>;
+    // Ā = d A^-T
+    LogDet => |n, d| ElemMul(Broadcast(d, &n.0), Transpose(Inverse(&n.0))),
+        ElemMul<Broadcast<'a, N, D>, Transpose<Inverse<&'a N>>>;
+    Trace => |n, d| ElemMul(Broadcast(d, &n.0), Constant(&n.0, Pattern::Identity)),
+        ElemMul<Broadcast<'a, N, D>, Constant<&'a N>>;
+    // Ā = sym(L^-T Φ(L^T D) L^-1)
+    Cholesky => |l, d| {
+        let phi = ElemMul(Constant(&l.0, Pattern::LowerHalf), Mul(Transpose(l), Broadcast(d, l)));
+        let s = Mul(Mul(Transpose(Inverse(l)), phi), Inverse(l));
+        Mul(Atom::ratio(1, 2).unwrap(), Add(s.clone(), Transpose(s)))
+    },
+        Mul<Atom, Add<CholeskyGrad<'a, N, D>, Transpose<CholeskyGrad<'a, N, D>>>>;
+    // With C = cof(A), Ā = tr(A^-1 D) C - C D^T A^-T
+    Cofactor => |c, d| Sub(
+        ElemMul(Broadcast(Trace(Mul(Inverse(&c.0), Broadcast(d.clone(), c))), c), c),
+        Mul(Mul(c, Transpose(Broadcast(d, c))), Transpose(Inverse(&c.0))),
+    ),
+        Sub<
+            ElemMul<Broadcast<'a, Self, Trace<CofactorGrad<'a, N, D>>>, &'a Self>,
+            Mul<Mul<&'a Self, Transpose<Broadcast<'a, Self, D>>>, Transpose<Inverse<&'a N>>>,
+        >;
+);
+
+/// A constant matrix of the shape of a square matrix `N`, which is only evaluated if its shape
+/// can't be inferred.
+#[derive(Clone, Debug)]
+pub struct Constant<N>(pub N, pub Pattern);
+
+/// The elements of a [`Constant`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pattern {
+    Identity,
+    /// Ones below the diagonal and halves on it, which take the lower triangle of a matrix and
+    /// halve its diagonal when multiplied elementwise.
+    LowerHalf,
+}
+
+impl<T: Real, N: Eval<T = MatrixNode<T>>> Eval for Constant<N> {
+    type T = MatrixNode<T>;
+
+    fn try_eval(&self) -> error::Result<Self::T> {
+        let Some((n, _)) = shape_of(&self.0)? else {
+            return Ok(MatrixNode::Zero(None));
+        };
+        Ok(match self.1 {
+            Pattern::Identity => MatrixNode::Identity(n),
+            Pattern::LowerHalf => mat(DMatrix::from_fn(n, n, |i, j| match i.cmp(&j) {
+                Ordering::Greater => T::from(One),
+                Ordering::Equal => T::from(Atom::ratio(1, 2).unwrap()),
+                Ordering::Less => T::from(Zero),
+            })),
+        })
+    }
+
+    fn forget(&self) {
+        self.0.forget()
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        self.0.infer_shape()
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        self.0.symbols()
+    }
+}
+
+impl<'a, N> Differentiable<'a> for Constant<N>
+where
+    Self: Eval,
+{
+    type Δ<D> = Atom where Self: 'a;
+
+    fn derivative<const LEN: usize, D>(&'a self, _: [&str; LEN], _: D) -> [Self::Δ<D>; LEN] {
+        [Zero; LEN]
+    }
+
+    fn is_zero(&self) -> bool {
+        false
+    }
+}
+
+/// The gradient `D` of an output, with scalars broadcast to the shape of `N`.
+#[derive(Debug)]
+pub struct Broadcast<'a, N, D>(pub D, pub &'a N);
+
+impl<N, D: Clone> Clone for Broadcast<'_, N, D> {
+    fn clone(&self) -> Self {
+        Broadcast(self.0.clone(), self.1)
+    }
+}
+
+impl<T: Real, N: Eval<T = MatrixNode<T>>, D: Eval> Eval for Broadcast<'_, N, D>
+where
+    D::T: IntoMatrix<T>,
+{
+    type T = MatrixNode<T>;
+
+    fn try_eval(&self) -> error::Result<Self::T> {
+        let Some((r, c)) = shape_of(self.1)? else {
+            return Ok(MatrixNode::Zero(None));
+        };
+        let d = self.0.try_eval()?.into_matrix(r, c);
+        check_grad("Broadcast", &d, (r, c), || self.symbols())?;
+        Ok(d)
+    }
+
+    fn forget(&self) {
+        self.0.forget();
+        self.1.forget();
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        let (d, s) = (self.0.infer_shape()?, self.1.infer_shape()?);
+        match d.elementwise(s) {
+            Some(_) => Ok(s),
+            None => Err(ShapeError::new("Broadcast", vec![d, s], self.symbols())),
+        }
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        [self.0.symbols(), self.1.symbols()].concat()
+    }
+}
+
+impl<'a, 'b, N, D: Differentiable<'b>> Differentiable<'b> for Broadcast<'a, N, D>
+where
+    Self: Eval,
+{
+    type Δ<G> = D::Δ<Reduce<'b, N, D, G>> where Self: 'b;
+
+    fn derivative<const LEN: usize, G: Clone>(
+        &'b self,
+        k: [&str; LEN],
+        g: G,
+    ) -> [Self::Δ<G>; LEN] {
+        self.0.derivative(k, Reduce(g, self.1, &self.0))
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+}
+
+/// The gradient `G` of a [`Broadcast`] of `D` to the shape of `N`, summed to a 1x1 matrix if
+/// `D` is a scalar or a 1x1 matrix. It is the adjoint of broadcasting.
+#[derive(Debug)]
+pub struct Reduce<'a, N, D, G>(pub G, pub &'a N, pub &'a D);
+
+impl<N, D, G: Clone> Clone for Reduce<'_, N, D, G> {
+    fn clone(&self) -> Self {
+        Reduce(self.0.clone(), self.1, self.2)
+    }
+}
+
+impl<T: Real, N: Eval<T = MatrixNode<T>>, D: Eval<T: Eval>, G: Eval> Eval for Reduce<'_, N, D, G>
+where
+    G::T: IntoMatrix<T>,
+{
+    type T = MatrixNode<T>;
+
+    fn try_eval(&self) -> error::Result<Self::T> {
+        let Some((r, c)) = shape_of(self.1)? else {
+            return Ok(MatrixNode::Zero(None));
+        };
+        let g = self.0.try_eval()?.into_matrix(r, c);
+        check_grad("Reduce", &g, (r, c), || self.symbols())?;
+        let d = match self.2.infer_shape()? {
+            Shape::Unknown => self.2.with_value(Eval::infer_shape)??,
+            s => s,
+        };
+        Ok(match matches!(d, Shape::Scalar | Shape::Matrix(1, 1)) {
+            true => MatrixNode::fill(1, 1, g.sum_().0),
+            false => g,
+        })
+    }
+
+    fn forget(&self) {
+        self.0.forget();
+        self.1.forget();
+        self.2.forget();
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        let (g, s, d) = (
+            self.0.infer_shape()?,
+            self.1.infer_shape()?,
+            self.2.infer_shape()?,
+        );
+        match (g.elementwise(s), d) {
+            (Some(_), Shape::Scalar | Shape::Matrix(1, 1)) => Ok(Shape::Matrix(1, 1)),
+            (Some(_), Shape::Matrix(..)) => Ok(s),
+            (Some(_), _) => Ok(Shape::Unknown),
+            (None, _) => Err(ShapeError::new("Reduce", vec![g, s], self.symbols())),
+        }
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        [self.0.symbols(), self.1.symbols()].concat()
+    }
+}
+
+impl<'a, 'b, N, D, G: Differentiable<'b>> Differentiable<'b> for Reduce<'a, N, D, G>
+where
+    Self: Eval,
+{
+    type Δ<H> = G::Δ<Broadcast<'a, N, H>> where Self: 'b;
+
+    fn derivative<const LEN: usize, H: Clone>(
+        &'b self,
+        k: [&str; LEN],
+        h: H,
+    ) -> [Self::Δ<H>; LEN] {
+        self.0.derivative(k, Broadcast(h, self.1))
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+}
+
+/// `X` with `A X = B`, for a square `A`. Fails to evaluate if `A` is singular.
+#[derive(Clone, Debug)]
+pub struct Solve<A, B>(pub A, pub B);
+
+fn solve<T: Real>(a: &DMatrix<T>, b: &DMatrix<T>) -> error::Result<DMatrix<T>> {
+    a.clone()
+        .lu()
+        .solve(b)
+        .ok_or_else(|| Error::Value("Solve cannot be applied to a singular matrix".into()))
+}
+
+/// The values of `A` and `B` in [`Solve`]`(A, B)`, or `None` for `B` if it is a zero matrix
+/// without a shape.
+fn solve_inputs<T: Real>(
+    op: &'static str,
+    (a, b): (&impl Eval<T = MatrixNode<T>>, &impl Eval<T = MatrixNode<T>>),
+) -> error::Result<(DMatrix<T>, Option<DMatrix<T>>)> {
+    let av = square_input(op, a, a.try_eval()?)?;
+    let bv = b.try_eval()?;
+    let s = bv.infer_shape()?;
+    match bv.into_dense() {
+        Some(bv) if bv.nrows() != av.nrows() => {
+            let shapes = vec![Shape::Matrix(av.nrows(), av.ncols()), s];
+            Err(ShapeError::new(op, shapes, [a.symbols(), b.symbols()].concat()).into())
+        }
+        bv => Ok((av, bv)),
+    }
+}
+
+impl<T: Real, A: Eval<T = MatrixNode<T>>, B: Eval<T = MatrixNode<T>>> Eval for Solve<A, B> {
+    type T = MatrixNode<T>;
+
+    fn try_eval(&self) -> error::Result<Self::T> {
+        match solve_inputs("Solve", (&self.0, &self.1))? {
+            (a, Some(b)) => Ok(mat(solve(&a, &b)?)),
+            (_, None) => Ok(MatrixNode::Zero(None)),
+        }
+    }
+
+    fn forget(&self) {
+        self.0.forget();
+        self.1.forget();
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        shape::binary("Solve", &self.0, &self.1, |a, b| match (a, b) {
+            (Shape::Matrix(n, m), Shape::Matrix(r, c)) if n == m && m == r => {
+                Some(Shape::Matrix(r, c))
+            }
+            (Shape::Matrix(n, m), Shape::Unknown) if n == m => Some(Shape::Unknown),
+            (Shape::Unknown, s @ (Shape::Matrix(..) | Shape::Unknown)) => Some(s),
+            _ => None,
+        })
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        [self.0.symbols(), self.1.symbols()].concat()
+    }
+}
+
+/// `B̄ = A^-T D` in [`Solve`]`(A, B)`, with `Ā = -B̄ X^T`.
+type SolveGrad<'a, A, B, D> = Solve<Transpose<&'a A>, Broadcast<'a, Solve<A, B>, D>>;
+
+impl<'a, A: Differentiable<'a> + 'a, B: Differentiable<'a> + 'a> Differentiable<'a> for Solve<A, B>
+where
+    Self: Eval,
+{
+    type Δ<D> = Add<
+        A::Δ<Neg<Mul<SolveGrad<'a, A, B, D>, Transpose<&'a Self>>>>,
+        B::Δ<SolveGrad<'a, A, B, D>>,
+    > where Self: 'a;
+
+    fn derivative<const LEN: usize, D: Clone>(
+        &'a self,
+        k: [&str; LEN],
+        d: D,
+    ) -> [Self::Δ<D>; LEN] {
+        let db = Solve(Transpose(&self.0), Broadcast(d, self));
+        zip_map(
+            self.0.derivative(k, Neg(Mul(db.clone(), Transpose(self)))),
+            self.1.derivative(k, db),
+            Add,
+        )
+    }
+
+    fn is_zero(&self) -> bool {
+        self.1.is_zero()
+    }
+}
+
+/// Checks the gradient `f` returns for a matrix against central differences of its value.
+/// Symmetric perturbations are used for ops that only read a triangle of the matrix.
+#[cfg(test)]
+pub(crate) fn check_gradient(
+    a: &DMatrix<f64>,
+    symmetric: bool,
+    f: impl Fn(DMatrix<f64>) -> (f64, DMatrix<f64>),
+) {
+    let h = 1e-6;
+    let (_, grad) = f(a.clone());
+    for i in 0..a.nrows() {
+        for j in 0..a.ncols() {
+            let mut e = DMatrix::zeros(a.nrows(), a.ncols());
+            e[(i, j)] = h;
+            let mut expected = grad[(i, j)];
+            if symmetric && i != j {
+                e[(j, i)] = h;
+                expected += grad[(j, i)];
+            }
+            let numeric = (f(a + &e).0 - f(a - &e).0) / (2. * h);
+            assert!(
+                (numeric - expected).abs() < 1e-5,
+                "({i}, {j}): {numeric} != {expected}"
+            );
+        }
+    }
+}
+
+#[test]
+fn gradients() {
+    use crate::{ops, prelude::*};
+
+    let a = DMatrix::<f64>::from_row_slice(3, 3, &[4., 1., 0.5, 1., 3., 0.2, 0.5, 0.2, 2.]);
+    let b = DMatrix::from_row_slice(3, 2, &[1., -2., 0.5, 3., 2., 1.]);
+    let w = mat(DMatrix::from_fn(3, 3, |i, j| (i + 2 * j) as f64 / 3. - 1.));
+
+    assert!((Det(mat(a.clone())).eval().0 - a.determinant()).abs() < 1e-12);
+    assert!((LogDet(mat(a.clone())).eval().0 - a.determinant().ln()).abs() < 1e-12);
+    assert_eq!(Trace(mat(a.clone())).eval().0, 9.);
+    let l = Cholesky(mat(a.clone())).eval().into_dense().unwrap();
+    assert!((&l * l.transpose() - &a).abs().max() < 1e-12);
+    let x = Solve(mat(a.clone()), mat(b.clone()))
+        .eval()
+        .into_dense()
+        .unwrap();
+    assert!((&a * x - &b).abs().max() < 1e-12);
+
+    macro_rules! grad {
+        ($y:expr) => {
+            |m: DMatrix<f64>| {
+                let a = mat(m).symbol("a");
+                let y = $y(&a);
+                let [da] = y.derivative(["a"], One);
+                (y.eval().0, da.eval().into_dense().unwrap())
+            }
+        };
+    }
+
+    check_gradient(&a, false, grad!(|a| Sum(ops::ElemMul(Inverse(a), &w))));
+    check_gradient(&a, false, grad!(Det));
+    check_gradient(&a, false, grad!(LogDet));
+    check_gradient(&a, false, grad!(Trace));
+    check_gradient(&a, false, grad!(|a| Sum(ops::ElemMul(Cofactor(a), &w))));
+    check_gradient(&a, true, grad!(|a| Sum(ops::ElemMul(Cholesky(a), &w))));
+
+    let bm = mat(b.clone());
+    let wb = mat(DMatrix::from_fn(3, 2, |i, j| (i * 2 + j) as f64 - 2.));
+    check_gradient(&a, false, grad!(|a| Sum(ops::ElemMul(Solve(a, &bm), &wb))));
+    check_gradient(&b, false, |m| {
+        let x = mat(m).symbol("b");
+        let y = Sum(ops::ElemMul(Solve(mat(a.clone()), &x), &wb));
+        let [db] = y.derivative(["b"], One);
+        (y.eval().0, db.eval().into_dense().unwrap())
+    });
+
+    // The gradients are differentiated again through the gradient of `Sum(da * w)`.
+    macro_rules! grad2 {
+        ($y:expr) => {
+            |m: DMatrix<f64>| {
+                let a = mat(m).symbol("a");
+                let y = $y(&a);
+                let [da] = y.derivative(["a"], One);
+                let z = Sum(ops::ElemMul(&da, &w));
+                let [dda] = z.derivative(["a"], One);
+                (z.eval().0, dda.eval().into_dense().unwrap())
+            }
+        };
+    }
+
+    check_gradient(&a, false, grad2!(|a| Sum(ops::ElemMul(Inverse(a), &w))));
+    check_gradient(&a, false, grad2!(Det));
+    check_gradient(&a, false, grad2!(LogDet));
+    check_gradient(&a, false, grad2!(Cofactor));
+    check_gradient(&a, false, grad2!(|a| Sum(ops::ElemMul(Solve(a, &bm), &wb))));
+    let sym = |m: DMatrix<f64>| (&m + m.transpose()) / 2.;
+    check_gradient(&a, true, |m| {
+        grad2!(|a| Sum(ops::ElemMul(Cholesky(a), &w)))(sym(m))
+    });
+    let t = Trace(mat(a.clone()).symbol("a"));
+    let [da] = t.derivative(["a"], One);
+    let [dda] = Sum(ops::ElemMul(&da, &w)).derivative(["a"], One);
+    assert_eq!(dda.eval(), Zero);
+
+    // The gradient of the determinant of a singular matrix is its cofactor matrix.
+    let singular = DMatrix::from_row_slice(2, 2, &[1., 2., 2., 4.]);
+    let c = Cofactor(mat(singular)).eval().into_dense().unwrap();
+    let expected = DMatrix::from_row_slice(2, 2, &[4., -2., -2., 1.]);
+    assert!((c - expected).abs().max() < 1e-12);
+
+    let err = Inverse(mat(b.clone()).symbol("b"))
+        .infer_shape()
+        .unwrap_err();
+    assert_eq!((err.op, err.symbols), ("Inverse", vec!["b"]));
+    assert_eq!(
+        Solve(mat(a.clone()), mat(b.clone())).infer_shape(),
+        Ok(Shape::Matrix(3, 2))
+    );
+}
@@ -1,33 +1,300 @@
+use crate::backend::{Backend, Nalgebra};
+use crate::shape::{Shape, ShapeError};
 use crate::value::Atom;
-use crate::{prelude::*, value};
+use crate::workspace;
+use crate::{error, prelude::*, value};
 use nalgebra::allocator::Allocator;
-use nalgebra::{DefaultAllocator, Dyn, OMatrix};
+use nalgebra::constraint::{AreMultipliable, ShapeConstraint};
+use nalgebra::{ClosedAdd, ClosedMul, DMatrix, DefaultAllocator, Dim, Dyn, OMatrix};
+use std::any::{Any, TypeId};
 use std::fmt::Debug;
 use std::ops::*;
 
-#[derive(Clone)]
-pub struct MatrixNode<T>(pub Option<OMatrix<T, Dyn, Dyn>>)
+/// A matrix value, either dynamically sized or with static nalgebra dimensions such as
+/// `Const<3>`. Zero, constant and identity matrices are kept lazy, so they don't allocate until
+/// they are combined with a dense matrix.
+///
+/// Dense matrices are stored by the [`Backend`] `B`, which runs their kernels.
+pub enum MatrixNode<T: nalgebra::Scalar, R: Dim = Dyn, C: Dim = Dyn, B: Backend<T> = Nalgebra>
 where
-    DefaultAllocator: Allocator<T, Dyn, Dyn>;
+    DefaultAllocator: Allocator<T, R, C>,
+{
+    Dense(B::Storage<R, C>),
+    /// A zero matrix. The shape is `None` for the derivative with respect to a symbol that
+    /// doesn't appear in the expression.
+    Zero(Option<(usize, usize)>),
+    /// A `rows x cols` matrix with every element equal to the value.
+    Fill(usize, usize, T),
+    /// The `n x n` identity matrix.
+    Identity(usize),
+}
+
+/// Checks that `rows x cols` fits the static dimensions `R` and `C`, which a matrix built from
+/// its variants doesn't always do.
+fn check_dims<R: Dim, C: Dim>(rows: usize, cols: usize) -> Result<(), ShapeError> {
+    let (r, c) = (R::try_to_usize(), C::try_to_usize());
+    if r.is_none_or(|r| r == rows) && c.is_none_or(|c| c == cols) {
+        return Ok(());
+    }
+    let shapes = vec![
+        Shape::Matrix(rows, cols),
+        Shape::Matrix(r.unwrap_or(rows), c.unwrap_or(cols)),
+    ];
+    Err(ShapeError::new("Dims", shapes, vec![]))
+}
+
+/// Panics if `rows x cols` doesn't fit the static dimensions `R` and `C`.
+pub(crate) fn assert_dims<R: Dim, C: Dim>(rows: usize, cols: usize) {
+    if check_dims::<R, C>(rows, cols).is_err() {
+        panic!(
+            "{rows}x{cols} doesn't fit the dimensions {:?}x{:?}",
+            R::try_to_usize(),
+            C::try_to_usize()
+        );
+    }
+}
+
+/// `a` as a `B`, if they are the same type.
+fn cast<A: 'static, B: 'static>(a: A) -> Result<B, A> {
+    let mut a = Some(a);
+    match (&mut a as &mut dyn Any).downcast_mut::<Option<B>>() {
+        Some(b) => Ok(b.take().unwrap()),
+        None => Err(a.unwrap()),
+    }
+}
+
+/// An `r x c` matrix of `elems` in column-major order. Dynamically sized matrices take their
+/// buffer from the active [`Workspace`](workspace::Workspace).
+pub(crate) fn pooled<T: nalgebra::Scalar, R: Dim, C: Dim>(
+    r: usize,
+    c: usize,
+    elems: impl Iterator<Item = T>,
+) -> OMatrix<T, R, C>
+where
+    DefaultAllocator: Allocator<T, R, C>,
+{
+    if (TypeId::of::<R>(), TypeId::of::<C>()) != (TypeId::of::<Dyn>(), TypeId::of::<Dyn>()) {
+        return OMatrix::from_iterator_generic(R::from_usize(r), C::from_usize(c), elems);
+    }
+    let mut buffer = workspace::buffer(r * c);
+    buffer.extend(elems);
+    match cast(DMatrix::from_vec(r, c, buffer)) {
+        Ok(m) => m,
+        Err(_) => unreachable!(),
+    }
+}
 
-impl<T> MatrixNode<T>
+/// Returns the buffer of `m` to the active workspace, if it's dynamically sized.
+pub(crate) fn recycle<T: nalgebra::Scalar, R: Dim, C: Dim>(m: OMatrix<T, R, C>)
 where
-    DefaultAllocator: Allocator<T, Dyn, Dyn>,
+    DefaultAllocator: Allocator<T, R, C>,
+{
+    if let Ok(m) = cast::<_, DMatrix<T>>(m) {
+        workspace::recycle(Vec::from(m.data));
+    }
+}
+
+/// Clones with [`Backend::copy`], into a buffer from the active
+/// [`Workspace`](workspace::Workspace) for nalgebra.
+impl<T: nalgebra::Scalar, R: Dim, C: Dim, B: Backend<T>> Clone for MatrixNode<T, R, C, B>
+where
+    DefaultAllocator: Allocator<T, R, C>,
+{
+    fn clone(&self) -> Self {
+        match self {
+            MatrixNode::Dense(m) => MatrixNode::Dense(B::copy(m)),
+            MatrixNode::Zero(shape) => MatrixNode::Zero(*shape),
+            MatrixNode::Fill(r, c, v) => MatrixNode::Fill(*r, *c, v.clone()),
+            MatrixNode::Identity(n) => MatrixNode::Identity(*n),
+        }
+    }
+}
+
+impl<T: nalgebra::Scalar, R: Dim, C: Dim, B: Backend<T>> MatrixNode<T, R, C, B>
+where
+    DefaultAllocator: Allocator<T, R, C>,
 {
     pub fn shape(&self) -> Option<(usize, usize)> {
-        self.0.as_ref().map(|m| (m.nrows(), m.ncols()))
+        match self {
+            MatrixNode::Dense(m) => Some(B::shape(m)),
+            MatrixNode::Zero(shape) => *shape,
+            MatrixNode::Fill(r, c, _) => Some((*r, *c)),
+            MatrixNode::Identity(n) => Some((*n, *n)),
+        }
+    }
+
+    pub fn zeros(rows: usize, cols: usize) -> Self {
+        assert_dims::<R, C>(rows, cols);
+        MatrixNode::Zero(Some((rows, cols)))
+    }
+
+    pub fn identity(n: usize) -> Self {
+        assert_dims::<R, C>(n, n);
+        MatrixNode::Identity(n)
     }
 }
 
-impl<T> Debug for MatrixNode<T>
+impl<T: nalgebra::Scalar + crate::value::Scalar, R: Dim, C: Dim, B: Backend<T>>
+    MatrixNode<T, R, C, B>
 where
-    DefaultAllocator: Allocator<T, Dyn, Dyn>,
+    DefaultAllocator: Allocator<T, R, C>,
 {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if let Some((r, c)) = self.shape() {
-            write!(f, " [{r}x{c}] ")
+    pub fn fill(rows: usize, cols: usize, value: T) -> Self {
+        if value == T::from(Zero) {
+            MatrixNode::zeros(rows, cols)
         } else {
-            write!(f, "Zero")
+            assert_dims::<R, C>(rows, cols);
+            MatrixNode::Fill(rows, cols, value)
+        }
+    }
+
+    /// The element at row `i` and column `j`.
+    pub fn get(&self, i: usize, j: usize) -> T {
+        match self {
+            MatrixNode::Dense(m) => B::get(m, i, j),
+            MatrixNode::Zero(_) => T::from(Zero),
+            MatrixNode::Fill(_, _, v) => v.clone(),
+            MatrixNode::Identity(_) => T::from(if i == j { One } else { Zero }),
+        }
+    }
+
+    /// Allocates the matrix, or returns `None` for a zero matrix without a shape.
+    pub fn into_dense(self) -> Option<B::Storage<R, C>> {
+        match self {
+            MatrixNode::Dense(m) => Some(m),
+            m => m.shape().map(|(r, c)| B::from_fn(r, c, |i, j| m.get(i, j))),
+        }
+    }
+
+    /// The matrix as a mutable dense matrix, e.g. for updating a parameter in place.
+    pub fn dense_mut(&mut self) -> &mut B::Storage<R, C> {
+        if !matches!(self, MatrixNode::Dense(_)) {
+            let m = std::mem::replace(self, MatrixNode::Zero(None));
+            *self = MatrixNode::Dense(m.dense());
+        }
+        match self {
+            MatrixNode::Dense(m) => m,
+            _ => unreachable!(),
+        }
+    }
+
+    /// The same matrix with other dimensions, e.g. a dynamically sized gradient as the static
+    /// shape of its parameter. Panics if the shape doesn't fit.
+    ///
+    /// Only copies a dense matrix if the dimensions are different types.
+    pub fn into_dims<R2: Dim, C2: Dim>(self) -> MatrixNode<T, R2, C2, B>
+    where
+        DefaultAllocator: Allocator<T, R2, C2>,
+    {
+        if let Some((r, c)) = self.shape() {
+            assert_dims::<R2, C2>(r, c);
+        }
+        match self {
+            MatrixNode::Dense(m) => MatrixNode::Dense(cast(m).unwrap_or_else(|m| {
+                let (r, c) = B::shape(&m);
+                let copy = B::from_fn(r, c, |i, j| B::get(&m, i, j));
+                B::recycle(m);
+                copy
+            })),
+            MatrixNode::Zero(shape) => MatrixNode::Zero(shape),
+            MatrixNode::Fill(r, c, v) => MatrixNode::Fill(r, c, v),
+            MatrixNode::Identity(n) => MatrixNode::Identity(n),
+        }
+    }
+
+    /// Applies `f` to every element, in place for dense matrices and without allocating for lazy
+    /// ones.
+    pub fn map(self, f: impl Fn(T) -> T) -> Self {
+        match self {
+            MatrixNode::Dense(m) => MatrixNode::Dense(B::map(m, f)),
+            MatrixNode::Zero(None) => MatrixNode::Zero(None),
+            MatrixNode::Zero(Some((r, c))) => MatrixNode::fill(r, c, f(T::from(Zero))),
+            MatrixNode::Fill(r, c, v) => MatrixNode::fill(r, c, f(v)),
+            m => MatrixNode::Dense(m.dense()).map(f),
+        }
+    }
+
+    /// Returns the buffer of a dense, dynamically sized matrix to the active
+    /// [`Workspace`](workspace::Workspace), so it can be reused instead of dropped.
+    pub fn recycle(self) {
+        if let MatrixNode::Dense(m) = self {
+            B::recycle(m);
+        }
+    }
+
+    fn dense(self) -> B::Storage<R, C> {
+        self.into_dense().expect("zero matrix without a shape")
+    }
+
+    /// `f(x, y)` for the elements `x` of `self` and `y` of `rhs`, in the buffer of `self`. The
+    /// buffer of `rhs` is recycled.
+    fn zip_map(self, rhs: Self, f: impl Fn(T, T) -> T) -> Self {
+        MatrixNode::Dense(B::zip_map(self.dense(), rhs.dense(), f))
+    }
+
+    /// `x op y` for every element `x`, or `y op x` if `swap`.
+    pub(crate) fn broadcast(self, op: BinOp, y: T, swap: bool) -> Self
+    where
+        T: Add<Output = T> + Sub<Output = T> + Mul<Output = T> + Div<Output = T>,
+    {
+        match self {
+            #[cfg(feature = "simd")]
+            MatrixNode::Dense(mut m) if crate::kernels::supports::<T>() => {
+                crate::kernels::broadcast(B::as_mut_slice(&mut m), op, y, swap);
+                MatrixNode::Dense(m)
+            }
+            // A zero without a shape stands for a scalar when it is combined with one.
+            MatrixNode::Zero(None) => {
+                let zero = T::from(Zero);
+                let v = match swap {
+                    true => op.apply(y, zero.clone()),
+                    false => op.apply(zero.clone(), y),
+                };
+                match v == zero {
+                    true => MatrixNode::Zero(None),
+                    false => MatrixNode::fill(1, 1, v),
+                }
+            }
+            m if swap => m.map(|x| op.apply(y.clone(), x)),
+            m => m.map(|x| op.apply(x, y.clone())),
+        }
+    }
+}
+
+/// An arithmetic op between the elements of a matrix and a scalar.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl BinOp {
+    pub(crate) fn apply<T>(self, x: T, y: T) -> T
+    where
+        T: Add<Output = T> + Sub<Output = T> + Mul<Output = T> + Div<Output = T>,
+    {
+        match self {
+            BinOp::Add => x + y,
+            BinOp::Sub => x - y,
+            BinOp::Mul => x * y,
+            BinOp::Div => x / y,
+        }
+    }
+}
+
+impl<T: nalgebra::Scalar, R: Dim, C: Dim, B: Backend<T>> Debug for MatrixNode<T, R, C, B>
+where
+    DefaultAllocator: Allocator<T, R, C>,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self, self.shape()) {
+            (MatrixNode::Zero(_), Some((r, c))) => write!(f, "Zero[{r}x{c}]"),
+            (MatrixNode::Zero(_), None) => write!(f, "Zero"),
+            (MatrixNode::Identity(n), _) => write!(f, " I[{n}x{n}] "),
+            (_, Some((r, c))) => write!(f, " [{r}x{c}] "),
+            _ => unreachable!(),
         }
     }
 }
@@ -47,19 +314,20 @@ where
 //    }
 //}
 
-impl<T: nalgebra::Scalar + crate::value::Scalar> PartialEq for MatrixNode<T>
+impl<T: nalgebra::Scalar + crate::value::Scalar, R: Dim, C: Dim, B: Backend<T>> PartialEq
+    for MatrixNode<T, R, C, B>
 where
-    OMatrix<T, Dyn, Dyn>: PartialEq,
+    DefaultAllocator: Allocator<T, R, C>,
 {
     fn eq(&self, other: &Self) -> bool {
-        match (self.0.as_ref(), other.0.as_ref()) {
-            (Some(a), Some(b)) => {
-                if a.nrows() != b.nrows() || a.ncols() != b.ncols() {
+        match (self.shape(), other.shape()) {
+            (Some((r, c)), Some(shape)) => {
+                if (r, c) != shape {
                     return false;
                 }
-                for i in 0..a.nrows() {
-                    for j in 0..a.ncols() {
-                        if a[(i, j)] != b[(i, j)] {
+                for i in 0..r {
+                    for j in 0..c {
+                        if self.get(i, j) != other.get(i, j) {
                             return false;
                         }
                     }
@@ -72,125 +340,197 @@ where
     }
 }
 
-impl<'a, T: Copy + PartialEq + std::fmt::Debug + 'static> Differentiable<'a> for MatrixNode<T> {
-    type Δ<D> = Atom;
-    type T = MatrixNode<T>;
+impl<T: nalgebra::Scalar, R: Dim, C: Dim, B: Backend<T>> Eval for MatrixNode<T, R, C, B>
+where
+    DefaultAllocator: Allocator<T, R, C>,
+{
+    type T = MatrixNode<T, R, C, B>;
 
-    fn eval(&self) -> Self::T {
-        self.clone()
+    fn try_eval(&self) -> error::Result<Self::T> {
+        self.infer_shape()?;
+        Ok(self.clone())
     }
 
+    fn with_value<V>(&self, f: impl FnOnce(&Self::T) -> V) -> error::Result<V> {
+        self.infer_shape()?;
+        Ok(f(self))
+    }
+
+    /// Fails if the shape doesn't fit the static dimensions.
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        let Some((r, c)) = self.shape() else {
+            return Ok(Shape::Unknown);
+        };
+        check_dims::<R, C>(r, c)?;
+        Ok(Shape::Matrix(r, c))
+    }
+}
+
+impl<'a, T: nalgebra::Scalar + Copy, R: Dim, C: Dim, B: Backend<T>> Differentiable<'a>
+    for MatrixNode<T, R, C, B>
+where
+    DefaultAllocator: Allocator<T, R, C>,
+{
+    type Δ<D> = Atom;
+
     fn derivative<const LEN: usize, D>(&'a self, _: [&str; LEN], _: D) -> [Self::Δ<D>; LEN] {
         [Zero; LEN]
     }
 
     fn is_zero(&self) -> bool {
-        self.0.is_none()
+        matches!(self, MatrixNode::Zero(_))
     }
 }
 
-impl<T> Add<MatrixNode<T>> for MatrixNode<T>
+impl<T, R: Dim, C: Dim, B: Backend<T>> Add<MatrixNode<T, R, C, B>> for MatrixNode<T, R, C, B>
 where
-    OMatrix<T, Dyn, Dyn>: Add<OMatrix<T, Dyn, Dyn>, Output = OMatrix<T, Dyn, Dyn>>,
-    DefaultAllocator: Allocator<T, Dyn, Dyn>,
+    T: nalgebra::Scalar + crate::value::Scalar + Add<T, Output = T>,
+    DefaultAllocator: Allocator<T, R, C>,
 {
-    type Output = MatrixNode<T>;
-
-    fn add(self, rhs: MatrixNode<T>) -> Self::Output {
-        MatrixNode(match (self.0, rhs.0) {
-            (None, None) => None,
-            (None, Some(r)) => Some(r),
-            (Some(l), None) => Some(l),
-            (Some(l), Some(r)) => Some(l + r),
-        })
+    type Output = MatrixNode<T, R, C, B>;
+
+    fn add(self, rhs: MatrixNode<T, R, C, B>) -> Self::Output {
+        match (self, rhs) {
+            (l @ MatrixNode::Zero(_), MatrixNode::Zero(None)) => l,
+            (MatrixNode::Zero(_), r) => r,
+            (l, MatrixNode::Zero(_)) => l,
+            (MatrixNode::Fill(r, c, a), MatrixNode::Fill(_, _, b)) => MatrixNode::fill(r, c, a + b),
+            (l, r) => l.zip_map(r, |x, y| x + y),
+        }
     }
 }
 
-impl<T: 'static> Sub<MatrixNode<T>> for MatrixNode<T>
+impl<T, R: Dim, C: Dim, B: Backend<T>> Sub<MatrixNode<T, R, C, B>> for MatrixNode<T, R, C, B>
 where
-    OMatrix<T, Dyn, Dyn>: Sub<OMatrix<T, Dyn, Dyn>, Output = OMatrix<T, Dyn, Dyn>>,
-    DefaultAllocator: Allocator<T, Dyn, Dyn>,
-    T: std::ops::Neg<Output = T> + std::clone::Clone + std::cmp::PartialEq + std::fmt::Debug,
+    DefaultAllocator: Allocator<T, R, C>,
+    T: nalgebra::Scalar + crate::value::Scalar,
+    T: std::ops::Neg<Output = T> + std::ops::Sub<Output = T>,
 {
-    type Output = MatrixNode<T>;
+    type Output = MatrixNode<T, R, C, B>;
+
+    fn sub(self, rhs: MatrixNode<T, R, C, B>) -> Self::Output {
+        match (self, rhs) {
+            (MatrixNode::Zero(None), r @ MatrixNode::Zero(_)) => r,
+            (l, MatrixNode::Zero(_)) => l,
+            (MatrixNode::Zero(_), r) => r.map(|x| -x),
+            (MatrixNode::Fill(r, c, a), MatrixNode::Fill(_, _, b)) => MatrixNode::fill(r, c, a - b),
+            (l, r) => l.zip_map(r, |x, y| x - y),
+        }
+    }
+}
 
-    fn sub(self, rhs: MatrixNode<T>) -> Self::Output {
-        MatrixNode(match (self.0, rhs.0) {
-            (None, None) => None,
-            (None, Some(r)) => Some(r.map(|x| -x)),
-            (Some(l), None) => Some(l),
-            (Some(l), Some(r)) => Some(l - r),
-        })
+impl<T: nalgebra::Scalar + crate::value::Scalar, R: Dim, C: Dim, B: Backend<T>>
+    SubAssign<MatrixNode<T, R, C, B>> for MatrixNode<T, R, C, B>
+where
+    MatrixNode<T, R, C, B>: Sub<MatrixNode<T, R, C, B>, Output = MatrixNode<T, R, C, B>>,
+    DefaultAllocator: Allocator<T, R, C>,
+{
+    /// Zero matrices, including ones without a shape, leave `self` unchanged, so a zero gradient
+    /// can always be subtracted from a parameter.
+    fn sub_assign(&mut self, rhs: MatrixNode<T, R, C, B>) {
+        let l = std::mem::replace(self, MatrixNode::Zero(None));
+        *self = l - rhs;
     }
 }
 
-impl<T> Mul<MatrixNode<T>> for MatrixNode<T>
+/// Matrix product. Dimensions that can't be multiplied fail to compile if both are static, and
+/// panic otherwise. [`Mul`](crate::ops::Mul) nodes check them first, so evaluating one with
+/// `try_eval` returns a shape error instead.
+impl<T, R1: Dim, C1: Dim, R2: Dim, C2: Dim, B: Backend<T>> Mul<MatrixNode<T, R2, C2, B>>
+    for MatrixNode<T, R1, C1, B>
 where
-    OMatrix<T, Dyn, Dyn>: Mul<OMatrix<T, Dyn, Dyn>, Output = OMatrix<T, Dyn, Dyn>>,
-    DefaultAllocator: Allocator<T, Dyn, Dyn>,
+    T: nalgebra::Scalar + crate::value::Scalar,
+    T: num_traits::Zero + num_traits::One + ClosedAdd + ClosedMul,
+    ShapeConstraint: AreMultipliable<R1, C1, R2, C2>,
+    DefaultAllocator: Allocator<T, R1, C1> + Allocator<T, R2, C2> + Allocator<T, R1, C2>,
 {
-    type Output = MatrixNode<T>;
+    type Output = MatrixNode<T, R1, C2, B>;
 
-    fn mul(self, rhs: MatrixNode<T>) -> Self::Output {
+    fn mul(self, rhs: MatrixNode<T, R2, C2, B>) -> Self::Output {
         let (l, r) = (self, rhs);
-        let err = format!("{l:?} cannot be multiplied by {r:?}");
 
-        MatrixNode(match (l.0, r.0) {
-            (Some(l), Some(r)) => {
-                assert_eq!(l.ncols(), r.nrows(), "{err}");
-                Some(l * r)
+        match (l.shape(), r.shape()) {
+            (Some((lr, lc)), Some((rr, rc))) => {
+                assert_eq!(lc, rr, "{l:?} cannot be multiplied by {r:?}");
+                match (l, r) {
+                    (l @ MatrixNode::Zero(_), r) | (l, r @ MatrixNode::Zero(_)) => {
+                        l.recycle();
+                        r.recycle();
+                        MatrixNode::zeros(lr, rc)
+                    }
+                    (MatrixNode::Identity(_), r) => r.into_dims(),
+                    (l, MatrixNode::Identity(_)) => l.into_dims(),
+                    (l, r) => MatrixNode::Dense(B::matmul(l.dense(), r.dense())),
+                }
             }
-            _ => None,
-        })
+            _ => MatrixNode::Zero(None),
+        }
     }
 }
 
-impl<T: nalgebra::Scalar + crate::value::Scalar> Mul<Atom> for MatrixNode<T>
+impl<T, R: Dim, C: Dim, B: Backend<T>> Mul<Atom> for MatrixNode<T, R, C, B>
 where
-    DefaultAllocator: Allocator<T, Dyn, Dyn>,
+    T: nalgebra::Scalar + crate::value::Scalar + Mul<T, Output = T>,
+    DefaultAllocator: Allocator<T, R, C>,
 {
-    type Output = MatrixNode<T>;
+    type Output = MatrixNode<T, R, C, B>;
 
     fn mul(self, rhs: Atom) -> Self::Output {
         if rhs == value::Zero {
-            return Self(None);
+            // The shape of the zero is unknown, e.g. if `self` is the gradient of another symbol.
+            self.recycle();
+            return MatrixNode::Zero(None);
+        }
+        if rhs == value::One {
+            return self;
         }
-        self
+        self.map(|n| n * T::from(rhs))
     }
 }
 
-impl<T: nalgebra::Scalar + crate::value::Scalar> Mul<MatrixNode<T>> for Atom
+/// Constants multiply the gradients of symbols, which are added together across symbols of
+/// different shapes, so the product is dynamically sized.
+impl<T, R: Dim, C: Dim, B: Backend<T>> Mul<MatrixNode<T, R, C, B>> for Atom
 where
-    DefaultAllocator: Allocator<T, Dyn, Dyn>,
+    T: nalgebra::Scalar + crate::value::Scalar + Mul<T, Output = T>,
+    DefaultAllocator: Allocator<T, R, C> + Allocator<T, Dyn, Dyn>,
 {
-    type Output = MatrixNode<T>;
+    type Output = MatrixNode<T, Dyn, Dyn, B>;
 
-    fn mul(self, rhs: MatrixNode<T>) -> Self::Output {
-        rhs * self
+    fn mul(self, rhs: MatrixNode<T, R, C, B>) -> Self::Output {
+        (rhs * self).into_dims()
     }
 }
 
-impl<T: nalgebra::Scalar + crate::value::Scalar + Add<T, Output = T>> Add<Atom> for MatrixNode<T> {
-    type Output = MatrixNode<T>;
+impl<T, R: Dim, C: Dim, B: Backend<T>> Add<Atom> for MatrixNode<T, R, C, B>
+where
+    T: nalgebra::Scalar + crate::value::Scalar + Add<T, Output = T>,
+    DefaultAllocator: Allocator<T, R, C>,
+{
+    type Output = MatrixNode<T, R, C, B>;
 
     fn add(self, rhs: Atom) -> Self::Output {
-        Self(self.0.map(|m| m.map(|n| n + T::from(rhs))))
+        self.map(|n| n + T::from(rhs))
     }
 }
 
-impl<T: nalgebra::Scalar + crate::value::Scalar + Add<T, Output = T>> Add<MatrixNode<T>> for Atom {
-    type Output = MatrixNode<T>;
+impl<T, R: Dim, C: Dim, B: Backend<T>> Add<MatrixNode<T, R, C, B>> for Atom
+where
+    T: nalgebra::Scalar + crate::value::Scalar + Add<T, Output = T>,
+    DefaultAllocator: Allocator<T, R, C>,
+{
+    type Output = MatrixNode<T, R, C, B>;
 
-    fn add(self, rhs: MatrixNode<T>) -> Self::Output {
+    fn add(self, rhs: MatrixNode<T, R, C, B>) -> Self::Output {
         rhs + self
     }
 }
 
-pub fn mat<T>(m: OMatrix<T, Dyn, Dyn>) -> MatrixNode<T>
+pub fn mat<T: nalgebra::Scalar, R: Dim, C: Dim>(m: OMatrix<T, R, C>) -> MatrixNode<T, R, C>
 where
-    DefaultAllocator: Allocator<T, Dyn, Dyn>,
+    DefaultAllocator: Allocator<T, R, C>,
 {
-    MatrixNode(Some(m))
+    MatrixNode::Dense(m)
 }
 
 macro_rules! matrix_scalar_op {
@@ -199,19 +539,25 @@ macro_rules! matrix_scalar_op {
     //)*};
 
     (for $t:ty => $($op:ident:$Op:ident)*) => {$(
-        impl $Op<NodeValue<$t>> for MatrixNode<$t> {
-            type Output = MatrixNode<$t>;
+        impl<R: Dim, C: Dim, B: Backend<$t>> $Op<NodeValue<$t>> for MatrixNode<$t, R, C, B>
+        where
+            DefaultAllocator: Allocator<$t, R, C>,
+        {
+            type Output = MatrixNode<$t, R, C, B>;
 
             fn $op(self, rhs: NodeValue<$t>) -> Self::Output {
-                MatrixNode(self.0.map(|m| m.map(|n| n.$op(rhs.0))))
+                self.broadcast(BinOp::$Op, rhs.0, false)
             }
         }
 
-        impl $Op<MatrixNode<$t>> for NodeValue<$t> {
-            type Output = MatrixNode<$t>;
+        impl<R: Dim, C: Dim, B: Backend<$t>> $Op<MatrixNode<$t, R, C, B>> for NodeValue<$t>
+        where
+            DefaultAllocator: Allocator<$t, R, C>,
+        {
+            type Output = MatrixNode<$t, R, C, B>;
 
-            fn $op(self, rhs: MatrixNode<$t>) -> Self::Output {
-                MatrixNode(rhs.0.map(|m| m.map(|n| self.0.$op(n))))
+            fn $op(self, rhs: MatrixNode<$t, R, C, B>) -> Self::Output {
+                rhs.broadcast(BinOp::$Op, self.0, true)
             }
         }
     )*};
@@ -219,12 +565,18 @@ macro_rules! matrix_scalar_op {
 
 matrix_scalar_op!(for f32 => add:Add mul:Mul div:Div sub:Sub);
 matrix_scalar_op!(for f64 => add:Add mul:Mul div:Div sub:Sub);
+matrix_scalar_op!(for Complex<f32> => add:Add mul:Mul div:Div sub:Sub);
+matrix_scalar_op!(for Complex<f64> => add:Add mul:Mul div:Div sub:Sub);
 
-impl Neg for MatrixNode<f32> {
-    type Output = MatrixNode<f32>;
+impl<T, R: Dim, C: Dim, B: Backend<T>> Neg for MatrixNode<T, R, C, B>
+where
+    T: nalgebra::Scalar + value::Scalar + Neg<Output = T>,
+    DefaultAllocator: Allocator<T, R, C>,
+{
+    type Output = MatrixNode<T, R, C, B>;
 
     fn neg(self) -> Self::Output {
-        MatrixNode(self.0.map(|m| -m))
+        self.map(|n| -n)
     }
 }
 
@@ -254,3 +606,124 @@ fn gradient_decent() {
     assert_eq!(dw2.eval().shape(), w2.eval().shape());
     assert_eq!(dw1.eval().shape(), w1.eval().shape());
 }
+
+#[test]
+fn checkpoint() {
+    let x = mat(DMatrix::<f32>::new_random(3, 1)).symbol("input");
+
+    let w1 = mat(DMatrix::<f32>::new_random(2, 3)).symbol("w1");
+    let b1 = mat(DMatrix::<f32>::new_random(2, 1)).symbol("b1");
+
+    let w2 = mat(DMatrix::<f32>::new_random(4, 2)).symbol("w2");
+    let b2 = mat(DMatrix::<f32>::new_random(4, 1)).symbol("b2");
+
+    let dl2 = mat(DMatrix::<f32>::repeat(4, 1, 1.));
+
+    let l1 = (&w1 * &x + &b1).cached();
+    let l2 = (&w2 * &l1 + &b2).checkpoint();
+    let reference = &w2 * (&w1 * &x + &b1) + &b2;
+
+    assert_eq!(l2.eval(), reference.eval());
+    assert!(l2.0.is_cached());
+    assert!(!l1.0.is_cached());
+
+    let [dw1, dw2] = l2.derivative(["w1", "w2"], &dl2);
+    let [dw1_ref, dw2_ref] = reference.derivative(["w1", "w2"], &dl2);
+
+    assert_eq!(dw1.eval(), dw1_ref.eval());
+    assert_eq!(dw2.eval(), dw2_ref.eval());
+    assert!(l1.0.is_cached());
+
+    l2.forget();
+    assert!(!l2.0.is_cached());
+    assert!(!l1.0.is_cached());
+}
+
+#[test]
+fn shaped_zero() {
+    let x = MatrixNode::<f32>::zeros(3, 1).symbol("x");
+    let mut w = mat(DMatrix::<f32>::new_random(2, 3)).symbol("w");
+    let b = MatrixNode::fill(2, 1, 1f32).symbol("b");
+    let dl = MatrixNode::<f32>::identity(2) * mat(DMatrix::repeat(2, 1, 1.));
+
+    let y = &w * &x + &b;
+    assert_eq!(y.eval(), MatrixNode::fill(2, 1, 1.));
+
+    let [dw, db, dz] = y.derivative(["w", "b", "z"], &dl);
+    let (dw, db, dz) = (dw.eval(), db.eval(), dz.eval());
+
+    assert!(dw.is_zero());
+    assert_eq!(dw.shape(), Some((2, 3)));
+    assert_eq!(db, mat(DMatrix::repeat(2, 1, 1.)));
+    assert_eq!(dz.shape(), None);
+
+    let before = w.eval();
+    w.0.node -= dw;
+    w.0.node -= dz;
+    assert_eq!(w.eval(), before);
+
+    w.0.node.dense_mut()[(0, 0)] = 5.;
+    assert_eq!(w.eval().get(0, 0), 5.);
+    assert_eq!(MatrixNode::<f32>::identity(2).get(1, 0), 0.);
+
+    // A scalar added to a zero without a shape isn't lost.
+    let shapeless = MatrixNode::<f32>::Zero(None);
+    let sum = shapeless.clone() + NodeValue(2.);
+    assert_eq!(sum, MatrixNode::fill(1, 1, 2.));
+    assert_eq!((shapeless * NodeValue(2.)).shape(), None);
+}
+
+#[test]
+fn static_dims() {
+    use nalgebra::{Matrix2x3, Vector2, Vector3, U1, U2, U3};
+
+    let x = mat(Vector3::new(1f32, 2., 3.)).symbol("x");
+    let mut w = mat(Matrix2x3::new(0.1f32, 0., 0.2, 0., 0.3, 0.)).symbol("w");
+    let b = mat(Vector2::new(0.5f32, -0.5)).symbol("b");
+    let dl = mat(Vector2::new(1f32, 1.));
+
+    let l = &w * &x + &b;
+    let y = l.exp();
+    let _: MatrixNode<f32, U2, U1> = y.eval();
+    let _: MatrixNode<f32, U3, U2> = (&w).transpose().eval();
+
+    let (xd, wd, bd) = (
+        x.eval().into_dims::<Dyn, Dyn>().symbol("x"),
+        w.eval().into_dims::<Dyn, Dyn>().symbol("w"),
+        b.eval().into_dims::<Dyn, Dyn>().symbol("b"),
+    );
+    let l_ref = &wd * &xd + &bd;
+    let reference = l_ref.exp();
+    assert_eq!(y.eval().into_dims(), reference.eval());
+
+    let [dw, dx, db] = y.derivative(["w", "x", "b"], &dl);
+    let [dw_ref, dx_ref, db_ref] =
+        reference.derivative(["w", "x", "b"], dl.clone().into_dims::<Dyn, Dyn>());
+    let dw = dw.eval();
+    assert_eq!(dw, dw_ref.eval());
+    assert_eq!(dx.eval(), dx_ref.eval());
+    assert_eq!(db.eval(), db_ref.eval());
+    assert_eq!(Sum(&y).eval().0, Sum(&reference).eval().0);
+
+    // Static and dynamic dimensions mix in products.
+    let mixed: MatrixNode<f32, U2, Dyn> = w.eval() * xd.eval();
+    assert_eq!(mixed.into_dims(), (&wd * &xd).eval());
+
+    w.0.node -= dw.into_dims::<U2, U3>();
+    assert_eq!(w.eval().shape(), Some((2, 3)));
+
+    // Constants keep the dimensions of the matrices they multiply.
+    let _: MatrixNode<f32, U2, U3> = w.eval() * Atom::int(2);
+
+    // Shapes that don't fit the dimensions fail to evaluate instead of panicking.
+    let bad = MatrixNode::<f32, U2, U3>::Zero(Some((3, 3))).symbol("bad");
+    let Err(error::Error::Shape(e)) = (&bad * &x).try_eval() else {
+        panic!("expected a shape error");
+    };
+    assert_eq!(e.shapes, [Shape::Matrix(3, 3), Shape::Matrix(2, 3)]);
+    let long = mat(DMatrix::from_element(4, 1, 1f32)).symbol("long");
+    let Err(error::Error::Shape(e)) = (&w * &long).try_eval() else {
+        panic!("expected a shape error");
+    };
+    assert_eq!((e.op, e.symbols), ("Mul", vec!["w", "long"]));
+}
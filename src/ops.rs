@@ -1,35 +1,64 @@
-use crate::{mat::MatrixNode, prelude::*, Node};
+use crate::{
+    error,
+    mat::MatrixNode,
+    prelude::*,
+    shape::{self, Shape, ShapeError},
+    Node,
+};
 /// This module contains the implementations for the various operators
-use nalgebra::{allocator::Allocator, DefaultAllocator, Dyn};
-
-fn zip_map<const LEN: usize, A, B, C>(a: [A; LEN], b: [B; LEN], f: impl Fn(A, B) -> C) -> [C; LEN] {
-    match a
-        .into_iter()
-        .zip(b.into_iter())
-        .map(|(a, b)| f(a, b))
-        .collect::<Vec<_>>()
-        .try_into()
-    {
-        Ok(v) => v,
-        Err(_) => unreachable!(),
-    }
+use nalgebra::{allocator::Allocator, DefaultAllocator, Dim};
+use std::fmt::Debug;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+pub(crate) fn zip_map<const LEN: usize, A, B, C>(
+    a: [A; LEN],
+    b: [B; LEN],
+    f: impl Fn(A, B) -> C,
+) -> [C; LEN] {
+    let (mut a, mut b) = (a.into_iter(), b.into_iter());
+    std::array::from_fn(|_| f(a.next().unwrap(), b.next().unwrap()))
 }
 
 #[derive(Clone)]
 pub struct Add<Lhs, Rhs>(pub Lhs, pub Rhs);
 
-impl<'a, L, R, LNode: Differentiable<'a, T = L>, RNode: Differentiable<'a, T = R>>
-    Differentiable<'a> for Add<LNode, RNode>
+impl<L, R, LNode: Eval<T = L>, RNode: Eval<T = R>> Eval for Add<LNode, RNode>
 where
     L: std::ops::Add<R>,
+    L: Eval,
+    R: Eval,
 {
-    type Δ<D> = Add<LNode::Δ<D>, RNode::Δ<D>> where Self: 'a;
     type T = <L as std::ops::Add<R>>::Output;
 
-    fn eval(&self) -> Self::T {
-        self.0.eval() + self.1.eval()
+    fn try_eval(&self) -> error::Result<Self::T> {
+        let (l, r) = (self.0.try_eval()?, self.1.try_eval()?);
+        shape::values("Add", (&self.0, &l), (&self.1, &r), Shape::elementwise)?;
+        Ok(l + r)
+    }
+
+    fn forget(&self) {
+        self.0.forget();
+        self.1.forget();
     }
 
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        shape::binary("Add", &self.0, &self.1, Shape::elementwise)
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        let mut symbols = self.0.symbols();
+        symbols.extend(self.1.symbols());
+        symbols
+    }
+}
+
+impl<'a, LNode: Differentiable<'a>, RNode: Differentiable<'a>> Differentiable<'a>
+    for Add<LNode, RNode>
+where
+    Self: Eval,
+{
+    type Δ<D> = Add<LNode::Δ<D>, RNode::Δ<D>> where Self: 'a;
+
     fn derivative<'d, const LEN: usize, D: Clone>(
         &'a self,
         k: [&str; LEN],
@@ -50,18 +79,42 @@ where
 #[derive(Clone)]
 pub struct Sub<Lhs, Rhs>(pub Lhs, pub Rhs);
 
-impl<'a, L, R, LNode: Differentiable<'a, T = L>, RNode: Differentiable<'a, T = R>>
-    Differentiable<'a> for Sub<LNode, RNode>
+impl<L, R, LNode: Eval<T = L>, RNode: Eval<T = R>> Eval for Sub<LNode, RNode>
 where
     L: std::ops::Sub<R>,
+    L: Eval,
+    R: Eval,
 {
-    type Δ<T> = Sub<LNode::Δ<T>, RNode::Δ<T>> where Self: 'a;
-
     type T = <L as std::ops::Sub<R>>::Output;
 
-    fn eval(&self) -> Self::T {
-        self.0.eval() - self.1.eval()
+    fn try_eval(&self) -> error::Result<Self::T> {
+        let (l, r) = (self.0.try_eval()?, self.1.try_eval()?);
+        shape::values("Sub", (&self.0, &l), (&self.1, &r), Shape::elementwise)?;
+        Ok(l - r)
+    }
+
+    fn forget(&self) {
+        self.0.forget();
+        self.1.forget();
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        shape::binary("Sub", &self.0, &self.1, Shape::elementwise)
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        let mut symbols = self.0.symbols();
+        symbols.extend(self.1.symbols());
+        symbols
     }
+}
+
+impl<'a, LNode: Differentiable<'a>, RNode: Differentiable<'a>> Differentiable<'a>
+    for Sub<LNode, RNode>
+where
+    Self: Eval,
+{
+    type Δ<T> = Sub<LNode::Δ<T>, RNode::Δ<T>> where Self: 'a;
 
     fn derivative<const LEN: usize, D: Clone>(
         &'a self,
@@ -83,17 +136,35 @@ where
 #[derive(Clone, Debug)]
 pub struct Neg<N>(pub N);
 
-impl<'a, N: Differentiable<'a>> Differentiable<'a> for Neg<N>
+impl<N: Eval> Eval for Neg<N>
 where
     N::T: std::ops::Neg,
 {
-    type Δ<D> = N::Δ<Neg<D>> where Self: 'a;
     type T = <N::T as std::ops::Neg>::Output;
 
-    fn eval(&self) -> Self::T {
-        -self.0.eval()
+    fn try_eval(&self) -> error::Result<Self::T> {
+        Ok(std::ops::Neg::neg(self.0.try_eval()?))
     }
 
+    fn forget(&self) {
+        self.0.forget()
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        self.0.infer_shape()
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        self.0.symbols()
+    }
+}
+
+impl<'a, N: Differentiable<'a>> Differentiable<'a> for Neg<N>
+where
+    Self: Eval,
+{
+    type Δ<D> = N::Δ<Neg<D>> where Self: 'a;
+
     fn derivative<const LEN: usize, D: Clone>(
         &'a self,
         k: [&str; LEN],
@@ -110,20 +181,44 @@ where
 #[derive(Clone)]
 pub struct Div<Lhs, Rhs>(pub Lhs, pub Rhs);
 
-impl<'a, L, R, LNode: Differentiable<'a, T = L>, RNode: Differentiable<'a, T = R>>
-    Differentiable<'a> for Div<LNode, RNode>
+impl<L, R, LNode: Eval<T = L>, RNode: Eval<T = R>> Eval for Div<LNode, RNode>
 where
     L: std::ops::Div<R>,
+    L: Eval,
+    R: Eval,
 {
-    // 1/y * dy/dx - x/y^2 * dy/dx
-    type Δ<D> = Sub<LNode::Δ<Div<D, &'a RNode>>, RNode::Δ<ElemMul<Div<&'a LNode, ElemMul<&'a RNode, &'a RNode>>, D>>> where Self: 'a;
-
     type T = <L as std::ops::Div<R>>::Output;
 
-    fn eval(&self) -> Self::T {
-        self.0.eval() / self.1.eval()
+    fn try_eval(&self) -> error::Result<Self::T> {
+        let (l, r) = (self.0.try_eval()?, self.1.try_eval()?);
+        shape::values("Div", (&self.0, &l), (&self.1, &r), Shape::elementwise)?;
+        Ok(l / r)
+    }
+
+    fn forget(&self) {
+        self.0.forget();
+        self.1.forget();
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        shape::binary("Div", &self.0, &self.1, Shape::elementwise)
     }
 
+    fn symbols(&self) -> Vec<&'static str> {
+        let mut symbols = self.0.symbols();
+        symbols.extend(self.1.symbols());
+        symbols
+    }
+}
+
+impl<'a, LNode: Differentiable<'a>, RNode: Differentiable<'a>> Differentiable<'a>
+    for Div<LNode, RNode>
+where
+    Self: Eval,
+{
+    // 1/y * dy/dx - x/y^2 * dy/dx, with the partials conjugated for complex values
+    type Δ<D> = Sub<LNode::Δ<Div<D, Conj<&'a RNode>>>, RNode::Δ<ElemMul<Conj<Div<&'a LNode, ElemMul<&'a RNode, &'a RNode>>>, D>>> where Self: 'a;
+
     fn derivative<const LEN: usize, D: Clone>(
         &'a self,
         k: [&str; LEN],
@@ -131,8 +226,9 @@ where
     ) -> [Self::Δ<D>; LEN] {
         let Div(x, y) = self;
         zip_map(
-            self.0.derivative(k, Div(d.clone(), y)),
-            self.1.derivative(k, ElemMul(Div(x, ElemMul(y, y)), d)),
+            self.0.derivative(k, Div(d.clone(), Conj(y))),
+            self.1
+                .derivative(k, ElemMul(Conj(Div(x, ElemMul(y, y))), d)),
             Sub,
         )
     }
@@ -145,21 +241,45 @@ where
 #[derive(Clone)]
 pub struct ElemMul<Lhs, Rhs>(pub Lhs, pub Rhs);
 
-impl<'a, L, R, LNode: Differentiable<'a, T = L> + 'a, RNode: Differentiable<'a, T = R> + 'a>
-    Differentiable<'a> for ElemMul<LNode, RNode>
+impl<L, R, LNode: Eval<T = L>, RNode: Eval<T = R>> Eval for ElemMul<LNode, RNode>
 where
     L: crate::primitive_ops::ElemMul<R>,
+    L: Eval,
+    R: Eval,
 {
-    type Δ<D> = Add<LNode::Δ<ElemMul<&'a RNode, D>>, RNode::Δ<ElemMul<&'a LNode, D>>>
-    where
-        Self: 'a;
-
     type T = <L as crate::primitive_ops::ElemMul<R>>::Output;
 
-    fn eval(&self) -> Self::T {
-        self.0.eval().elem_mul(self.1.eval())
+    fn try_eval(&self) -> error::Result<Self::T> {
+        let (l, r) = (self.0.try_eval()?, self.1.try_eval()?);
+        shape::values("ElemMul", (&self.0, &l), (&self.1, &r), Shape::elementwise)?;
+        Ok(l.elem_mul(r))
+    }
+
+    fn forget(&self) {
+        self.0.forget();
+        self.1.forget();
     }
 
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        shape::binary("ElemMul", &self.0, &self.1, Shape::elementwise)
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        let mut symbols = self.0.symbols();
+        symbols.extend(self.1.symbols());
+        symbols
+    }
+}
+
+impl<'a, LNode: Differentiable<'a> + 'a, RNode: Differentiable<'a> + 'a> Differentiable<'a>
+    for ElemMul<LNode, RNode>
+where
+    Self: Eval,
+{
+    type Δ<D> = Add<LNode::Δ<ElemMul<Conj<&'a RNode>, D>>, RNode::Δ<ElemMul<Conj<&'a LNode>, D>>>
+    where
+        Self: 'a;
+
     fn derivative<const LEN: usize, D: Clone>(
         &'a self,
         k: [&str; LEN],
@@ -167,8 +287,8 @@ where
     ) -> [Self::Δ<D>; LEN] {
         let ElemMul(x, y) = self;
         zip_map(
-            self.0.derivative(k, ElemMul(y, d.clone())),
-            self.1.derivative(k, ElemMul(x, d)),
+            self.0.derivative(k, ElemMul(Conj(y), d.clone())),
+            self.1.derivative(k, ElemMul(Conj(x), d)),
             Add,
         )
     }
@@ -181,18 +301,43 @@ where
 #[derive(Clone)]
 pub struct Mul<Lhs, Rhs>(pub Lhs, pub Rhs);
 
-impl<'a, L, R, LNode: Differentiable<'a, T = L> + 'a, RNode: Differentiable<'a, T = R> + 'a>
-    Differentiable<'a> for Mul<LNode, RNode>
+impl<L, R, LNode: Eval<T = L>, RNode: Eval<T = R>> Eval for Mul<LNode, RNode>
 where
     L: std::ops::Mul<R>,
+    L: Eval,
+    R: Eval,
 {
-    type Δ<D> = Add<LNode::Δ<Mul<D, Transpose<&'a RNode>>>, RNode::Δ<Mul<Transpose<&'a LNode>, D>>>;
     type T = <L as std::ops::Mul<R>>::Output;
 
-    fn eval(&self) -> Self::T {
-        self.0.eval() * self.1.eval()
+    fn try_eval(&self) -> error::Result<Self::T> {
+        let (l, r) = (self.0.try_eval()?, self.1.try_eval()?);
+        shape::values("Mul", (&self.0, &l), (&self.1, &r), Shape::matmul)?;
+        Ok(l * r)
     }
 
+    fn forget(&self) {
+        self.0.forget();
+        self.1.forget();
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        shape::binary("Mul", &self.0, &self.1, Shape::matmul)
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        let mut symbols = self.0.symbols();
+        symbols.extend(self.1.symbols());
+        symbols
+    }
+}
+
+impl<'a, LNode: Differentiable<'a> + 'a, RNode: Differentiable<'a> + 'a> Differentiable<'a>
+    for Mul<LNode, RNode>
+where
+    Self: Eval,
+{
+    type Δ<D> = Add<LNode::Δ<Mul<D, Adjoint<&'a RNode>>>, RNode::Δ<Mul<Adjoint<&'a LNode>, D>>>;
+
     fn derivative<const LEN: usize, D: Clone>(
         &'a self,
         k: [&str; LEN],
@@ -200,8 +345,8 @@ where
     ) -> [Self::Δ<D>; LEN] {
         let Mul(x, y) = self;
         zip_map(
-            self.0.derivative(k, Mul(d.clone(), Transpose(y))),
-            self.1.derivative(k, Mul(Transpose(x), d)),
+            self.0.derivative(k, Mul(d.clone(), Adjoint(y))),
+            self.1.derivative(k, Mul(Adjoint(x), d)),
             Add,
         )
     }
@@ -215,49 +360,80 @@ where
 // w2 -> x
 // + l1
 
-trait TransposeAble {
-    fn transpose_(self) -> Self;
+/// Values that [`Transpose`] can be applied to.
+pub trait TransposeAble {
+    type Output;
+
+    fn transpose_(self) -> Self::Output;
 }
 
 impl<T: Scalar> TransposeAble for NodeValue<T> {
+    type Output = Self;
+
     fn transpose_(self) -> Self {
         self
     }
 }
 
 impl TransposeAble for Atom {
+    type Output = Self;
+
     fn transpose_(self) -> Self {
         self
     }
 }
 
-impl<T: Clone + PartialEq + std::fmt::Debug + 'static> TransposeAble for MatrixNode<T>
+impl<T: nalgebra::Scalar, R: Dim, C: Dim, B: Backend<T>> TransposeAble for MatrixNode<T, R, C, B>
 where
-    DefaultAllocator: Allocator<T, Dyn, Dyn>,
+    DefaultAllocator: Allocator<T, R, C> + Allocator<T, C, R>,
 {
-    fn transpose_(self) -> Self {
-        Self(self.0.map(|m| m.transpose()))
+    type Output = MatrixNode<T, C, R, B>;
+
+    fn transpose_(self) -> Self::Output {
+        match self {
+            MatrixNode::Dense(m) => MatrixNode::Dense(B::transpose(m)),
+            MatrixNode::Zero(shape) => MatrixNode::Zero(shape.map(|(r, c)| (c, r))),
+            MatrixNode::Fill(r, c, v) => MatrixNode::Fill(c, r, v),
+            MatrixNode::Identity(n) => MatrixNode::Identity(n),
+        }
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct Transpose<N>(pub N);
 
-impl<'a, T: TransposeAble, N: Differentiable<'a, T = T>> Differentiable<'a> for Transpose<N> {
-    type Δ<D> = Transpose<N::Δ<D>>where Self: 'a;
-    type T = T;
+impl<T: TransposeAble, N: Eval<T = T>> Eval for Transpose<N> {
+    type T = T::Output;
 
-    fn eval(&self) -> Self::T {
-        self.0.eval().transpose_()
+    fn try_eval(&self) -> error::Result<Self::T> {
+        Ok(self.0.try_eval()?.transpose_())
     }
 
+    fn forget(&self) {
+        self.0.forget()
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        self.0.infer_shape().map(Shape::transpose)
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        self.0.symbols()
+    }
+}
+
+impl<'a, N: Differentiable<'a>> Differentiable<'a> for Transpose<N>
+where
+    Self: Eval,
+{
+    type Δ<D> = N::Δ<Transpose<D>> where Self: 'a;
+
     fn derivative<'d, const LEN: usize, D: Clone>(
         &'a self,
         k: [&str; LEN],
         d: D,
     ) -> [Self::Δ<D>; LEN] {
-        // Maybe transpose d here?
-        self.0.derivative(k, d).map(Transpose)
+        self.0.derivative(k, Transpose(d))
     }
 
     fn is_zero(&self) -> bool {
@@ -265,27 +441,151 @@ impl<'a, T: TransposeAble, N: Differentiable<'a, T = T>> Differentiable<'a> for
     }
 }
 
+/// The complex conjugate, which is the identity for real values.
+#[derive(Clone, Debug)]
+pub struct Conj<N>(pub N);
+
+impl<N: Eval> Eval for Conj<N>
+where
+    N::T: crate::primitive_ops::Conj,
+{
+    type T = <N::T as crate::primitive_ops::Conj>::Output;
+
+    fn try_eval(&self) -> error::Result<Self::T> {
+        Ok(crate::primitive_ops::Conj::conj(self.0.try_eval()?))
+    }
+
+    fn forget(&self) {
+        self.0.forget()
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        self.0.infer_shape()
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        self.0.symbols()
+    }
+}
+
+impl<'a, N: Differentiable<'a>> Differentiable<'a> for Conj<N>
+where
+    Self: Eval,
+{
+    type Δ<D> = N::Δ<Conj<D>> where Self: 'a;
+
+    fn derivative<const LEN: usize, D: Clone>(
+        &'a self,
+        k: [&str; LEN],
+        d: D,
+    ) -> [Self::Δ<D>; LEN] {
+        self.0.derivative(k, Conj(d))
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+}
+
+/// The conjugate transpose, which is the transpose for real values.
+#[derive(Clone, Debug)]
+pub struct Adjoint<N>(pub N);
+
+impl<T: TransposeAble, N: Eval<T = T>> Eval for Adjoint<N>
+where
+    T::Output: crate::primitive_ops::Conj,
+{
+    type T = <T::Output as crate::primitive_ops::Conj>::Output;
+
+    fn try_eval(&self) -> error::Result<Self::T> {
+        Ok(crate::primitive_ops::Conj::conj(
+            self.0.try_eval()?.transpose_(),
+        ))
+    }
+
+    fn forget(&self) {
+        self.0.forget()
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        self.0.infer_shape().map(Shape::transpose)
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        self.0.symbols()
+    }
+}
+
+impl<'a, N: Differentiable<'a>> Differentiable<'a> for Adjoint<N>
+where
+    Self: Eval,
+{
+    type Δ<D> = N::Δ<Adjoint<D>> where Self: 'a;
+
+    fn derivative<const LEN: usize, D: Clone>(
+        &'a self,
+        k: [&str; LEN],
+        d: D,
+    ) -> [Self::Δ<D>; LEN] {
+        self.0.derivative(k, Adjoint(d))
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+}
+
+/// Values that [`Sum`] can be applied to.
+pub trait Summable {
+    type Output;
+
+    fn sum_(self) -> Self::Output;
+}
+
+impl<T, R: Dim, C: Dim, B: Backend<T>> Summable for MatrixNode<T, R, C, B>
+where
+    T: Scalar + nalgebra::Scalar + std::ops::Add<Output = T>,
+    DefaultAllocator: Allocator<T, R, C>,
+{
+    type Output = NodeValue<T>;
+
+    fn sum_(self) -> Self::Output {
+        NodeValue(self.into_dense().map_or(T::from(Zero), B::sum))
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Sum<N>(pub N);
 
-impl<'a, T: Scalar + Copy + std::iter::Sum, N: Differentiable<'a, T = MatrixNode<T>>>
-    Differentiable<'a> for Sum<N>
+impl<N: Eval> Eval for Sum<N>
 where
-    DefaultAllocator: Allocator<T, Dyn, Dyn>,
+    N::T: Summable,
 {
-    type Δ<D> = N::Δ<D> where Self: 'a;
-    type T = NodeValue<T>;
-
-    fn eval(&self) -> Self::T {
-        NodeValue(
-            self.0
-                .eval()
-                .0
-                .map(|m| m.iter().copied().sum())
-                .unwrap_or(T::from(Zero)),
-        )
+    type T = <N::T as Summable>::Output;
+
+    fn try_eval(&self) -> error::Result<Self::T> {
+        Ok(self.0.try_eval()?.sum_())
     }
 
+    fn forget(&self) {
+        self.0.forget()
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        self.0.infer_shape().map(|_| Shape::Scalar)
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        self.0.symbols()
+    }
+}
+
+impl<'a, N: Differentiable<'a>> Differentiable<'a> for Sum<N>
+where
+    Self: Eval,
+{
+    type Δ<D> = N::Δ<D> where Self: 'a;
+
     fn derivative<const LEN: usize, D: Clone>(
         &'a self,
         k: [&str; LEN],
@@ -302,24 +602,41 @@ where
 #[derive(Clone, Debug)]
 pub struct Exp<N>(pub N);
 
-impl<'a, N: Differentiable<'a>> Differentiable<'a> for Exp<N>
+impl<N: Eval> Eval for Exp<N>
 where
     N::T: crate::primitive_ops::Exp,
 {
-    type Δ<D> = N::Δ<ElemMul<D,&'a Self>> where Self: 'a;
-
     type T = <N::T as crate::primitive_ops::Exp>::Output;
 
-    fn eval(&self) -> Self::T {
-        crate::primitive_ops::Exp::exp(self.0.eval())
+    fn try_eval(&self) -> error::Result<Self::T> {
+        Ok(crate::primitive_ops::Exp::exp(self.0.try_eval()?))
+    }
+
+    fn forget(&self) {
+        self.0.forget()
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        self.0.infer_shape()
     }
 
+    fn symbols(&self) -> Vec<&'static str> {
+        self.0.symbols()
+    }
+}
+
+impl<'a, N: Differentiable<'a>> Differentiable<'a> for Exp<N>
+where
+    Self: Eval,
+{
+    type Δ<D> = N::Δ<ElemMul<D, Conj<&'a Self>>> where Self: 'a;
+
     fn derivative<const LEN: usize, D: Clone>(
         &'a self,
         k: [&str; LEN],
         d: D,
     ) -> [Self::Δ<D>; LEN] {
-        self.0.derivative(k, ElemMul(d, self))
+        self.0.derivative(k, ElemMul(d, Conj(self)))
     }
 
     fn is_zero(&self) -> bool {
@@ -327,6 +644,245 @@ where
     }
 }
 
+/// Evaluates `N`, but is treated as a constant when differentiating.
+#[derive(Clone, Debug)]
+pub struct Detach<N>(pub N);
+
+impl<N: Eval> Eval for Detach<N> {
+    type T = N::T;
+
+    fn try_eval(&self) -> error::Result<Self::T> {
+        self.0.try_eval()
+    }
+
+    fn forget(&self) {
+        self.0.forget()
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        self.0.infer_shape()
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        self.0.symbols()
+    }
+}
+
+impl<'a, N: Differentiable<'a>> Differentiable<'a> for Detach<N> {
+    type Δ<D> = Atom where Self: 'a;
+
+    fn derivative<const LEN: usize, D>(&'a self, _: [&str; LEN], _: D) -> [Self::Δ<D>; LEN] {
+        [Zero; LEN]
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+}
+
+/// The derivative of a gradient whose backward pass can't be differentiated, which fails to
+/// evaluate unless the gradient doesn't depend on the symbol, i.e. it is `None`.
+#[derive(Clone, Copy, Debug)]
+pub struct Undifferentiable(pub Option<&'static str>);
+
+impl Undifferentiable {
+    /// The derivatives of the gradient `n` of `op` with respect to the symbols `k`.
+    pub(crate) fn of<const LEN: usize>(
+        op: &'static str,
+        n: &impl Eval,
+        k: [&str; LEN],
+    ) -> [Self; LEN] {
+        let symbols = n.symbols();
+        k.map(|k| Undifferentiable(symbols.contains(&k).then_some(op)))
+    }
+}
+
+impl Eval for Undifferentiable {
+    type T = Atom;
+
+    fn try_eval(&self) -> error::Result<Self::T> {
+        match self.0 {
+            Some(op) => Err(error::Error::Value(format!(
+                "the gradient of {op} cannot be differentiated"
+            ))),
+            None => Ok(Zero),
+        }
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        Ok(Shape::Unknown)
+    }
+}
+
+impl<'a> Differentiable<'a> for Undifferentiable {
+    type Δ<D> = Self;
+
+    fn derivative<const LEN: usize, D>(&'a self, _: [&str; LEN], _: D) -> [Self::Δ<D>; LEN] {
+        [*self; LEN]
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_none()
+    }
+}
+
+/// Remembers the value of `N` after it has been evaluated once, until it is forgotten.
+///
+/// The value is behind a lock so that cached nodes can be shared between threads. The lock isn't
+/// held while `N` is evaluated, so a node evaluated concurrently may be evaluated more than once.
+pub struct Cached<N: Eval>(pub N, Mutex<Option<N::T>>);
+
+impl<N: Eval> Cached<N> {
+    pub fn new(node: N) -> Self {
+        Self(node, Mutex::new(None))
+    }
+
+    pub fn is_cached(&self) -> bool {
+        self.value().is_some()
+    }
+
+    pub(crate) fn value(&self) -> MutexGuard<'_, Option<N::T>> {
+        self.1.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<N: Eval> Eval for Cached<N>
+where
+    N::T: Clone,
+{
+    type T = N::T;
+
+    fn try_eval(&self) -> error::Result<Self::T> {
+        if let Some(value) = &*self.value() {
+            return Ok(value.clone());
+        }
+        let v = self.0.try_eval()?;
+        Ok(self.value().get_or_insert(v).clone())
+    }
+
+    fn with_value<R>(&self, f: impl FnOnce(&Self::T) -> R) -> error::Result<R> {
+        if let Some(value) = &*self.value() {
+            return Ok(f(value));
+        }
+        Ok(f(&self.try_eval()?))
+    }
+
+    fn forget(&self) {
+        self.value().take();
+        self.0.forget();
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        self.0.infer_shape()
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        self.0.symbols()
+    }
+}
+
+impl<'a, N: Differentiable<'a>> Differentiable<'a> for Cached<N>
+where
+    Self: Eval,
+{
+    type Δ<D> = N::Δ<D> where Self: 'a;
+
+    fn derivative<const LEN: usize, D: Clone>(
+        &'a self,
+        k: [&str; LEN],
+        d: D,
+    ) -> [Self::Δ<D>; LEN] {
+        self.0.derivative(k, d)
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+}
+
+impl<N: Eval + Debug> Debug for Cached<N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// A [`Cached`] node that drops the values cached inside `N` once its own value is known.
+///
+/// Only the output of the checkpoint is kept in memory. The activations inside it are recomputed,
+/// and cached again, when the backward pass needs them.
+pub struct Checkpoint<N: Eval>(pub Cached<N>);
+
+impl<N: Eval> Checkpoint<N> {
+    pub fn new(node: N) -> Self {
+        Self(Cached::new(node))
+    }
+
+    pub fn is_cached(&self) -> bool {
+        self.0.is_cached()
+    }
+}
+
+impl<N: Eval> Eval for Checkpoint<N>
+where
+    N::T: Clone,
+{
+    type T = N::T;
+
+    fn try_eval(&self) -> error::Result<Self::T> {
+        if let Some(value) = &*self.0.value() {
+            return Ok(value.clone());
+        }
+        let v = self.0 .0.try_eval()?;
+        self.0 .0.forget();
+        *self.0.value() = Some(v.clone());
+        Ok(v)
+    }
+
+    fn with_value<R>(&self, f: impl FnOnce(&Self::T) -> R) -> error::Result<R> {
+        if let Some(value) = &*self.0.value() {
+            return Ok(f(value));
+        }
+        Ok(f(&self.try_eval()?))
+    }
+
+    fn forget(&self) {
+        self.0.forget()
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        self.0.infer_shape()
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        self.0.symbols()
+    }
+}
+
+impl<'a, N: Differentiable<'a>> Differentiable<'a> for Checkpoint<N>
+where
+    Self: Eval,
+{
+    type Δ<D> = N::Δ<D> where Self: 'a;
+
+    fn derivative<const LEN: usize, D: Clone>(
+        &'a self,
+        k: [&str; LEN],
+        d: D,
+    ) -> [Self::Δ<D>; LEN] {
+        self.0 .0.derivative(k, d)
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0 .0.is_zero()
+    }
+}
+
+impl<N: Eval + Debug> Debug for Checkpoint<N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
 macro_rules! impl_debug {
     ($($op: literal $name: ident),*) => {
         $(impl<'a, Lhs: std::fmt::Debug, Rhs: std::fmt::Debug> std::fmt::Debug for $name<Lhs, Rhs> where Self: Differentiable<'a>{
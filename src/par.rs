@@ -0,0 +1,226 @@
+//! Multi-threaded evaluation on the [`rayon`] thread pool.
+//!
+//! [`ParEval::par_eval`] evaluates both operands of a binary op in parallel, and
+//! [`par_eval_all`] evaluates the gradients returned by
+//! [`derivative`](crate::Differentiable::derivative) in parallel. Each op combines its operands
+//! the same way as [`Eval::try_eval`], so the results don't depend on the scheduling of the
+//! threads.
+
+use crate::backend::Backend;
+use crate::custom::{self, Custom};
+use crate::decompose::{Decomposed, FactorGrad};
+use crate::dual::{Dual, HyperDual};
+use crate::indexing::{Concat, Gather, Index, Pad, Part, Reshape, Scatter, Select, Slice, Stack};
+use crate::linalg::{
+    Broadcast, Cholesky, Cofactor, Constant, Det, Inverse, LogDet, Reduce, Solve, Trace,
+};
+use crate::mat::MatrixNode;
+use crate::ops::{self, Cached, Checkpoint, Summable, TransposeAble};
+use crate::primitive_ops::{Conj, ElemMul, Exp};
+use crate::shape::{self, Shape};
+use crate::tensor::{GradView, TensorNode, ViewGrad, Viewed};
+use crate::value::Atom;
+use crate::{error, Eval, Node, Symbol};
+use nalgebra::{allocator::Allocator, Complex, DefaultAllocator, Dim};
+use rayon::prelude::*;
+
+pub trait ParEval: Eval + Sync {
+    /// [`Eval::try_eval`] on the thread pool.
+    fn try_par_eval(&self) -> error::Result<Self::T>;
+
+    /// [`Eval::eval`] on the thread pool.
+    fn par_eval(&self) -> Self::T {
+        self.try_par_eval().unwrap_or_else(|e| panic!("{e}"))
+    }
+}
+
+/// Evaluates every node in parallel, such as the gradients with respect to each symbol.
+pub fn par_eval_all<N: ParEval, const LEN: usize>(nodes: &[N; LEN]) -> [N::T; LEN]
+where
+    N::T: Send,
+{
+    try_par_eval_all(nodes).unwrap_or_else(|e| panic!("{e}"))
+}
+
+/// [`par_eval_all`], returning the first error instead of panicking.
+pub fn try_par_eval_all<N: ParEval, const LEN: usize>(
+    nodes: &[N; LEN],
+) -> error::Result<[N::T; LEN]>
+where
+    N::T: Send,
+{
+    match nodes
+        .par_iter()
+        .map(N::try_par_eval)
+        .collect::<error::Result<Vec<_>>>()?
+        .try_into()
+    {
+        Ok(v) => Ok(v),
+        Err(_) => unreachable!(),
+    }
+}
+
+macro_rules! impl_binary {
+    ($($Op:ident: $Trait:path => $f:expr, $rule:expr;)*) => {$(
+        impl<L, R, LNode: ParEval<T = L>, RNode: ParEval<T = R>> ParEval for ops::$Op<LNode, RNode>
+        where
+            L: $Trait + Eval + Send,
+            R: Eval + Send,
+        {
+            fn try_par_eval(&self) -> error::Result<Self::T> {
+                let (l, r) = rayon::join(|| self.0.try_par_eval(), || self.1.try_par_eval());
+                let (l, r) = (l?, r?);
+                shape::values(stringify!($Op), (&self.0, &l), (&self.1, &r), $rule)?;
+                Ok($f(l, r))
+            }
+        }
+    )*};
+}
+
+impl_binary!(
+    Add: std::ops::Add<R> => std::ops::Add::add, Shape::elementwise;
+    Sub: std::ops::Sub<R> => std::ops::Sub::sub, Shape::elementwise;
+    Div: std::ops::Div<R> => std::ops::Div::div, Shape::elementwise;
+    Mul: std::ops::Mul<R> => std::ops::Mul::mul, Shape::matmul;
+    ElemMul: ElemMul<R> => ElemMul::elem_mul, Shape::elementwise;
+);
+
+macro_rules! impl_unary {
+    ($([$($g:tt)*] $ty:ty => |$n:ident| $f:expr;)*) => {$(
+        impl<$($g)*> ParEval for $ty {
+            fn try_par_eval(&self) -> error::Result<Self::T> {
+                let $n = self;
+                Ok($f)
+            }
+        }
+    )*};
+}
+
+impl_unary!(
+    [N: ParEval] Node<N> => |n| n.0.try_par_eval()?;
+    [N: ParEval] &N => |n| (*n).try_par_eval()?;
+    [N: ParEval] Symbol<N> => |n| n.node.try_par_eval()?;
+    [N: ParEval] ops::Detach<N> => |n| n.0.try_par_eval()?;
+    [N: ParEval<T: std::ops::Neg>] ops::Neg<N> => |n| std::ops::Neg::neg(n.0.try_par_eval()?);
+    [N: ParEval<T: TransposeAble>] ops::Transpose<N> => |n| n.0.try_par_eval()?.transpose_();
+    [N: ParEval<T: Conj>] ops::Conj<N> => |n| Conj::conj(n.0.try_par_eval()?);
+    [N: ParEval<T: TransposeAble<Output: Conj>>] ops::Adjoint<N> =>
+        |n| Conj::conj(n.0.try_par_eval()?.transpose_());
+    [N: ParEval<T: Summable>] ops::Sum<N> => |n| n.0.try_par_eval()?.sum_();
+    [N: ParEval<T: Exp>] ops::Exp<N> => |n| Exp::exp(n.0.try_par_eval()?);
+);
+
+impl<N: ParEval> ParEval for Cached<N>
+where
+    N::T: Clone + Send,
+{
+    fn try_par_eval(&self) -> error::Result<Self::T> {
+        if let Some(value) = &*self.value() {
+            return Ok(value.clone());
+        }
+        let v = self.0.try_par_eval()?;
+        Ok(self.value().get_or_insert(v).clone())
+    }
+}
+
+impl<N: ParEval> ParEval for Checkpoint<N>
+where
+    N::T: Clone + Send,
+{
+    fn try_par_eval(&self) -> error::Result<Self::T> {
+        if let Some(value) = &*self.0.value() {
+            return Ok(value.clone());
+        }
+        let v = self.0 .0.try_par_eval()?;
+        self.0 .0.forget();
+        *self.0.value() = Some(v.clone());
+        Ok(v)
+    }
+}
+
+/// Nodes without independent operands, which are evaluated on the calling thread.
+macro_rules! impl_sequential {
+    ($([$($g:tt)*] $ty:ty;)*) => {$(
+        impl<$($g)*> ParEval for $ty
+        where
+            Self: Eval + Sync,
+        {
+            fn try_par_eval(&self) -> error::Result<Self::T> {
+                self.try_eval()
+            }
+        }
+    )*};
+}
+
+impl_sequential!(
+    [] Atom;
+    [] ops::Undifferentiable;
+    [] u8; [] i8; [] u16; [] i16; [] u32; [] i32; [] u64; [] i64; [] u128; [] i128;
+    [] f32; [] f64; [] Complex<f32>; [] Complex<f64>;
+    [T] Dual<T>;
+    [T] HyperDual<T>;
+    [T] TensorNode<T>;
+    [N] Viewed<N>;
+    [N, D] ViewGrad<'_, N, D>;
+    [N, D] GradView<'_, N, D>;
+    [N] Slice<N>;
+    [N] Gather<N>;
+    [N] Reshape<N>;
+    [N] Index<N>;
+    [N, D] Scatter<'_, N, D>;
+    [N, D] Select<'_, N, D>;
+    [L, R] Concat<L, R>;
+    [L, R] Stack<L, R>;
+    [L, R, D] Part<'_, L, R, D>;
+    [L, R, D] Pad<'_, L, R, D>;
+    [N] Inverse<N>;
+    [N] Det<N>;
+    [N] LogDet<N>;
+    [N] Trace<N>;
+    [N] Cholesky<N>;
+    [N] Cofactor<N>;
+    [N] Constant<N>;
+    [N, D] Broadcast<'_, N, D>;
+    [N, D, G] Reduce<'_, N, D, G>;
+    [A, B] Solve<A, B>;
+    [N] Decomposed<N>;
+    [N, D] FactorGrad<'_, N, D>;
+    [Op, I] Custom<Op, I>;
+    [Op: custom::CustomOp, I, D] custom::Grad<'_, Op, I, D>;
+);
+
+impl<T: nalgebra::Scalar, R: Dim, C: Dim, B: Backend<T>> ParEval for MatrixNode<T, R, C, B>
+where
+    Self: Eval + Sync,
+    DefaultAllocator: Allocator<T, R, C>,
+{
+    fn try_par_eval(&self) -> error::Result<Self::T> {
+        self.try_eval()
+    }
+}
+
+#[test]
+fn deterministic() {
+    use crate::prelude::*;
+    use crate::test::fixture;
+
+    let m = |r, c, s| mat(fixture(r, c, s));
+    let x = m(8, 1, 1.).symbol("x");
+    let w1 = m(16, 8, 2.).symbol("w1");
+    let w2 = m(16, 8, 3.).symbol("w2");
+    let w3 = m(4, 16, 4.).symbol("w3");
+    let h = (Node(Exp(&w1 * &x)) + ops::ElemMul(&w2 * &x, &w2 * &x)).cached();
+    let z = (&h - &w1 * &x).checkpoint();
+    let y = &w3 * &z;
+
+    assert_eq!(y.par_eval(), y.eval());
+    y.forget();
+
+    let dy = mat(DMatrix::from_element(4, 1, 1.));
+    let grads = y.derivative(["x", "w1", "w2", "w3"], &dy);
+    let parallel = par_eval_all(&grads);
+    y.forget();
+    for (p, g) in parallel.into_iter().zip(&grads) {
+        assert_eq!(p, g.eval());
+    }
+}
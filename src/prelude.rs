@@ -1,9 +1,23 @@
 pub use crate::{
-    mat::mat,
-    ops::{Exp, Sum},
+    backend::{Backend, Nalgebra, Reference},
+    compile::{Compile, Compiled},
+    custom::{custom, CustomOp},
+    decompose::{qr, svd, symmetric_eigen},
+    dual::{Dual, HyperDual},
+    error::Error,
+    indexing::{Concat, Gather, Index, Reshape, Slice, Stack},
+    linalg::{Cholesky, Cofactor, Det, Inverse, LogDet, Solve, Trace},
+    mat::{mat, MatrixNode},
+    ops::{Adjoint, Conj, Detach, Exp, Sum},
     primitive_ops::*,
+    print::Print,
+    tensor::{tensor, TensorNode, Views},
     value::*,
-    Differentiable,
+    workspace::Workspace,
+    Differentiable, Eval,
 };
 
-pub use nalgebra::{self, matrix, DMatrix, Matrix};
+#[cfg(feature = "ndarray")]
+pub use crate::backend::Ndarray;
+
+pub use nalgebra::{self, matrix, Complex, DMatrix, Matrix};
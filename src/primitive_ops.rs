@@ -1,6 +1,9 @@
-use crate::value::Atom;
-use crate::{mat::MatrixNode, value::NodeValue};
-use nalgebra::{allocator::Allocator, DefaultAllocator, Dyn};
+use crate::backend::Backend;
+use crate::mat::{BinOp, MatrixNode};
+use crate::value::NodeValue;
+use crate::value::{Atom, Scalar};
+use nalgebra::constraint::{SameNumberOfColumns, SameNumberOfRows, ShapeConstraint};
+use nalgebra::{allocator::Allocator, Complex, DefaultAllocator, Dim};
 
 pub trait Exp {
     type Output;
@@ -12,8 +15,14 @@ pub trait ElemMul<Rhs = Self> {
     fn elem_mul(self, rhs: Rhs) -> Self::Output;
 }
 
+/// Complex conjugate, which is the identity for real values.
+pub trait Conj {
+    type Output;
+    fn conj(self) -> Self::Output;
+}
+
 macro_rules! impl_ops {
-    ($($t: ident $(:$exp:ident)?)*) => {$(
+    ($($t: ty $(:$exp:ident)?),*) => {$(
         impl ElemMul for $t {
             type Output = $t;
             fn elem_mul(self, rhs: Self) -> Self::Output {
@@ -21,52 +30,61 @@ macro_rules! impl_ops {
             }
         }
 
-        impl ElemMul<MatrixNode<$t>> for NodeValue<$t> {
-            type Output = MatrixNode<$t>;
-            fn elem_mul(self, mut rhs: MatrixNode<$t>) -> Self::Output {
-                rhs.0
-                    .iter_mut()
-                    .for_each(|m| m.iter_mut().for_each(|n| *n *= self.0));
-                rhs
+        impl<R: Dim, C: Dim, B: Backend<$t>> ElemMul<MatrixNode<$t, R, C, B>> for NodeValue<$t>
+        where
+            DefaultAllocator: Allocator<$t, R, C>,
+        {
+            type Output = MatrixNode<$t, R, C, B>;
+            fn elem_mul(self, rhs: MatrixNode<$t, R, C, B>) -> Self::Output {
+                rhs.broadcast(BinOp::Mul, self.0, true)
             }
         }
 
-        impl ElemMul<NodeValue<$t>> for MatrixNode<$t> {
-            type Output = MatrixNode<$t>;
+        impl<R: Dim, C: Dim, B: Backend<$t>> ElemMul<NodeValue<$t>> for MatrixNode<$t, R, C, B>
+        where
+            DefaultAllocator: Allocator<$t, R, C>,
+        {
+            type Output = MatrixNode<$t, R, C, B>;
             fn elem_mul(self, rhs: NodeValue<$t>) -> Self::Output {
                 rhs.elem_mul(self)
             }
         }
 
-        impl ElemMul<MatrixNode<$t>> for $t {
-            type Output = MatrixNode<$t>;
-            fn elem_mul(self, mut rhs: MatrixNode<$t>) -> Self::Output {
-                rhs.0
-                    .iter_mut()
-                    .for_each(|m| m.iter_mut().for_each(|n| *n *= self));
-                rhs
+        impl<R: Dim, C: Dim, B: Backend<$t>> ElemMul<MatrixNode<$t, R, C, B>> for $t
+        where
+            DefaultAllocator: Allocator<$t, R, C>,
+        {
+            type Output = MatrixNode<$t, R, C, B>;
+            fn elem_mul(self, rhs: MatrixNode<$t, R, C, B>) -> Self::Output {
+                rhs.broadcast(BinOp::Mul, self, true)
             }
         }
 
-        impl ElemMul<$t> for MatrixNode<$t> {
-            type Output = MatrixNode<$t>;
+        impl<R: Dim, C: Dim, B: Backend<$t>> ElemMul<$t> for MatrixNode<$t, R, C, B>
+        where
+            DefaultAllocator: Allocator<$t, R, C>,
+        {
+            type Output = MatrixNode<$t, R, C, B>;
             fn elem_mul(self, rhs: $t) -> Self::Output {
                 rhs.elem_mul(self)
             }
         }
 
-        impl ElemMul<MatrixNode<$t>> for Atom {
-            type Output = MatrixNode<$t>;
-            fn elem_mul(self, mut rhs: MatrixNode<$t>) -> Self::Output {
-                rhs.0
-                    .iter_mut()
-                    .for_each(|m| m.iter_mut().for_each(|n| *n *= $t::from(self)));
-                rhs
+        impl<R: Dim, C: Dim, B: Backend<$t>> ElemMul<MatrixNode<$t, R, C, B>> for Atom
+        where
+            DefaultAllocator: Allocator<$t, R, C>,
+        {
+            type Output = MatrixNode<$t, R, C, B>;
+            fn elem_mul(self, rhs: MatrixNode<$t, R, C, B>) -> Self::Output {
+                rhs.broadcast(BinOp::Mul, <$t>::from(self), true)
             }
         }
 
-        impl ElemMul<Atom> for MatrixNode<$t> {
-            type Output = MatrixNode<$t>;
+        impl<R: Dim, C: Dim, B: Backend<$t>> ElemMul<Atom> for MatrixNode<$t, R, C, B>
+        where
+            DefaultAllocator: Allocator<$t, R, C>,
+        {
+            type Output = MatrixNode<$t, R, C, B>;
             fn elem_mul(self, rhs: Atom) -> Self::Output {
                 rhs.elem_mul(self)
             }
@@ -75,43 +93,117 @@ macro_rules! impl_ops {
         $(impl Exp for $t {
             type Output = $t;
             fn $exp(self) -> Self::Output {
-                $t::exp(self)
+                <$t as nalgebra::ComplexField>::exp(self)
             }
         })?
     )*};
 }
 
-impl_ops!(f32:exp f64:exp u8 i8 u16 i16 u32 i32 u64 i64 u128 i128);
+impl_ops!(
+    f32:exp, f64:exp, u8, i8, u16, i16, u32, i32, u64, i64, u128, i128,
+    Complex<f32>:exp, Complex<f64>:exp
+);
+
+macro_rules! impl_real_conj {
+    ($($t: ty),*) => {$(
+        impl Conj for $t {
+            type Output = $t;
+            fn conj(self) -> Self::Output {
+                self
+            }
+        }
+
+        impl<R: Dim, C: Dim, B: Backend<$t>> Conj for MatrixNode<$t, R, C, B>
+        where
+            DefaultAllocator: Allocator<$t, R, C>,
+        {
+            type Output = Self;
+            fn conj(self) -> Self::Output {
+                self
+            }
+        }
+    )*};
+}
+
+impl_real_conj!(f32, f64, u8, i8, u16, i16, u32, i32, u64, i64, u128, i128);
+
+macro_rules! impl_complex_conj {
+    ($($t: ty),*) => {$(
+        impl Conj for Complex<$t> {
+            type Output = Self;
+            fn conj(self) -> Self::Output {
+                Complex::conj(&self)
+            }
+        }
+
+        impl<R: Dim, C: Dim, B: Backend<Complex<$t>>> Conj for MatrixNode<Complex<$t>, R, C, B>
+        where
+            DefaultAllocator: Allocator<Complex<$t>, R, C>,
+        {
+            type Output = Self;
+            fn conj(self) -> Self::Output {
+                self.map(|z| Complex::conj(&z))
+            }
+        }
+    )*};
+}
+
+impl_complex_conj!(f32, f64);
 
-impl<T: Exp<Output = T> + Copy> Exp for MatrixNode<T>
+impl<T, R: Dim, C: Dim, B: Backend<T>> Exp for MatrixNode<T, R, C, B>
 where
-    DefaultAllocator: Allocator<T, Dyn, Dyn>,
+    T: Exp<Output = T> + nalgebra::Scalar + Scalar,
+    DefaultAllocator: Allocator<T, R, C>,
 {
     type Output = Self;
 
-    fn exp(mut self) -> Self::Output {
-        self.0
-            .iter_mut()
-            .for_each(|m| m.iter_mut().for_each(|n| *n = n.exp()));
-        self
+    fn exp(self) -> Self::Output {
+        match self {
+            #[cfg(feature = "simd")]
+            MatrixNode::Dense(mut m) if crate::kernels::supports::<T>() => {
+                crate::kernels::exp(B::as_mut_slice(&mut m));
+                MatrixNode::Dense(m)
+            }
+            m => m.map(T::exp),
+        }
     }
 }
 
-impl<L, R> ElemMul<MatrixNode<R>> for MatrixNode<L>
+/// Element-wise product. Different shapes fail to compile if both are static.
+impl<T, R1: Dim, C1: Dim, R2: Dim, C2: Dim, B: Backend<T>> ElemMul<MatrixNode<T, R2, C2, B>>
+    for MatrixNode<T, R1, C1, B>
 where
-    L: ElemMul<R, Output = L> + Copy,
-    R: Copy,
-    DefaultAllocator: Allocator<L, Dyn, Dyn>,
-    DefaultAllocator: Allocator<R, Dyn, Dyn>,
+    T: ElemMul<Output = T> + nalgebra::Scalar + Scalar + Copy,
+    DefaultAllocator: Allocator<T, R1, C1>,
+    DefaultAllocator: Allocator<T, R2, C2>,
+    ShapeConstraint: SameNumberOfRows<R1, R2> + SameNumberOfColumns<C1, C2>,
 {
     type Output = Self;
 
-    fn elem_mul(mut self, rhs: MatrixNode<R>) -> Self::Output {
-        self.0.iter_mut().zip(rhs.0.iter()).for_each(|(m, n)| {
-            m.iter_mut()
-                .zip(n.iter())
-                .for_each(|(a, b)| *a = a.elem_mul(*b))
-        });
-        self
+    fn elem_mul(self, rhs: MatrixNode<T, R2, C2, B>) -> Self::Output {
+        match (self, rhs) {
+            (MatrixNode::Zero(shape), r) => {
+                r.recycle();
+                MatrixNode::Zero(shape)
+            }
+            (l, MatrixNode::Zero(_)) => {
+                let shape = l.shape();
+                l.recycle();
+                MatrixNode::Zero(shape)
+            }
+            (l, MatrixNode::Fill(_, _, v)) => l.map(|n| n.elem_mul(v)),
+            (l, r) => {
+                let (l, r) = (l.into_dense().unwrap(), r.into_dense().unwrap());
+                #[cfg(feature = "simd")]
+                if crate::kernels::supports::<T>() {
+                    let (mut l, mut r) = (l, r);
+                    assert_eq!(B::shape(&l), B::shape(&r), "{l:?} and {r:?}");
+                    crate::kernels::elem_mul(B::as_mut_slice(&mut l), B::as_mut_slice(&mut r));
+                    B::recycle(r);
+                    return MatrixNode::Dense(l);
+                }
+                MatrixNode::Dense(B::zip_map(l, r, T::elem_mul))
+            }
+        }
     }
 }
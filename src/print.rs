@@ -0,0 +1,513 @@
+//! Expressions and their derivatives as readable formulas, either as plain text with minimal
+//! parentheses or as LaTeX.
+//!
+//! Nodes implement [`Display`](std::fmt::Display) through [`Print`]. Terms that are known to be
+//! zero and factors that are known to be one are left out, so a derivative prints as the formula
+//! it simplifies to, rather than as the tree of products with seeds that it is built from.
+
+use crate::backend::Backend;
+use crate::mat::MatrixNode;
+use crate::ops::{
+    Add, Adjoint, Cached, Checkpoint, Conj, Detach, Div, ElemMul, Exp, Mul, Neg, Sub, Sum,
+    Transpose,
+};
+use crate::shape::Shape;
+use crate::value::{Atom, NodeValue, One, Zero};
+use crate::{Eval, Node, Symbol};
+use nalgebra::{allocator::Allocator, DefaultAllocator, Dim};
+use std::any::TypeId;
+use std::fmt::{Display, Formatter};
+
+/// How to render a node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Style {
+    Text,
+    Latex,
+}
+
+/// How tightly a printed node binds, from sums to operands that never need parentheses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Prec {
+    Sum,
+    Product,
+    /// Negations and negative numbers.
+    Prefix,
+    /// Transposes and adjoints.
+    Postfix,
+    Atom,
+}
+
+/// A printed node.
+#[derive(Clone, Debug)]
+pub struct Printed {
+    pub text: String,
+    pub prec: Prec,
+    /// The value of the node, if it is a constant, such as the derivative of a symbol.
+    pub constant: Option<Atom>,
+}
+
+impl Printed {
+    pub fn new(text: impl Into<String>, prec: Prec) -> Self {
+        Self {
+            text: text.into(),
+            prec,
+            constant: None,
+        }
+    }
+
+    pub fn constant(a: Atom, style: Style) -> Self {
+        let (num, den) = (a.numer(), a.denom());
+        let sign = if num < 0 { Prec::Prefix } else { Prec::Atom };
+        let printed = match (den, style) {
+            (1, _) => Self::new(num.to_string(), sign),
+            (_, Style::Text) => Self::new(format!("{num}/{den}"), Prec::Product),
+            (_, Style::Latex) if num < 0 => {
+                Self::new(format!("-\\frac{{{}}}{{{den}}}", -num), Prec::Prefix)
+            }
+            (_, Style::Latex) => Self::new(format!("\\frac{{{num}}}{{{den}}}"), Prec::Atom),
+        };
+        Self {
+            constant: Some(a),
+            ..printed
+        }
+    }
+
+    /// The text, in parentheses unless it binds at least as tightly as `prec`.
+    pub fn at(&self, prec: Prec, style: Style) -> String {
+        if self.prec >= prec {
+            self.text.clone()
+        } else {
+            parens(&self.text, style)
+        }
+    }
+}
+
+fn parens(text: &str, style: Style) -> String {
+    match style {
+        Style::Text => format!("({text})"),
+        Style::Latex => format!("\\left({text}\\right)"),
+    }
+}
+
+/// A function applied to `x`, such as `exp(x)`.
+fn call(text: &str, latex: &str, x: &Printed, style: Style) -> Printed {
+    match style {
+        Style::Text => Printed::new(format!("{text}({})", x.text), Prec::Atom),
+        Style::Latex => Printed::new(format!("{latex}{}", parens(&x.text, style)), Prec::Atom),
+    }
+}
+
+fn neg(x: Printed, style: Style) -> Printed {
+    if let Some(a) = x.constant {
+        return Printed::constant(-a, style);
+    }
+    Printed::new(format!("-{}", after_minus(&x, style)), Prec::Prefix)
+}
+
+/// The operand of a minus sign. `-x * y` is `-(x * y)`, but `--x` and `x - -y` are not valid, so
+/// operands that start with a minus sign are in parentheses, whatever their precedence.
+fn after_minus(x: &Printed, style: Style) -> String {
+    if x.text.starts_with('-') {
+        parens(&x.text, style)
+    } else {
+        x.at(Prec::Product, style)
+    }
+}
+
+/// The right operand of a product, which is in parentheses unless it binds more tightly than a
+/// product, so that `x * (y / z)` keeps its grouping.
+fn right(x: &Printed, style: Style) -> String {
+    if x.prec > Prec::Product && x.prec != Prec::Prefix {
+        x.text.clone()
+    } else {
+        parens(&x.text, style)
+    }
+}
+
+/// A product, where `op` is the operator in `style`. Products by constants are folded.
+fn product(l: Printed, op: &str, r: Printed, style: Style) -> Printed {
+    match (l.constant, r.constant) {
+        (Some(a), Some(b)) => return Printed::constant(a * b, style),
+        (Some(a), _) | (_, Some(a)) if a == Zero => return Printed::constant(Zero, style),
+        (Some(a), _) if a == One => return r,
+        (_, Some(b)) if b == One => return l,
+        (Some(a), _) if a == -One => return neg(r, style),
+        (_, Some(b)) if b == -One => return neg(l, style),
+        _ => {}
+    }
+    let op = match (style, op) {
+        // Juxtaposition, unless the numbers would run together.
+        (Style::Latex, "") if r.constant.is_some() || r.text.starts_with(char::is_numeric) => {
+            " \\cdot "
+        }
+        (Style::Latex, "") => " ",
+        _ => op,
+    };
+    let text = format!("{}{op}{}", l.at(Prec::Product, style), right(&r, style));
+    Printed::new(text, Prec::Product)
+}
+
+/// Whether values of type `V` are real, so that conjugation doesn't change them.
+fn is_real<V: 'static>() -> bool {
+    [
+        TypeId::of::<Atom>(),
+        TypeId::of::<NodeValue<f32>>(),
+        TypeId::of::<NodeValue<f64>>(),
+        TypeId::of::<MatrixNode<f32>>(),
+        TypeId::of::<MatrixNode<f64>>(),
+    ]
+    .contains(&TypeId::of::<V>())
+}
+
+/// Nodes that can be printed as formulas.
+pub trait Print {
+    fn print(&self, style: Style) -> Printed;
+
+    /// The node as a LaTeX formula, e.g. `w_{2} \exp\left(w_{1} x\right)`.
+    fn to_latex(&self) -> String {
+        self.print(Style::Latex).text
+    }
+}
+
+impl Print for Atom {
+    fn print(&self, style: Style) -> Printed {
+        Printed::constant(*self, style)
+    }
+}
+
+macro_rules! impl_number {
+    ($($t: ty),*) => {
+        $(impl Print for $t {
+            fn print(&self, _: Style) -> Printed {
+                let prec = if *self < (0 as $t) { Prec::Prefix } else { Prec::Atom };
+                Printed::new(self.to_string(), prec)
+            }
+        })*
+    };
+}
+
+impl_number!(f32, f64, i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+
+impl<T: nalgebra::Scalar, R: Dim, C: Dim, B: Backend<T>> Print for MatrixNode<T, R, C, B>
+where
+    DefaultAllocator: Allocator<T, R, C>,
+{
+    fn print(&self, style: Style) -> Printed {
+        match (self, self.shape(), style) {
+            (MatrixNode::Zero(_), ..) => Printed::constant(Zero, style),
+            (MatrixNode::Identity(_), _, Style::Text) => Printed::new("I", Prec::Atom),
+            (MatrixNode::Identity(n), _, Style::Latex) => {
+                Printed::new(format!("I_{{{n}}}"), Prec::Atom)
+            }
+            (_, Some((r, c)), Style::Text) => Printed::new(format!("[{r}x{c}]"), Prec::Atom),
+            (_, Some((r, c)), Style::Latex) => {
+                Printed::new(format!("\\mathbf{{M}}_{{{r} \\times {c}}}"), Prec::Atom)
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// A symbol name in LaTeX, with a trailing number or `_suffix` as a subscript and multi-letter
+/// names upright, e.g. `w1` as `w_{1}` and `bias` as `\mathrm{bias}`.
+fn latex_symbol(name: &str) -> String {
+    let (base, sub) = match name.split_once('_') {
+        Some((base, sub)) if !base.is_empty() && !sub.is_empty() => (base, Some(sub)),
+        _ => match name.trim_end_matches(|c: char| c.is_ascii_digit()) {
+            base if !base.is_empty() && base.len() < name.len() => {
+                (base, Some(&name[base.len()..]))
+            }
+            _ => (name, None),
+        },
+    };
+    let escape = |s: &str| s.replace('_', "\\_");
+    let base = if base.chars().count() > 1 {
+        format!("\\mathrm{{{}}}", escape(base))
+    } else {
+        base.to_string()
+    };
+    match sub {
+        Some(sub) => format!("{base}_{{{}}}", escape(sub)),
+        None => base,
+    }
+}
+
+impl<N: Eval> Print for Symbol<N> {
+    fn print(&self, style: Style) -> Printed {
+        let name = self.symbols()[0];
+        match style {
+            Style::Text => Printed::new(name, Prec::Atom),
+            Style::Latex => Printed::new(latex_symbol(name), Prec::Atom),
+        }
+    }
+}
+
+impl<N: Print> Print for Node<N> {
+    fn print(&self, style: Style) -> Printed {
+        self.0.print(style)
+    }
+}
+
+impl<N: Print> Print for &N {
+    fn print(&self, style: Style) -> Printed {
+        (**self).print(style)
+    }
+}
+
+impl<N: Eval + Print> Print for Cached<N> {
+    fn print(&self, style: Style) -> Printed {
+        self.0.print(style)
+    }
+}
+
+impl<N: Eval + Print> Print for Checkpoint<N> {
+    fn print(&self, style: Style) -> Printed {
+        self.0.print(style)
+    }
+}
+
+impl<L: Print, R: Print> Print for Add<L, R> {
+    fn print(&self, style: Style) -> Printed {
+        let (l, r) = (self.0.print(style), self.1.print(style));
+        match (l.constant, r.constant) {
+            (Some(a), Some(b)) => Printed::constant(a + b, style),
+            (Some(a), _) if a == Zero => r,
+            (_, Some(b)) if b == Zero => l,
+            // `x + -y` as `x - y`, which is the same because negations bind loosely.
+            _ => match r.text.strip_prefix('-') {
+                Some(y) if r.prec == Prec::Prefix => {
+                    Printed::new(format!("{} - {y}", l.text), Prec::Sum)
+                }
+                _ => Printed::new(format!("{} + {}", l.text, r.text), Prec::Sum),
+            },
+        }
+    }
+}
+
+impl<L: Print, R: Print> Print for Sub<L, R> {
+    fn print(&self, style: Style) -> Printed {
+        let (l, r) = (self.0.print(style), self.1.print(style));
+        match (l.constant, r.constant) {
+            (Some(a), Some(b)) => Printed::constant(a - b, style),
+            (Some(a), _) if a == Zero => neg(r, style),
+            (_, Some(b)) if b == Zero => l,
+            _ => Printed::new(
+                format!("{} - {}", l.text, after_minus(&r, style)),
+                Prec::Sum,
+            ),
+        }
+    }
+}
+
+impl<L: Print, R: Print> Print for Mul<L, R> {
+    fn print(&self, style: Style) -> Printed {
+        let op = match style {
+            Style::Text => " * ",
+            Style::Latex => "",
+        };
+        product(self.0.print(style), op, self.1.print(style), style)
+    }
+}
+
+impl<L: Print, R: Print> Print for ElemMul<L, R> {
+    fn print(&self, style: Style) -> Printed {
+        let op = match style {
+            Style::Text => " .* ",
+            Style::Latex => " \\odot ",
+        };
+        product(self.0.print(style), op, self.1.print(style), style)
+    }
+}
+
+impl<L: Print, R: Print> Print for Div<L, R> {
+    fn print(&self, style: Style) -> Printed {
+        let (l, r) = (self.0.print(style), self.1.print(style));
+        match (l.constant, r.constant) {
+            (Some(a), Some(b)) if b != Zero => {
+                return Printed::constant(a * Atom::ratio(b.denom(), b.numer()).unwrap(), style)
+            }
+            (Some(a), _) if a == Zero => return Printed::constant(Zero, style),
+            (_, Some(b)) if b == One => return l,
+            _ => {}
+        }
+        match style {
+            Style::Text => Printed::new(
+                format!("{} / {}", l.at(Prec::Product, style), right(&r, style)),
+                Prec::Product,
+            ),
+            Style::Latex => Printed::new(format!("\\frac{{{}}}{{{}}}", l.text, r.text), Prec::Atom),
+        }
+    }
+}
+
+impl<N: Print> Print for Neg<N> {
+    fn print(&self, style: Style) -> Printed {
+        neg(self.0.print(style), style)
+    }
+}
+
+/// `x` with a postfix operator such as a transpose.
+fn postfix(x: Printed, text: &str, latex: &str, style: Style) -> Printed {
+    if x.constant.is_some() {
+        return x;
+    }
+    let op = match style {
+        Style::Text => text,
+        Style::Latex => latex,
+    };
+    Printed::new(format!("{}{op}", x.at(Prec::Atom, style)), Prec::Postfix)
+}
+
+impl<N: Eval + Print> Print for Transpose<N> {
+    fn print(&self, style: Style) -> Printed {
+        let x = self.0.print(style);
+        if self.0.infer_shape() == Ok(Shape::Scalar) {
+            return x;
+        }
+        postfix(x, "^T", "^{\\top}", style)
+    }
+}
+
+impl<N: Eval<T: 'static> + Print> Print for Adjoint<N> {
+    fn print(&self, style: Style) -> Printed {
+        let x = self.0.print(style);
+        match (self.0.infer_shape(), is_real::<N::T>()) {
+            (Ok(Shape::Scalar), true) => x,
+            (Ok(Shape::Scalar), false) => Conj(&self.0).print(style),
+            (_, true) => postfix(x, "^T", "^{\\top}", style),
+            (_, false) => postfix(x, "^H", "^{\\mathsf{H}}", style),
+        }
+    }
+}
+
+impl<N: Eval<T: 'static> + Print> Print for Conj<N> {
+    fn print(&self, style: Style) -> Printed {
+        let x = self.0.print(style);
+        if x.constant.is_some() || is_real::<N::T>() {
+            return x;
+        }
+        match style {
+            Style::Text => call("conj", "", &x, style),
+            Style::Latex => Printed::new(format!("\\overline{{{}}}", x.text), Prec::Atom),
+        }
+    }
+}
+
+impl<N: Print> Print for Exp<N> {
+    fn print(&self, style: Style) -> Printed {
+        let x = self.0.print(style);
+        if x.constant == Some(Zero) {
+            return Printed::constant(One, style);
+        }
+        call("exp", "\\exp", &x, style)
+    }
+}
+
+impl<N: Print> Print for Sum<N> {
+    fn print(&self, style: Style) -> Printed {
+        let x = self.0.print(style);
+        if x.constant == Some(Zero) {
+            return x;
+        }
+        call("sum", "\\sum", &x, style)
+    }
+}
+
+impl<N: Print> Print for Detach<N> {
+    fn print(&self, style: Style) -> Printed {
+        let x = self.0.print(style);
+        if x.constant.is_some() {
+            return x;
+        }
+        call("detach", "\\operatorname{detach}", &x, style)
+    }
+}
+
+macro_rules! impl_display {
+    ($($name: ident<$($p: ident),*>),*) => {
+        $(impl<$($p),*> Display for $name<$($p),*> where Self: Print {
+            fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+                write!(f, "{}", self.print(Style::Text).text)
+            }
+        })*
+    };
+}
+
+impl_display!(
+    Node<N>, Symbol<N>, Add<L, R>, Sub<L, R>, Mul<L, R>, ElemMul<L, R>, Div<L, R>, Neg<N>,
+    Transpose<N>, Adjoint<N>, Conj<N>, Exp<N>, Sum<N>, Detach<N>
+);
+
+impl Display for Atom {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.print(Style::Text).text)
+    }
+}
+
+#[test]
+fn minimal_parentheses() {
+    use crate::{ops, prelude::*};
+
+    let x = 1f64.symbol("x");
+    let y = 2f64.symbol("y");
+    let z = 3f64.symbol("z");
+    assert_eq!((&x + &y * &z).to_string(), "x + y * z");
+    assert_eq!(((&x + &y) * &z).to_string(), "(x + y) * z");
+    assert_eq!((&x - (&y + &z)).to_string(), "x - (y + z)");
+    assert_eq!((&x - &y + &z).to_string(), "x - y + z");
+    assert_eq!((&x * &y * &z).to_string(), "x * y * z");
+    assert_eq!((&x * (&y / &z)).to_string(), "x * (y / z)");
+    assert_eq!(ops::Neg(&x + &y).to_string(), "-(x + y)");
+    assert_eq!(ops::Neg(ops::Neg(&x)).to_string(), "-(-x)");
+    assert_eq!(
+        ops::Neg(ops::Mul(ops::Neg(&x), &y)).to_string(),
+        "-(-x * y)"
+    );
+    assert_eq!(ops::Neg(ops::Mul(&x, &y)).to_string(), "-x * y");
+    assert_eq!(ops::Sub(&x, ops::Neg(&y)).to_string(), "x - (-y)");
+    assert_eq!(
+        ops::Sub(&x, ops::Mul(ops::Neg(&y), &z)).to_string(),
+        "x - (-y * z)"
+    );
+    assert_eq!(ops::Sub(&x, -1.5).to_string(), "x - (-1.5)");
+    assert_eq!(Node(Exp(&x * &y)).to_string(), "exp(x * y)");
+
+    let w = mat(DMatrix::<f64>::repeat(2, 3, 1.)).symbol("w");
+    let v = mat(DMatrix::<f64>::repeat(3, 1, 1.)).symbol("v");
+    assert_eq!(ops::Transpose(&w * &v).to_string(), "(w * v)^T");
+    assert_eq!(ops::Transpose(&v).to_string(), "v^T");
+    assert_eq!(ops::Transpose(&x).to_string(), "x");
+}
+
+#[test]
+fn derivatives() {
+    use crate::prelude::*;
+
+    let x = 2f64.symbol("x");
+    let y = 3f64.symbol("y");
+    let f = &x * &y + &x * Node(Exp(&x / &y)) - 0.5;
+    assert_eq!(f.to_string(), "x * y + x * exp(x / y) - 0.5");
+    let [dx, dy] = f.derivative(["x", "y"], One);
+    assert_eq!(dx.to_string(), "y + exp(x / y) + x .* exp(x / y) / y");
+    let g = &x * &x + &y;
+    let [dx2] = g.derivative(["x"], One);
+    assert_eq!(dx2.to_string(), "x + x");
+    assert_eq!(Node(crate::ops::Sub(Zero, &y)).to_string(), "-y");
+    assert_eq!(dy.to_string(), "x - x / (y .* y) .* (x .* exp(x / y))");
+}
+
+#[test]
+fn latex() {
+    use crate::prelude::*;
+
+    let x = 2f64.symbol("x");
+    let w1 = 3f64.symbol("w1");
+    let b = 1f64.symbol("bias");
+    let f = &w1 * Node(Exp(&x / (&b + &x))) + 2.;
+    assert_eq!(
+        f.to_latex(),
+        "w_{1} \\exp\\left(\\frac{x}{\\mathrm{bias} + x}\\right) + 2"
+    );
+    assert_eq!(Atom::ratio(-1, 2).unwrap().to_latex(), "-\\frac{1}{2}");
+    assert_eq!(Node(crate::ops::Sub(&x, &x)).to_latex(), "x - x");
+}
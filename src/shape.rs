@@ -0,0 +1,193 @@
+//! Shape inference, for catching mismatched operands before an expression is evaluated.
+
+use crate::Eval;
+use std::fmt::{Debug, Display};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shape {
+    Scalar,
+    Matrix(usize, usize),
+    /// A tensor that is neither a scalar nor a matrix, of rank at most [`MAX_RANK`].
+    Tensor(Dims),
+    /// The shape is not known, e.g. for a zero matrix. Compatible with any other shape.
+    Unknown,
+}
+
+/// The highest rank of a [`Shape::Tensor`]. The shapes of tensors of a higher rank are unknown.
+pub const MAX_RANK: usize = 6;
+
+/// The dimensions of a [`Shape::Tensor`], stored inline so that shapes are `Copy`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Dims {
+    rank: usize,
+    dims: [usize; MAX_RANK],
+}
+
+impl Dims {
+    /// `None` if there are more than [`MAX_RANK`] dimensions.
+    pub fn new(dims: &[usize]) -> Option<Self> {
+        let mut inline = [0; MAX_RANK];
+        inline.get_mut(..dims.len())?.copy_from_slice(dims);
+        Some(Dims {
+            rank: dims.len(),
+            dims: inline,
+        })
+    }
+
+    pub fn as_slice(&self) -> &[usize] {
+        &self.dims[..self.rank]
+    }
+}
+
+impl Debug for Dims {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_slice().fmt(f)
+    }
+}
+
+impl Shape {
+    /// Shape of an elementwise op, where scalars are broadcast.
+    pub fn elementwise(self, rhs: Self) -> Option<Self> {
+        match (self, rhs) {
+            (Shape::Unknown, s) | (s, Shape::Unknown) => Some(s),
+            (Shape::Scalar, s) | (s, Shape::Scalar) => Some(s),
+            (l, r) if l == r => Some(l),
+            _ => None,
+        }
+    }
+
+    /// Shape of a matrix product, where scalars are broadcast.
+    pub fn matmul(self, rhs: Self) -> Option<Self> {
+        match (self, rhs) {
+            (Shape::Unknown, _) | (_, Shape::Unknown) => Some(Shape::Unknown),
+            (Shape::Scalar, s) | (s, Shape::Scalar) => Some(s),
+            (Shape::Matrix(r, n), Shape::Matrix(m, c)) if n == m => Some(Shape::Matrix(r, c)),
+            _ => None,
+        }
+    }
+
+    pub fn transpose(self) -> Self {
+        match self {
+            Shape::Matrix(r, c) => Shape::Matrix(c, r),
+            s => s,
+        }
+    }
+}
+
+impl Display for Shape {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Shape::Scalar => write!(f, "scalar"),
+            Shape::Matrix(r, c) => write!(f, "{r}x{c}"),
+            Shape::Tensor(dims) => {
+                let dims = dims.as_slice().iter().map(|n| n.to_string());
+                write!(f, "{}", dims.collect::<Vec<_>>().join("x"))
+            }
+            Shape::Unknown => write!(f, "?"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShapeError {
+    /// The op whose operands have incompatible shapes.
+    pub op: &'static str,
+    pub shapes: Vec<Shape>,
+    /// Symbols in the operands of `op`.
+    pub symbols: Vec<&'static str>,
+}
+
+impl ShapeError {
+    pub fn new(op: &'static str, shapes: Vec<Shape>, symbols: Vec<&'static str>) -> Self {
+        let mut unique = vec![];
+        for s in symbols {
+            if !unique.contains(&s) {
+                unique.push(s);
+            }
+        }
+        Self {
+            op,
+            shapes,
+            symbols: unique,
+        }
+    }
+}
+
+impl Display for ShapeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} cannot be applied to shapes ", self.op)?;
+        for (i, s) in self.shapes.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{s}")?;
+        }
+        write!(f, " (symbols: {})", self.symbols.join(", "))
+    }
+}
+
+impl std::error::Error for ShapeError {}
+
+pub(crate) fn binary<L: Eval, R: Eval>(
+    op: &'static str,
+    l: &L,
+    r: &R,
+    rule: impl Fn(Shape, Shape) -> Option<Shape>,
+) -> Result<Shape, ShapeError> {
+    let (ls, rs) = (l.infer_shape()?, r.infer_shape()?);
+    rule(ls, rs)
+        .ok_or_else(|| ShapeError::new(op, vec![ls, rs], [l.symbols(), r.symbols()].concat()))
+}
+
+/// Checks the values `lv` and `rv` of the operands `l` and `r`, whose shapes can't always be
+/// inferred before they are evaluated, e.g. the output of a custom op.
+pub(crate) fn values<L: Eval, R: Eval>(
+    op: &'static str,
+    (l, lv): (&L, &L::T),
+    (r, rv): (&R, &R::T),
+    rule: impl Fn(Shape, Shape) -> Option<Shape>,
+) -> Result<Shape, ShapeError>
+where
+    L::T: Eval,
+    R::T: Eval,
+{
+    let (ls, rs) = (lv.infer_shape()?, rv.infer_shape()?);
+    rule(ls, rs)
+        .ok_or_else(|| ShapeError::new(op, vec![ls, rs], [l.symbols(), r.symbols()].concat()))
+}
+
+#[test]
+fn infer() {
+    use crate::prelude::*;
+
+    let x = mat(DMatrix::<f32>::new_random(3, 1)).symbol("input");
+
+    let w1 = mat(DMatrix::<f32>::new_random(2, 3)).symbol("w1");
+    let b1 = mat(DMatrix::<f32>::new_random(2, 1)).symbol("b1");
+
+    let w2 = mat(DMatrix::<f32>::new_random(4, 2)).symbol("w2");
+    let b2 = mat(DMatrix::<f32>::new_random(4, 1)).symbol("b2");
+
+    let l1 = &w1 * &x + &b1;
+    let l2 = &w2 * &l1 + &b2;
+    assert_eq!(l2.infer_shape(), Ok(Shape::Matrix(4, 1)));
+    assert_eq!(Sum(Exp(&l2)).infer_shape(), Ok(Shape::Scalar));
+    assert_eq!(
+        (&l2 * 2f32).transpose().infer_shape(),
+        Ok(Shape::Matrix(1, 4))
+    );
+
+    let bad = &w2 * &x + &b2;
+    let err = bad.infer_shape().unwrap_err();
+    assert_eq!(err.op, "Mul");
+    assert_eq!(err.shapes, [Shape::Matrix(4, 2), Shape::Matrix(3, 1)]);
+    assert_eq!(err.symbols, ["w2", "input"]);
+
+    let err = (&l1 + &b2).infer_shape().unwrap_err();
+    assert_eq!(err.op, "Add");
+    assert_eq!(err.symbols, ["w1", "input", "b1", "b2"]);
+    assert_eq!(
+        err.to_string(),
+        "Add cannot be applied to shapes 2x1, 4x1 (symbols: w1, input, b1, b2)"
+    );
+}
@@ -1,4 +1,10 @@
-use crate::{ops::Mul, prelude::*, Node};
+use crate::{
+    error,
+    ops::Mul,
+    prelude::*,
+    shape::{Shape, ShapeError},
+    Node,
+};
 use std::fmt::Debug;
 
 #[derive(Clone)]
@@ -19,13 +25,32 @@ impl<N> Symbol<N> {
     }
 }
 
-impl<'a, N: Differentiable<'a> + 'a> Differentiable<'a> for Symbol<N> {
-    type Δ<D> = Mul<Atom, D>;
+impl<N: Eval> Eval for Symbol<N> {
     type T = N::T;
 
-    fn eval(&self) -> Self::T {
-        self.node.eval()
+    fn try_eval(&self) -> error::Result<Self::T> {
+        self.node.try_eval()
+    }
+
+    fn with_value<R>(&self, f: impl FnOnce(&Self::T) -> R) -> error::Result<R> {
+        self.node.with_value(f)
+    }
+
+    fn forget(&self) {
+        self.node.forget()
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        self.node.infer_shape()
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        vec![self.symbol]
     }
+}
+
+impl<'a, N: Differentiable<'a> + 'a> Differentiable<'a> for Symbol<N> {
+    type Δ<D> = Mul<Atom, D>;
 
     fn derivative<const LEN: usize, D: Clone>(
         &'a self,
@@ -0,0 +1,960 @@
+//! N-dimensional tensors, stored as strided views into shared, row-major storage.
+//!
+//! [`Viewed`] nodes reshape, permute or slice a tensor without copying it, and their derivatives
+//! map the gradient of the view back onto the shape of the original tensor. Views are linear, so
+//! those gradients are differentiated again by taking the same view of the gradient.
+
+use crate::ops::Summable;
+use crate::primitive_ops::{Conj, ElemMul, Exp};
+use crate::shape::{Dims, Shape, ShapeError};
+use crate::value::{self, Atom, NodeValue, Scalar, Zero};
+use crate::{error, Differentiable, Eval, Node};
+use std::fmt::Debug;
+use std::ops::{Add, Mul, Neg, Range, Sub, SubAssign};
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub struct TensorNode<T> {
+    /// `None` for a zero tensor, which doesn't allocate.
+    data: Option<Arc<[T]>>,
+    /// `None` for the derivative with respect to a symbol that doesn't appear in the expression.
+    shape: Option<Vec<usize>>,
+    strides: Vec<usize>,
+    offset: usize,
+}
+
+/// A tensor with the given shape, from its elements in row-major order.
+pub fn tensor<T>(shape: &[usize], data: impl IntoIterator<Item = T>) -> TensorNode<T> {
+    let data: Arc<[T]> = data.into_iter().collect();
+    assert_eq!(
+        data.len(),
+        shape.iter().product::<usize>(),
+        "{} elements don't fit the shape {shape:?}",
+        data.len()
+    );
+    TensorNode {
+        data: Some(data),
+        shape: Some(shape.to_vec()),
+        strides: contiguous_strides(shape),
+        offset: 0,
+    }
+}
+
+fn contiguous_strides(shape: &[usize]) -> Vec<usize> {
+    let mut strides = vec![1; shape.len()];
+    for i in (1..shape.len()).rev() {
+        strides[i - 1] = strides[i] * shape[i];
+    }
+    strides
+}
+
+/// The offsets of the elements of a strided view, in row-major order.
+struct Offsets<'a> {
+    shape: &'a [usize],
+    strides: &'a [usize],
+    index: Vec<usize>,
+    offset: usize,
+    done: bool,
+}
+
+impl<'a> Offsets<'a> {
+    fn new(shape: &'a [usize], strides: &'a [usize], offset: usize) -> Self {
+        Offsets {
+            shape,
+            strides,
+            index: vec![0; shape.len()],
+            offset,
+            done: shape.contains(&0),
+        }
+    }
+}
+
+impl Iterator for Offsets<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.done {
+            return None;
+        }
+        let offset = self.offset;
+        self.done = true;
+        for axis in (0..self.shape.len()).rev() {
+            self.index[axis] += 1;
+            self.offset += self.strides[axis];
+            if self.index[axis] < self.shape[axis] {
+                self.done = false;
+                break;
+            }
+            self.offset -= self.index[axis] * self.strides[axis];
+            self.index[axis] = 0;
+        }
+        Some(offset)
+    }
+}
+
+impl<T> TensorNode<T> {
+    pub fn zeros(shape: &[usize]) -> Self {
+        TensorNode {
+            data: None,
+            shape: Some(shape.to_vec()),
+            strides: contiguous_strides(shape),
+            offset: 0,
+        }
+    }
+
+    fn zero() -> Self {
+        TensorNode {
+            data: None,
+            shape: None,
+            strides: vec![],
+            offset: 0,
+        }
+    }
+
+    pub fn shape(&self) -> Option<&[usize]> {
+        self.shape.as_deref()
+    }
+
+    pub fn strides(&self) -> &[usize] {
+        &self.strides
+    }
+
+    /// Whether the elements are stored in row-major order without gaps.
+    pub fn is_contiguous(&self) -> bool {
+        self.shape()
+            .is_some_and(|shape| self.strides == contiguous_strides(shape))
+    }
+
+    /// The tensor with a new shape with the same number of elements. Only copies if the tensor
+    /// isn't contiguous.
+    pub fn reshape(self, shape: &[usize]) -> Self
+    where
+        T: Copy + PartialEq + Scalar,
+    {
+        let Some(old) = self.shape() else {
+            return self;
+        };
+        assert_eq!(
+            old.iter().product::<usize>(),
+            shape.iter().product::<usize>(),
+            "a tensor of shape {old:?} cannot be reshaped to {shape:?}"
+        );
+        let t = if self.is_contiguous() || self.data.is_none() {
+            self
+        } else {
+            tensor(old, self.iter())
+        };
+        TensorNode {
+            shape: Some(shape.to_vec()),
+            strides: contiguous_strides(shape),
+            ..t
+        }
+    }
+
+    /// The tensor with its axes reordered, so that axis `i` is axis `axes[i]` of `self`.
+    pub fn permute(self, axes: &[usize]) -> Self {
+        let Some(shape) = self.shape() else {
+            return self;
+        };
+        let mut sorted = axes.to_vec();
+        sorted.sort_unstable();
+        assert!(
+            sorted.iter().copied().eq(0..shape.len()),
+            "{axes:?} is not a permutation of the axes of shape {shape:?}"
+        );
+        let shape = axes.iter().map(|&a| shape[a]).collect();
+        let strides = axes.iter().map(|&a| self.strides[a]).collect();
+        TensorNode {
+            shape: Some(shape),
+            strides,
+            ..self
+        }
+    }
+
+    /// The elements in `range` along `axis`.
+    pub fn slice(mut self, axis: usize, range: Range<usize>) -> Self {
+        let Some(shape) = self.shape.as_mut() else {
+            return self;
+        };
+        assert!(
+            axis < shape.len() && range.start <= range.end && range.end <= shape[axis],
+            "{range:?} is out of bounds for axis {axis} of shape {shape:?}"
+        );
+        shape[axis] = range.len();
+        self.offset += range.start * self.strides[axis];
+        self
+    }
+
+    fn offsets(&self) -> Offsets<'_> {
+        let mut offsets = Offsets::new(self.shape().unwrap_or(&[]), &self.strides, self.offset);
+        offsets.done |= self.shape.is_none();
+        offsets
+    }
+}
+
+impl<T: Copy + PartialEq + Scalar> TensorNode<T> {
+    /// A tensor of the given shape with every element equal to `value`, without allocating an
+    /// element per entry.
+    pub fn fill(shape: &[usize], value: T) -> Self {
+        TensorNode {
+            data: Some(Arc::new([value])),
+            shape: Some(shape.to_vec()),
+            strides: vec![0; shape.len()],
+            offset: 0,
+        }
+    }
+
+    pub fn get(&self, index: &[usize]) -> T {
+        let shape = self.shape().expect("zero tensor without a shape");
+        assert!(
+            index.len() == shape.len() && index.iter().zip(shape).all(|(i, n)| i < n),
+            "{index:?} is out of bounds for shape {shape:?}"
+        );
+        let offset: usize = index.iter().zip(&self.strides).map(|(i, s)| i * s).sum();
+        match &self.data {
+            Some(data) => data[self.offset + offset],
+            None => T::from(Zero),
+        }
+    }
+
+    /// The elements in row-major order. A zero tensor without a shape has none.
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        let data = self.data.as_deref();
+        self.offsets()
+            .map(move |i| data.map_or(T::from(Zero), |data| data[i]))
+    }
+
+    pub fn to_vec(&self) -> Vec<T> {
+        self.iter().collect()
+    }
+
+    /// Applies `f` to every element. A zero tensor stays lazy if `f` maps zero to zero.
+    pub fn map(self, f: impl Fn(T) -> T) -> Self {
+        let Some(shape) = self.shape() else {
+            return self;
+        };
+        if self.data.is_some() {
+            return tensor(shape, self.iter().map(f));
+        }
+        let zero = f(T::from(Zero));
+        if zero == T::from(Zero) {
+            TensorNode::zeros(shape)
+        } else {
+            TensorNode::fill(shape, zero)
+        }
+    }
+
+    /// Combines the elements of two tensors of the same shape.
+    fn zip_map(self, rhs: Self, op: &str, f: impl Fn(T, T) -> T) -> Self {
+        assert_eq!(
+            self.shape(),
+            rhs.shape(),
+            "{op} cannot be applied to tensors of different shapes"
+        );
+        let shape = self.shape().unwrap_or(&[]);
+        tensor(shape, self.iter().zip(rhs.iter()).map(|(a, b)| f(a, b)))
+    }
+
+    /// A tensor of `shape` that is zero except for `self` in `range` along `axis`. The inverse of
+    /// [`TensorNode::slice`].
+    fn unslice(self, shape: &[usize], axis: usize, range: Range<usize>) -> Self {
+        if self.data.is_none() {
+            return TensorNode::zeros(shape);
+        }
+        let mut data = vec![T::from(Zero); shape.iter().product()];
+        let strides = contiguous_strides(shape);
+        let mut region = shape.to_vec();
+        region[axis] = range.len();
+        let offsets = Offsets::new(&region, &strides, range.start * strides[axis]);
+        for (i, v) in offsets.zip(self.iter()) {
+            data[i] = v;
+        }
+        tensor(shape, data)
+    }
+}
+
+impl<T: Copy + Debug + Scalar> Debug for TensorNode<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let dims = |shape: &[usize]| {
+            shape
+                .iter()
+                .map(|n| n.to_string())
+                .collect::<Vec<_>>()
+                .join("x")
+        };
+        match (&self.data, self.shape()) {
+            (_, None) => write!(f, "Zero"),
+            (None, Some(shape)) => write!(f, "Zero[{}]", dims(shape)),
+            (Some(_), Some(shape)) => write!(f, " [{}] ", dims(shape)),
+        }
+    }
+}
+
+impl<T: Copy + PartialEq + Scalar> PartialEq for TensorNode<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.shape() == other.shape() && self.iter().eq(other.iter())
+    }
+}
+
+impl<T: Copy + PartialEq + Debug + 'static> Eval for TensorNode<T> {
+    type T = TensorNode<T>;
+
+    fn try_eval(&self) -> error::Result<Self::T> {
+        Ok(self.clone())
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        Ok(self.shape().map_or(Shape::Unknown, shape_of))
+    }
+}
+
+/// The [`Shape`] of a tensor with the dimensions `dims`, which is unknown if its rank is higher
+/// than [`MAX_RANK`](crate::shape::MAX_RANK).
+fn shape_of(dims: &[usize]) -> Shape {
+    match *dims {
+        [] => Shape::Scalar,
+        [r, c] => Shape::Matrix(r, c),
+        _ => Dims::new(dims).map_or(Shape::Unknown, Shape::Tensor),
+    }
+}
+
+impl<'a, T: Copy + PartialEq + Debug + 'static> Differentiable<'a> for TensorNode<T> {
+    type Δ<D> = Atom;
+
+    fn derivative<const LEN: usize, D>(&'a self, _: [&str; LEN], _: D) -> [Self::Δ<D>; LEN] {
+        [Zero; LEN]
+    }
+
+    fn is_zero(&self) -> bool {
+        self.data.is_none()
+    }
+}
+
+impl<T: Copy + PartialEq + Scalar + Add<Output = T>> Add for TensorNode<T> {
+    type Output = TensorNode<T>;
+
+    fn add(self, rhs: TensorNode<T>) -> Self::Output {
+        match (self.data.is_none(), rhs.data.is_none()) {
+            (true, true) if self.shape.is_none() => rhs,
+            (_, true) => self,
+            (true, _) => rhs,
+            _ => self.zip_map(rhs, "Add", Add::add),
+        }
+    }
+}
+
+impl<T: Copy + PartialEq + Scalar + Sub<Output = T> + Neg<Output = T>> Sub for TensorNode<T> {
+    type Output = TensorNode<T>;
+
+    fn sub(self, rhs: TensorNode<T>) -> Self::Output {
+        match (self.data.is_none(), rhs.data.is_none()) {
+            (true, true) if self.shape.is_none() => rhs,
+            (_, true) => self,
+            (true, _) => -rhs,
+            _ => self.zip_map(rhs, "Sub", Sub::sub),
+        }
+    }
+}
+
+impl<T: Copy + PartialEq + Scalar + Sub<Output = T> + Neg<Output = T>> SubAssign for TensorNode<T> {
+    /// Zero tensors, including ones without a shape, leave `self` unchanged, so a zero gradient
+    /// can always be subtracted from a parameter.
+    fn sub_assign(&mut self, rhs: TensorNode<T>) {
+        *self = std::mem::replace(self, TensorNode::zero()) - rhs;
+    }
+}
+
+impl<T: Copy + PartialEq + Scalar + Neg<Output = T>> Neg for TensorNode<T> {
+    type Output = TensorNode<T>;
+
+    fn neg(self) -> Self::Output {
+        self.map(|n| -n)
+    }
+}
+
+impl<T: Copy + PartialEq + Scalar + ElemMul<Output = T>> ElemMul for TensorNode<T> {
+    type Output = TensorNode<T>;
+
+    fn elem_mul(self, rhs: TensorNode<T>) -> Self::Output {
+        match (self.data.is_none(), rhs.data.is_none()) {
+            (true, _) => self,
+            (_, true) => TensorNode::zeros(self.shape().unwrap_or(&[])),
+            _ => self.zip_map(rhs, "ElemMul", ElemMul::elem_mul),
+        }
+    }
+}
+
+impl<T: Copy + PartialEq + Scalar + Conj<Output = T>> Conj for TensorNode<T> {
+    type Output = TensorNode<T>;
+
+    fn conj(self) -> Self::Output {
+        self.map(T::conj)
+    }
+}
+
+impl<T: Copy + PartialEq + Scalar + Exp<Output = T>> Exp for TensorNode<T> {
+    type Output = TensorNode<T>;
+
+    fn exp(self) -> Self::Output {
+        self.map(T::exp)
+    }
+}
+
+impl<T: Copy + PartialEq + Scalar + std::iter::Sum> Summable for TensorNode<T> {
+    type Output = NodeValue<T>;
+
+    fn sum_(self) -> Self::Output {
+        NodeValue(self.iter().sum())
+    }
+}
+
+impl<T: Copy + PartialEq + Scalar + Mul<Output = T>> Mul<Atom> for TensorNode<T> {
+    type Output = TensorNode<T>;
+
+    fn mul(self, rhs: Atom) -> Self::Output {
+        if rhs == value::Zero {
+            // The shape of the zero is unknown, as `self` is the gradient of another symbol.
+            return TensorNode::zero();
+        }
+        if rhs == value::One {
+            return self;
+        }
+        self.map(|n| n * T::from(rhs))
+    }
+}
+
+impl<T: Copy + PartialEq + Scalar + Add<Output = T>> Add<Atom> for TensorNode<T> {
+    type Output = TensorNode<T>;
+
+    fn add(self, rhs: Atom) -> Self::Output {
+        self.map(|n| n + T::from(rhs))
+    }
+}
+
+macro_rules! tensor_scalar_op {
+    ($($Op:ident:$op:ident),*) => {$(
+        impl<T: Copy + PartialEq + Scalar + $Op<Output = T>> $Op<TensorNode<T>> for Atom {
+            type Output = TensorNode<T>;
+
+            fn $op(self, rhs: TensorNode<T>) -> Self::Output {
+                rhs.$op(self)
+            }
+        }
+
+        impl<T: Copy + PartialEq + Scalar + $Op<Output = T>> $Op<NodeValue<T>> for TensorNode<T> {
+            type Output = TensorNode<T>;
+
+            fn $op(self, rhs: NodeValue<T>) -> Self::Output {
+                self.map(|n| n.$op(rhs.0))
+            }
+        }
+
+        impl<T: Copy + PartialEq + Scalar + $Op<Output = T>> $Op<TensorNode<T>> for NodeValue<T> {
+            type Output = TensorNode<T>;
+
+            fn $op(self, rhs: TensorNode<T>) -> Self::Output {
+                rhs.$op(self)
+            }
+        }
+    )*};
+}
+
+tensor_scalar_op!(Add:add, Mul:mul);
+
+impl<T: Copy + PartialEq + Scalar + ElemMul<Output = T>> ElemMul<NodeValue<T>> for TensorNode<T> {
+    type Output = TensorNode<T>;
+
+    fn elem_mul(self, rhs: NodeValue<T>) -> Self::Output {
+        self.map(|n| n.elem_mul(rhs.0))
+    }
+}
+
+impl<T: Copy + PartialEq + Scalar + ElemMul<Output = T>> ElemMul<Atom> for TensorNode<T> {
+    type Output = TensorNode<T>;
+
+    fn elem_mul(self, rhs: Atom) -> Self::Output {
+        self.map(|n| n.elem_mul(T::from(rhs)))
+    }
+}
+
+impl<T: Copy + PartialEq + Scalar + ElemMul<Output = T>> ElemMul<TensorNode<T>> for NodeValue<T> {
+    type Output = TensorNode<T>;
+
+    fn elem_mul(self, rhs: TensorNode<T>) -> Self::Output {
+        rhs.map(|n| self.0.elem_mul(n))
+    }
+}
+
+impl<T: Copy + PartialEq + Scalar + ElemMul<Output = T>> ElemMul<TensorNode<T>> for Atom {
+    type Output = TensorNode<T>;
+
+    fn elem_mul(self, rhs: TensorNode<T>) -> Self::Output {
+        rhs.map(|n| T::from(self).elem_mul(n))
+    }
+}
+
+/// Values that can be broadcast to the gradient of a tensor view.
+pub trait Broadcast<T> {
+    fn broadcast(self, shape: &[usize]) -> TensorNode<T>;
+}
+
+impl<T> Broadcast<T> for TensorNode<T> {
+    fn broadcast(self, _: &[usize]) -> TensorNode<T> {
+        self
+    }
+}
+
+impl<T: Copy + PartialEq + Scalar> Broadcast<T> for NodeValue<T> {
+    fn broadcast(self, shape: &[usize]) -> TensorNode<T> {
+        TensorNode::fill(shape, self.0)
+    }
+}
+
+impl<T: Copy + PartialEq + Scalar> Broadcast<T> for Atom {
+    fn broadcast(self, shape: &[usize]) -> TensorNode<T> {
+        TensorNode::fill(shape, T::from(self))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum View {
+    Reshape(Vec<usize>),
+    Permute(Vec<usize>),
+    Slice(usize, Range<usize>),
+}
+
+impl View {
+    fn name(&self) -> &'static str {
+        match self {
+            View::Reshape(_) => "Reshape",
+            View::Permute(_) => "Permute",
+            View::Slice(..) => "Slice",
+        }
+    }
+
+    /// The shape of the view of a tensor of shape `s`, or `None` if the view doesn't fit it.
+    fn shape(&self, s: Shape) -> Option<Shape> {
+        let dims = match s {
+            Shape::Scalar => vec![],
+            Shape::Matrix(r, c) => vec![r, c],
+            Shape::Tensor(dims) => dims.as_slice().to_vec(),
+            // The number of axes isn't known, so only a reshape has a known shape.
+            Shape::Unknown => {
+                return Some(match self {
+                    View::Reshape(shape) => shape_of(shape),
+                    _ => Shape::Unknown,
+                })
+            }
+        };
+        self.dims(&dims).map(|dims| shape_of(&dims))
+    }
+
+    /// The dimensions of the view of a tensor with the dimensions `dims`, or `None` if the view
+    /// doesn't fit it.
+    fn dims(&self, dims: &[usize]) -> Option<Vec<usize>> {
+        match self {
+            View::Reshape(shape) => {
+                (shape.iter().product::<usize>() == dims.iter().product()).then(|| shape.clone())
+            }
+            View::Permute(axes) => {
+                let mut sorted = axes.clone();
+                sorted.sort_unstable();
+                sorted
+                    .iter()
+                    .copied()
+                    .eq(0..dims.len())
+                    .then(|| axes.iter().map(|&a| dims[a]).collect())
+            }
+            View::Slice(axis, range) => {
+                let fits = *axis < dims.len() && range.start <= range.end;
+                (fits && range.end <= dims[*axis]).then(|| {
+                    let mut dims = dims.to_vec();
+                    dims[*axis] = range.len();
+                    dims
+                })
+            }
+        }
+    }
+
+    /// The dimensions of the view of `t`, the value of `n`. `None` for a zero tensor without a
+    /// shape.
+    fn check<T>(&self, n: &impl Eval, t: &TensorNode<T>) -> Result<Option<Vec<usize>>, ShapeError> {
+        let Some(dims) = t.shape() else {
+            return Ok(None);
+        };
+        match self.dims(dims) {
+            Some(view) => Ok(Some(view)),
+            None => Err(ShapeError::new(
+                self.name(),
+                vec![shape_of(dims)],
+                n.symbols(),
+            )),
+        }
+    }
+
+    fn apply<T: Copy + PartialEq + Scalar>(&self, t: TensorNode<T>) -> TensorNode<T> {
+        match self {
+            View::Reshape(shape) => t.reshape(shape),
+            View::Permute(axes) => t.permute(axes),
+            View::Slice(axis, range) => t.slice(*axis, range.clone()),
+        }
+    }
+}
+
+/// A view of the tensor `N`, which shares its storage.
+#[derive(Clone, Debug)]
+pub struct Viewed<N>(pub N, pub View);
+
+impl<T: Copy + PartialEq + Scalar, N: Eval<T = TensorNode<T>>> Eval for Viewed<N> {
+    type T = TensorNode<T>;
+
+    fn try_eval(&self) -> error::Result<Self::T> {
+        let t = self.0.try_eval()?;
+        self.1.check(&self.0, &t)?;
+        Ok(self.1.apply(t))
+    }
+
+    fn forget(&self) {
+        self.0.forget()
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        let s = self.0.infer_shape()?;
+        self.1
+            .shape(s)
+            .ok_or_else(|| ShapeError::new(self.1.name(), vec![s], self.0.symbols()))
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        self.0.symbols()
+    }
+}
+
+/// Views of tensor-valued nodes, which share the storage of the tensor.
+pub trait Views: Sized {
+    /// The tensor with a new shape. See [`TensorNode::reshape`].
+    fn reshape(self, shape: &[usize]) -> Node<Viewed<Self>> {
+        Node(Viewed(self, View::Reshape(shape.to_vec())))
+    }
+
+    /// The tensor with its axes reordered. See [`TensorNode::permute`].
+    fn permute(self, axes: &[usize]) -> Node<Viewed<Self>> {
+        Node(Viewed(self, View::Permute(axes.to_vec())))
+    }
+
+    /// The elements of the tensor in `range` along `axis`.
+    fn slice(self, axis: usize, range: Range<usize>) -> Node<Viewed<Self>> {
+        Node(Viewed(self, View::Slice(axis, range)))
+    }
+}
+
+impl<T, N: Eval<T = TensorNode<T>>> Views for N {}
+
+impl<'a, N: Differentiable<'a>> Differentiable<'a> for Viewed<N>
+where
+    Self: Eval,
+{
+    type Δ<D> = N::Δ<ViewGrad<'a, N, D>> where Self: 'a;
+
+    fn derivative<const LEN: usize, D: Clone>(
+        &'a self,
+        k: [&str; LEN],
+        d: D,
+    ) -> [Self::Δ<D>; LEN] {
+        self.0.derivative(k, ViewGrad(d, &self.0, self.1.clone()))
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+}
+
+/// The gradient of a view, mapped back onto the shape of the viewed tensor.
+#[derive(Debug)]
+pub struct ViewGrad<'a, N, D>(pub D, pub &'a N, pub View);
+
+impl<N, D: Clone> Clone for ViewGrad<'_, N, D> {
+    fn clone(&self) -> Self {
+        ViewGrad(self.0.clone(), self.1, self.2.clone())
+    }
+}
+
+impl<T, N, D> Eval for ViewGrad<'_, N, D>
+where
+    T: Copy + PartialEq + Scalar,
+    N: Eval<T = TensorNode<T>>,
+    D: Eval,
+    D::T: Broadcast<T>,
+{
+    type T = TensorNode<T>;
+
+    fn try_eval(&self) -> error::Result<Self::T> {
+        // The shape of the viewed tensor isn't known without evaluating it.
+        let source = self.1.try_eval()?;
+        let view_shape = self.2.check(self.1, &source)?;
+        let d = self
+            .0
+            .try_eval()?
+            .broadcast(view_shape.as_deref().unwrap_or(&[]));
+        let (Some(shape), Some(view_shape)) = (source.shape(), view_shape) else {
+            return Ok(d);
+        };
+        if let Some(ds) = d.shape().filter(|&ds| ds != view_shape) {
+            let shapes = vec![shape_of(ds), shape_of(&view_shape)];
+            return Err(ShapeError::new("ViewGrad", shapes, self.symbols()).into());
+        }
+        Ok(match &self.2 {
+            View::Reshape(_) => d.reshape(shape),
+            View::Permute(axes) => {
+                let mut inverse = vec![0; axes.len()];
+                axes.iter().enumerate().for_each(|(i, &a)| inverse[a] = i);
+                d.permute(&inverse)
+            }
+            View::Slice(axis, range) => d.unslice(shape, *axis, range.clone()),
+        })
+    }
+
+    fn forget(&self) {
+        self.0.forget();
+        self.1.forget();
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        let (d, s) = (self.0.infer_shape()?, self.1.infer_shape()?);
+        match self.2.shape(s) {
+            Some(view) if d.elementwise(view).is_some() => Ok(s),
+            _ => Err(ShapeError::new("ViewGrad", vec![d, s], self.symbols())),
+        }
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        [self.0.symbols(), self.1.symbols()].concat()
+    }
+}
+
+impl<'a, 'b, N, D: Differentiable<'b>> Differentiable<'b> for ViewGrad<'a, N, D>
+where
+    Self: Eval,
+{
+    type Δ<G> = D::Δ<GradView<'a, N, G>> where Self: 'b;
+
+    fn derivative<const LEN: usize, G: Clone>(
+        &'b self,
+        k: [&str; LEN],
+        g: G,
+    ) -> [Self::Δ<G>; LEN] {
+        self.0.derivative(k, GradView(g, self.1, self.2.clone()))
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+}
+
+/// The view of a gradient `D` of the viewed tensor `N`, which is the adjoint of [`ViewGrad`].
+#[derive(Debug)]
+pub struct GradView<'a, N, D>(pub D, pub &'a N, pub View);
+
+impl<N, D: Clone> Clone for GradView<'_, N, D> {
+    fn clone(&self) -> Self {
+        GradView(self.0.clone(), self.1, self.2.clone())
+    }
+}
+
+impl<T, N, D> Eval for GradView<'_, N, D>
+where
+    T: Copy + PartialEq + Scalar,
+    N: Eval<T = TensorNode<T>>,
+    D: Eval,
+    D::T: Broadcast<T>,
+{
+    type T = TensorNode<T>;
+
+    fn try_eval(&self) -> error::Result<Self::T> {
+        let source = self.1.try_eval()?;
+        self.2.check(self.1, &source)?;
+        let d = self.0.try_eval()?.broadcast(source.shape().unwrap_or(&[]));
+        let Some(shape) = source.shape() else {
+            return Ok(d);
+        };
+        if let Some(ds) = d.shape().filter(|&ds| ds != shape) {
+            let shapes = vec![shape_of(ds), shape_of(shape)];
+            return Err(ShapeError::new("GradView", shapes, self.symbols()).into());
+        }
+        Ok(self.2.apply(d))
+    }
+
+    fn forget(&self) {
+        self.0.forget();
+        self.1.forget();
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        let (d, s) = (self.0.infer_shape()?, self.1.infer_shape()?);
+        match (d.elementwise(s), self.2.shape(s)) {
+            (Some(_), Some(view)) => Ok(view),
+            _ => Err(ShapeError::new("GradView", vec![d, s], self.symbols())),
+        }
+    }
+
+    fn symbols(&self) -> Vec<&'static str> {
+        [self.0.symbols(), self.1.symbols()].concat()
+    }
+}
+
+impl<'a, 'b, N, D: Differentiable<'b>> Differentiable<'b> for GradView<'a, N, D>
+where
+    Self: Eval,
+{
+    type Δ<G> = D::Δ<ViewGrad<'a, N, G>> where Self: 'b;
+
+    fn derivative<const LEN: usize, G: Clone>(
+        &'b self,
+        k: [&str; LEN],
+        g: G,
+    ) -> [Self::Δ<G>; LEN] {
+        self.0.derivative(k, ViewGrad(g, self.1, self.2.clone()))
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+}
+
+#[cfg(feature = "ndarray")]
+impl<T: Copy> From<ndarray::ArrayD<T>> for TensorNode<T> {
+    fn from(a: ndarray::ArrayD<T>) -> Self {
+        tensor(a.shape(), a.iter().copied())
+    }
+}
+
+/// Panics for a zero tensor without a shape.
+#[cfg(feature = "ndarray")]
+impl<T: Copy + PartialEq + Scalar> From<TensorNode<T>> for ndarray::ArrayD<T> {
+    fn from(t: TensorNode<T>) -> Self {
+        let shape = t.shape().expect("zero tensor without a shape").to_vec();
+        ndarray::ArrayD::from_shape_vec(shape, t.to_vec()).unwrap()
+    }
+}
+
+#[test]
+fn views() {
+    let t = tensor(&[2, 3, 4], (0..24).map(|n| n as f32));
+    assert!(t.is_contiguous());
+    assert_eq!(t.get(&[1, 2, 3]), 23.);
+
+    let p = t.clone().permute(&[2, 0, 1]);
+    assert_eq!(p.shape(), Some(&[4, 2, 3][..]));
+    assert_eq!(p.get(&[3, 1, 2]), 23.);
+    assert!(!p.is_contiguous());
+
+    let s = t.clone().slice(1, 1..3);
+    assert_eq!(s.shape(), Some(&[2, 2, 4][..]));
+    assert_eq!(s.get(&[0, 0, 0]), 4.);
+    assert_eq!(s.to_vec().len(), 16);
+
+    let r = p.reshape(&[4, 6]);
+    assert!(r.is_contiguous());
+    assert_eq!(r.get(&[3, 5]), 23.);
+
+    assert_eq!(TensorNode::fill(&[2, 2], 3f32).iter().sum::<f32>(), 12.);
+    assert_eq!(TensorNode::<f32>::zeros(&[2, 2]).to_vec(), vec![0.; 4]);
+}
+
+#[test]
+fn view_gradients() {
+    use crate::{ops, prelude::*};
+
+    let x = tensor(&[2, 3, 4], (0..24).map(|n| n as f32 / 24.)).symbol("x");
+    let y = tensor(&[3, 2], (0..6).map(|n| n as f32)).symbol("y");
+
+    // Only the elements in the slice receive a gradient.
+    let s = (&x).slice(2, 1..2).reshape(&[3, 2]);
+    let f = Sum(ops::ElemMul(&s, &y));
+    let expected = (0..6)
+        .map(|n| (4 * n + 1) as f32 / 24. * n as f32)
+        .sum::<f32>();
+    assert_eq!(f.eval().0, expected);
+
+    let [dx, dy] = f.derivative(["x", "y"], One);
+    let dx = dx.eval();
+    assert_eq!(dx.shape(), Some(&[2, 3, 4][..]));
+    for i in 0..2 {
+        for j in 0..3 {
+            for k in 0..4 {
+                let g = if k == 1 { (i * 3 + j) as f32 } else { 0. };
+                assert_eq!(dx.get(&[i, j, k]), g);
+            }
+        }
+    }
+    assert_eq!(dy.eval(), s.eval());
+
+    // Gradients of a permuted view are permuted back.
+    let p = (&x).permute(&[2, 0, 1]);
+    let w = TensorNode::fill(&[4, 2, 3], 2f32);
+    let g = Sum(ops::ElemMul(p.exp(), &w));
+    let [dx] = g.derivative(["x"], One);
+    let dx = dx.eval();
+    assert_eq!(dx.get(&[1, 2, 3]), 2. * (23f32 / 24.).exp());
+
+    // Views are linear, so their gradients are differentiated through the same views.
+    let q = (&x).permute(&[2, 0, 1]).slice(0, 1..2);
+    let h = Sum(ops::ElemMul(&q, &q));
+    let [dx] = h.derivative(["x"], One);
+    let dh = Sum(ops::ElemMul(&dx, &x));
+    let [ddx] = dh.derivative(["x"], One);
+    let (dx, ddx) = (dx.eval(), ddx.eval());
+    assert_eq!(ddx.shape(), Some(&[2, 3, 4][..]));
+    for (i, j, k) in [(0, 0, 0), (1, 2, 1), (1, 0, 3)] {
+        let v = x.eval().get(&[i, j, k]);
+        let (g, gg) = if k == 1 { (2. * v, 4. * v) } else { (0., 0.) };
+        assert_eq!((dx.get(&[i, j, k]), ddx.get(&[i, j, k])), (g, gg));
+    }
+
+    // The gradient of a view of a constant is zero.
+    let [dz] = f.derivative(["z"], One);
+    assert!(dz.eval().shape().is_none());
+}
+
+#[test]
+fn view_shapes() {
+    use crate::prelude::*;
+
+    let m = tensor(&[2, 3], (0..6).map(|n| n as f32)).symbol("m");
+    assert_eq!(m.infer_shape(), Ok(Shape::Matrix(2, 3)));
+    assert_eq!((&m).permute(&[1, 0]).infer_shape(), Ok(Shape::Matrix(3, 2)));
+    assert_eq!((&m).slice(1, 1..3).infer_shape(), Ok(Shape::Matrix(2, 2)));
+    let flat = Shape::Tensor(Dims::new(&[6]).unwrap());
+    assert_eq!((&m).reshape(&[6]).infer_shape(), Ok(flat));
+    assert_eq!(
+        (&m).reshape(&[6]).reshape(&[3, 2]).infer_shape(),
+        Ok(Shape::Matrix(3, 2))
+    );
+
+    let e = (&m).reshape(&[4, 2]).infer_shape().unwrap_err();
+    assert_eq!((e.op, e.symbols), ("Reshape", vec!["m"]));
+    assert_eq!((&m).slice(0, 1..3).infer_shape().unwrap_err().op, "Slice");
+    assert_eq!((&m).permute(&[0]).infer_shape().unwrap_err().op, "Permute");
+
+    let t = tensor(&[2, 3, 4], (0..24).map(|n| n as f32));
+    let dims = Dims::new(&[2, 3, 4]).unwrap();
+    assert_eq!(t.infer_shape(), Ok(Shape::Tensor(dims)));
+    assert_eq!(Shape::Tensor(dims).to_string(), "2x3x4");
+
+    // Tensors of the same rank and size but different dimensions can't be added.
+    let u = tensor(&[4, 3, 2], (0..24).map(|n| n as f32)).symbol("u");
+    let sum = crate::ops::Add(&t, &u);
+    let e = sum.infer_shape().unwrap_err();
+    assert_eq!((e.op, &e.symbols[..]), ("Add", &["u"][..]));
+    assert_eq!(sum.try_eval(), Err(error::Error::Shape(e)));
+    assert_eq!(
+        (&t).slice(2, 1..2).reshape(&[2, 3]).infer_shape(),
+        Ok(Shape::Matrix(2, 3))
+    );
+
+    // A zero with a shape keeps it when a shapeless zero is added to it.
+    let z = TensorNode::<f32>::zeros(&[2, 2]);
+    let shapeless = z.clone() * Zero;
+    assert_eq!(shapeless.shape(), None);
+    assert_eq!((z + shapeless).shape(), Some(&[2, 2][..]));
+}
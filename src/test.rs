@@ -1,7 +1,23 @@
 extern crate test;
-use crate::Differentiable;
+use crate::{Differentiable, Eval};
+use nalgebra::DMatrix;
 use test::{black_box, Bencher};
 
+/// A `rows x cols` matrix of distinct values, scaled by `s` to tell matrices of the same shape
+/// apart.
+pub(crate) fn fixture(rows: usize, cols: usize, s: f64) -> DMatrix<f64> {
+    DMatrix::from_fn(rows, cols, |i, j| ((i * cols + j) as f64 * s).sin())
+}
+
+/// Asserts that `a` and `b` are equal up to rounding.
+#[track_caller]
+pub(crate) fn assert_close(a: &[f64], b: &[f64]) {
+    assert_eq!(a.len(), b.len());
+    for (a, b) in a.iter().zip(b) {
+        assert!((a - b).abs() <= 1e-12 * b.abs().max(1.), "{a} != {b}");
+    }
+}
+
 #[bench]
 fn basic(b: &mut Bencher) {
     let x = black_box(2f32.symbol("x"));
@@ -25,3 +41,120 @@ fn hand_written(b: &mut Bencher) {
         black_box(x * x + x * y);
     });
 }
+
+#[bench]
+fn basic_compiled(b: &mut Bencher) {
+    let x = 2f32.symbol("x");
+    let y = 3f32.symbol("y");
+    let f = &x * &y + &x * &x;
+    let mut program = crate::compile::Compiled::new(&f, ["x", "y"], crate::value::One).unwrap();
+    b.iter(|| {
+        program.bind("x", &[black_box(2.)]);
+        program.bind("y", &[black_box(3.)]);
+        program.run();
+        black_box(program.gradients());
+    });
+}
+
+// The generated function and the hand-written one are both called through a pointer, and
+// compute the value and the gradients.
+
+#[cfg(feature = "cranelift")]
+#[bench]
+fn basic_jit(b: &mut Bencher) {
+    let x = 2f32.symbol("x");
+    let y = 3f32.symbol("y");
+    let f = &x * &y + &x * &x;
+    let jit = crate::jit::Jit::new(&f, ["x", "y"], crate::value::One).unwrap();
+    let function = black_box(jit.function());
+    let mut out = [0f32; 3];
+    b.iter(|| {
+        unsafe { function(black_box([2f32, 3.]).as_ptr(), out.as_mut_ptr()) };
+        black_box(out)
+    });
+}
+
+#[bench]
+fn hand_written_gradients(b: &mut Bencher) {
+    unsafe extern "C" fn f(xy: *const f32, out: *mut f32) {
+        let (x, y) = (*xy, *xy.add(1));
+        *out = x * x + x * y;
+        *out.add(1) = x + x + y;
+        *out.add(2) = x;
+    }
+
+    let function: unsafe extern "C" fn(*const f32, *mut f32) = black_box(f);
+    let mut out = [0f32; 3];
+    b.iter(|| {
+        unsafe { function(black_box([2f32, 3.]).as_ptr(), out.as_mut_ptr()) };
+        black_box(out)
+    });
+}
+
+// The `MatrixNode` ops use the vectorized kernels with the `simd` feature, and the `nalgebra_*`
+// benches are the scalar loops they replace.
+
+fn random(rows: usize, cols: usize) -> nalgebra::DMatrix<f32> {
+    nalgebra::DMatrix::new_random(rows, cols)
+}
+
+#[bench]
+fn exp_matrix_node(b: &mut Bencher) {
+    use crate::primitive_ops::Exp;
+    let m = crate::mat::mat(random(256, 256));
+    b.iter(|| black_box(m.clone()).exp());
+}
+
+#[bench]
+fn exp_nalgebra(b: &mut Bencher) {
+    let m = random(256, 256);
+    b.iter(|| black_box(m.clone()).map(f32::exp));
+}
+
+#[bench]
+fn elem_mul_matrix_node(b: &mut Bencher) {
+    use crate::primitive_ops::ElemMul;
+    let (l, r) = (
+        crate::mat::mat(random(256, 256)),
+        crate::mat::mat(random(256, 256)),
+    );
+    b.iter(|| black_box(l.clone()).elem_mul(black_box(r.clone())));
+}
+
+#[bench]
+fn elem_mul_nalgebra(b: &mut Bencher) {
+    let (l, r) = (random(256, 256), random(256, 256));
+    b.iter(|| {
+        let mut l = black_box(l.clone());
+        l.iter_mut().zip(r.iter()).for_each(|(a, b)| *a *= *b);
+        l
+    });
+}
+
+#[bench]
+fn broadcast_matrix_node(b: &mut Bencher) {
+    let m = crate::mat::mat(random(256, 256));
+    b.iter(|| black_box(m.clone()) * crate::value::NodeValue(black_box(2f32)));
+}
+
+#[bench]
+fn broadcast_nalgebra(b: &mut Bencher) {
+    let m = random(256, 256);
+    let y = black_box(2f32);
+    b.iter(|| black_box(m.clone()).map(|x| x * y));
+}
+
+#[bench]
+fn matmul_matrix_node(b: &mut Bencher) {
+    let (l, r) = (
+        crate::mat::mat(random(256, 256)),
+        crate::mat::mat(random(256, 256)),
+    );
+    b.iter(|| black_box(l.clone()) * black_box(r.clone()));
+}
+
+#[bench]
+fn matmul_nalgebra(b: &mut Bencher) {
+    let (l, r) = (random(256, 256), random(256, 256));
+    b.iter(|| black_box(&l) * black_box(&r));
+}
@@ -1,61 +1,211 @@
 use crate::primitive_ops::*;
-use crate::Differentiable;
-pub use nalgebra::{Matrix, Storage};
+use crate::shape::{Shape, ShapeError};
+use crate::{error, Differentiable, Eval};
+use nalgebra::Complex;
+pub use nalgebra::Storage;
 use std::ops::*;
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
-pub enum Atom {
-    Zero,
-    One,
+/// An exact rational constant, such as the derivative of a symbol with respect to itself.
+///
+/// Constants that don't fit in an `i64` fraction are rounded, and constants outside of
+/// `±i64::MAX` saturate.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Atom {
+    num: i64,
+    // Always positive, and coprime with `num`.
+    den: i64,
+}
+
+#[allow(non_upper_case_globals)]
+pub const Zero: Atom = Atom::int(0);
+#[allow(non_upper_case_globals)]
+pub const One: Atom = Atom::int(1);
+
+impl Atom {
+    pub const fn int(n: i64) -> Self {
+        Self { num: n, den: 1 }
+    }
+
+    /// `num / den`, or `None` if `den` is zero.
+    pub fn ratio(num: i64, den: i64) -> Option<Self> {
+        (den != 0).then(|| Self::reduce(num as i128, den as i128))
+    }
+
+    pub fn numer(self) -> i64 {
+        self.num
+    }
+
+    pub fn denom(self) -> i64 {
+        self.den
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.num as f64 / self.den as f64
+    }
+
+    /// The integer part of the constant.
+    pub fn trunc(self) -> i64 {
+        self.num / self.den
+    }
+
+    fn reduce(mut num: i128, mut den: i128) -> Self {
+        if den < 0 {
+            (num, den) = (-num, -den);
+        }
+        let fits = |n: i128| n.unsigned_abs() <= i64::MAX as u128;
+        let g = gcd(num.unsigned_abs(), den as u128) as i128;
+        (num, den) = (num / g.max(1), den / g.max(1));
+        while !fits(num) || !fits(den) {
+            if den == 1 {
+                return Self::int(i64::MAX * num.signum() as i64);
+            }
+            // Drops the low bits of both, which keeps the value up to rounding.
+            (num, den) = (num / 2, (den / 2).max(1));
+        }
+        let g = gcd(num.unsigned_abs(), den as u128) as i128;
+        Self {
+            num: (num / g.max(1)) as i64,
+            den: (den / g.max(1)) as i64,
+        }
+    }
+}
+
+fn gcd(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+impl std::fmt::Debug for Atom {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.den == 1 {
+            write!(f, "{}", self.num)
+        } else {
+            write!(f, "{}/{}", self.num, self.den)
+        }
+    }
+}
+
+impl PartialOrd for Atom {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Atom {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.num as i128 * other.den as i128).cmp(&(other.num as i128 * self.den as i128))
+    }
 }
-pub use Atom::*;
 
 #[test]
 fn ord() {
-    assert!(One > Zero)
+    assert!(One > Zero);
+    assert!(Atom::ratio(1, 3) < Atom::ratio(1, 2));
+    assert!(-One < Zero);
 }
 
-impl<'a> Differentiable<'a> for Atom {
-    type Δ<T> = Atom;
+#[test]
+fn arithmetic() {
+    assert_eq!(One + One, Atom::int(2));
+    assert_eq!(One - One, Zero);
+    assert_eq!(Atom::ratio(2, -4), Some(-Atom::ratio(1, 2).unwrap()));
+    assert_eq!(Atom::ratio(1, 0), None);
+
+    let third = Atom::ratio(1, 3).unwrap();
+    assert_eq!(third + third + third, One);
+    assert_eq!(third * Atom::int(6), Atom::int(2));
+
+    assert_eq!(f32::from(third + One), 4. / 3.);
+    assert_eq!(i32::from(Atom::int(-7) * third), -2);
+
+    let max = Atom::int(i64::MAX);
+    assert_eq!(max + One, max);
+    assert_eq!(-max - Atom::int(2), -max);
+    assert_eq!(max * max, max);
+    assert_eq!(Atom::ratio(i64::MAX, 3).unwrap() * Atom::int(6), max);
+    assert_eq!(
+        Atom::ratio(1, i64::MAX).unwrap() * Atom::ratio(1, 2).unwrap(),
+        Zero
+    );
+
+    assert_eq!(u8::from(Atom::int(255)), 255);
+    assert!(std::panic::catch_unwind(|| u8::from(-One)).is_err());
+    assert!(std::panic::catch_unwind(|| i8::from(Atom::int(300))).is_err());
+
+    let x = 3f32.symbol("x");
+    let [dx] = (&x + &x).derivative(["x"], One);
+    assert_eq!(dx.eval(), Atom::int(2));
+}
+
+impl Eval for Atom {
     type T = Atom;
 
-    fn eval(&self) -> Self::T {
-        *self
+    fn try_eval(&self) -> error::Result<Self::T> {
+        Ok(*self)
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        // Zero multiplies to a zero matrix without a shape.
+        Ok(if *self == Zero {
+            Shape::Unknown
+        } else {
+            Shape::Scalar
+        })
     }
+}
+
+impl<'a> Differentiable<'a> for Atom {
+    type Δ<T> = Atom;
 
     fn derivative<const LEN: usize, D>(&'a self, _: [&str; LEN], _d: D) -> [Self::Δ<D>; LEN] {
         [Zero; LEN]
     }
 
     fn is_zero(&self) -> bool {
-        match self {
-            Zero => true,
-            One => false,
-        }
+        *self == Zero
     }
 }
 
 pub trait Scalar: From<Atom> {}
 
 macro_rules! impl_scalar {
-    ($($t: ident)*) => {$(
+    (int: $($t: ident)*) => {$(
+        /// Panics if the integer part of the constant doesn't fit in the type.
         impl From<Atom> for $t {
             fn from(n: Atom) -> Self {
-                match n{
-                    Zero => 0 as $t,
-                    One  => 1 as $t
-                }
+                $t::try_from(n.trunc())
+                    .unwrap_or_else(|_| panic!("{n:?} doesn't fit in {}", stringify!($t)))
+            }
+        }
+        impl_scalar!(@constant $t);
+    )*};
+    (float: $($t: ident)*) => {$(
+        impl From<Atom> for $t {
+            fn from(n: Atom) -> Self {
+                n.to_f64() as $t
             }
         }
+        impl_scalar!(@constant $t);
+    )*};
+    (@constant $t: ty) => {
         impl Scalar for $t{}
 
-        impl<'a> Differentiable<'a> for $t{
-                type Δ<T> = Atom;
+        impl Eval for $t{
                 type T = NodeValue<$t>;
 
-                fn eval(&self) -> Self::T {
-                    NodeValue(*self)
+                fn try_eval(&self) -> error::Result<Self::T> {
+                    Ok(NodeValue(*self))
+                }
+
+                fn infer_shape(&self) -> Result<Shape, ShapeError> {
+                    Ok(Shape::Scalar)
                 }
+        }
+
+        impl<'a> Differentiable<'a> for $t{
+                type Δ<T> = Atom;
 
                 fn derivative<const LEN: usize, D>(&'a self, _: [&str; LEN], _d:D) -> [Self::Δ< D>; LEN] {
                     [Zero; LEN]
@@ -65,11 +215,26 @@ macro_rules! impl_scalar {
                     false
                 }
         }
+    };
+}
+
+impl_scalar!(int: u8 i8 u16 i16 u32 i32 u64 i64 u128 i128);
+impl_scalar!(float: f32 f64);
+
+macro_rules! impl_complex_scalar {
+    ($($t: ident)*) => {$(
+        impl From<Atom> for Complex<$t> {
+            fn from(n: Atom) -> Self {
+                Complex::new(n.to_f64() as $t, 0.)
+            }
+        }
+        impl_scalar!(@constant Complex<$t>);
     )*};
 }
 
-impl_scalar!(u8 i8 u16 i16 u32 i32 u64 i64 u128 i128 f32 f64);
+impl_complex_scalar!(f32 f64);
 
+#[derive(Clone, Copy)]
 pub struct NodeValue<T>(pub T);
 
 impl<T> Deref for NodeValue<T> {
@@ -80,6 +245,19 @@ impl<T> Deref for NodeValue<T> {
     }
 }
 
+/// Scalar values are nodes too, so that the shapes of evaluated operands can be checked.
+impl<T: Clone> Eval for NodeValue<T> {
+    type T = NodeValue<T>;
+
+    fn try_eval(&self) -> error::Result<Self::T> {
+        Ok(self.clone())
+    }
+
+    fn infer_shape(&self) -> Result<Shape, ShapeError> {
+        Ok(Shape::Scalar)
+    }
+}
+
 macro_rules! impl_node_val_ops {
     ($($op:ident:$Op:ident)*) => {$(
         impl<L: $Op<R>, R> $Op<NodeValue<R>> for NodeValue<L> {
@@ -118,6 +296,22 @@ impl<T: Neg> Neg for NodeValue<T> {
     }
 }
 
+impl<T: Conj> Conj for NodeValue<T> {
+    type Output = NodeValue<<T as Conj>::Output>;
+
+    fn conj(self) -> Self::Output {
+        NodeValue(self.0.conj())
+    }
+}
+
+impl Conj for Atom {
+    type Output = Atom;
+
+    fn conj(self) -> Self::Output {
+        self
+    }
+}
+
 impl<T: Exp> Exp for NodeValue<T> {
     type Output = NodeValue<<T as Exp>::Output>;
 
@@ -130,10 +324,10 @@ impl Mul<Atom> for Atom {
     type Output = Atom;
 
     fn mul(self, rhs: Atom) -> Self::Output {
-        match (self, rhs) {
-            (One, One) => One,
-            _ => Zero,
-        }
+        Atom::reduce(
+            self.num as i128 * rhs.num as i128,
+            self.den as i128 * rhs.den as i128,
+        )
     }
 }
 
@@ -141,11 +335,26 @@ impl Add<Atom> for Atom {
     type Output = Atom;
 
     fn add(self, rhs: Atom) -> Self::Output {
-        match (self, rhs) {
-            (Zero, _) => rhs,
-            (_, Zero) => self,
-            (One, One) => panic!("Atom overflow"),
-        }
+        Atom::reduce(
+            self.num as i128 * rhs.den as i128 + rhs.num as i128 * self.den as i128,
+            self.den as i128 * rhs.den as i128,
+        )
+    }
+}
+
+impl Sub<Atom> for Atom {
+    type Output = Atom;
+
+    fn sub(self, rhs: Atom) -> Self::Output {
+        self + -rhs
+    }
+}
+
+impl Neg for Atom {
+    type Output = Atom;
+
+    fn neg(self) -> Self::Output {
+        Atom::reduce(-(self.num as i128), self.den as i128)
     }
 }
 
@@ -0,0 +1,155 @@
+//! Reuse of the buffers of dense matrices across nodes and evaluations.
+//!
+//! Matrix ops return the buffers of the dynamically sized matrices they consume to the active
+//! [`Workspace`], and new matrices, including clones of constants and symbols, take their buffer
+//! from it. Evaluating the same expressions again, e.g. in the next step of a training loop, then
+//! finds every buffer it needs in the workspace.
+
+use std::any::{Any, TypeId};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+thread_local! {
+    static ACTIVE: RefCell<Option<Pool>> = const { RefCell::new(None) };
+}
+
+#[derive(Default)]
+struct Pool {
+    /// The free buffers of each element type `T`, as a `Vec<Vec<T>>`.
+    free: HashMap<TypeId, Box<dyn Any>>,
+    allocations: usize,
+}
+
+impl Pool {
+    fn free<T: 'static>(&mut self) -> &mut Vec<Vec<T>> {
+        self.free
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(Vec::<Vec<T>>::new()))
+            .downcast_mut()
+            .unwrap()
+    }
+}
+
+/// A pool of matrix buffers, which is used by the matrix ops evaluated in [`Workspace::run`].
+///
+/// Values that leave `run`, such as gradients, can be handed back with
+/// [`MatrixNode::recycle`](crate::prelude::MatrixNode::recycle), or by subtracting them from a
+/// parameter inside `run`. Only the current thread uses the workspace, so nodes evaluated by
+/// other threads allocate as usual.
+#[derive(Default)]
+pub struct Workspace(Pool);
+
+impl Workspace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `f` with this workspace active on the current thread.
+    pub fn run<R>(&mut self, f: impl FnOnce() -> R) -> R {
+        /// Deactivates the workspace, even if `f` panics.
+        struct Restore<'a>(&'a mut Workspace, Option<Pool>);
+
+        impl Drop for Restore<'_> {
+            fn drop(&mut self) {
+                let pool = ACTIVE.with(|active| active.replace(self.1.take()));
+                self.0 .0 = pool.unwrap_or_default();
+            }
+        }
+
+        let pool = std::mem::take(&mut self.0);
+        let previous = ACTIVE.with(|active| active.replace(Some(pool)));
+        let _restore = Restore(self, previous);
+        f()
+    }
+
+    /// The number of buffers that were allocated because none of the free ones were big enough.
+    pub fn allocations(&self) -> usize {
+        self.0.allocations
+    }
+}
+
+impl std::fmt::Debug for Workspace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Workspace")
+            .field("allocations", &self.allocations())
+            .finish_non_exhaustive()
+    }
+}
+
+/// An empty buffer with room for `len` elements, from the active workspace if it has one.
+///
+/// The smallest free buffer that is big enough is used, so repeating the same sequence of
+/// requests is served by the same buffers.
+pub(crate) fn buffer<T: 'static>(len: usize) -> Vec<T> {
+    if len == 0 {
+        return Vec::new();
+    }
+    ACTIVE
+        .with(|active| {
+            let mut active = active.borrow_mut();
+            let pool = active.as_mut()?;
+            let free = pool.free::<T>();
+            let fit = (0..free.len())
+                .filter(|&i| free[i].capacity() >= len)
+                .min_by_key(|&i| free[i].capacity());
+            match fit {
+                Some(i) => Some(free.swap_remove(i)),
+                None => {
+                    pool.allocations += 1;
+                    None
+                }
+            }
+        })
+        .unwrap_or_else(|| Vec::with_capacity(len))
+}
+
+/// Returns `buffer` to the active workspace, or drops it if there is none.
+pub(crate) fn recycle<T: 'static>(mut buffer: Vec<T>) {
+    if buffer.capacity() == 0 {
+        return;
+    }
+    buffer.clear();
+    ACTIVE.with(|active| {
+        if let Some(pool) = active.borrow_mut().as_mut() {
+            pool.free().push(buffer);
+        }
+    });
+}
+
+#[test]
+fn steady_state() {
+    use crate::test::fixture;
+    use crate::{prelude::*, Node};
+
+    let m = |r, c, s| mat(fixture(r, c, s));
+    let train = |ws: Option<&mut Workspace>| {
+        let x = m(3, 1, 1.).symbol("x");
+        let b = m(8, 1, 2.).symbol("b");
+        let mut w1 = m(8, 3, 3.).symbol("w1");
+        let mut w2 = m(2, 8, 4.).symbol("w2");
+        let mut step = || {
+            let y = &w2 * Node(Exp(&w1 * &x + &b));
+            let [dw1, dw2] = y.derivative(["w1", "w2"], MatrixNode::<f64>::fill(2, 1, 1.));
+            let (dw1, dw2) = (dw1.eval(), dw2.eval());
+            w1.0.node -= dw1 * NodeValue(0.1);
+            w2.0.node -= dw2 * NodeValue(0.1);
+        };
+        let mut allocations = vec![];
+        match ws {
+            Some(ws) => (0..4).for_each(|_| {
+                ws.run(&mut step);
+                allocations.push(ws.allocations());
+            }),
+            None => (0..4).for_each(|_| step()),
+        }
+        (w1.0.node, w2.0.node, allocations)
+    };
+
+    let mut ws = Workspace::new();
+    let (w1, w2, allocations) = train(Some(&mut ws));
+    assert!(allocations[0] > 0);
+    assert!(allocations.iter().all(|&a| a == allocations[0]));
+
+    let (w1_ref, w2_ref, _) = train(None);
+    assert_eq!((w1, w2), (w1_ref, w2_ref));
+}